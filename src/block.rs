@@ -1,75 +1,86 @@
+use anyhow::{bail, Result};
 use bytes::BufMut;
 use bytes::{Buf, Bytes};
 
-///
+/// Block builder
 pub mod builder;
 
 /// Create
 pub mod iterator;
 
+/// Entry flag of a live key-value pair.
+pub(crate) const ENTRY_VALUE: u8 = 0;
+
+/// Entry flag of a deleted key.
+pub(crate) const ENTRY_TOMBSTONE: u8 = 1;
+
 /// block
+///
+/// Each entry is encoded as
+/// `shared_len | rest_len | rest of key | ts(u64) | flag(u8) | value_len | value`, where the
+/// lengths are varints, the user key shares its first `shared_len` bytes with the previous user
+/// key and the flag tells a live value from a tombstone. Every restart point stores its user key
+/// in full. The entries are followed by the offsets of the restart points (u32 each) and their
+/// number (u32).
 #[derive(Debug)]
 pub struct Block {
     /// data
     data: Vec<u8>,
 
-    /// offset
-    offsets: Vec<u16>,
+    /// offsets of the restart points
+    restarts: Vec<u32>,
 }
 
 impl Block {
-    ///
+    /// Encode the block to bytes.
     pub fn encode(&self) -> Bytes {
         let mut buf = self.data.clone();
-        let offsets_len = self.offsets.len();
-        for offset in &self.offsets {
-            buf.put_u16(*offset);
+        let restarts_len = self.restarts.len();
+        for offset in &self.restarts {
+            buf.put_u32(*offset);
         }
-        buf.put_u16(offsets_len as u16);
+        buf.put_u32(restarts_len as u32);
         buf.into()
     }
 
-    ///
-    pub fn decode(data: &[u8]) -> Self {
-        let mut idx = data.len() - 2;
-        let num_of_elemnts = (&data[idx..]).get_u16() as usize;
-
-        let mut offsets = Vec::new();
-        offsets.reserve(num_of_elemnts);
-
-        idx -= num_of_elemnts << 1;
-        for _ in 0..num_of_elemnts {
-            offsets.push((&data[idx..idx + 2]).get_u16());
-            idx += 2;
+    /// Decode the block from bytes. Every entry is checked, so that iterating over the block
+    /// cannot go out of bounds.
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        if data.len() < 4 {
+            bail!("block is shorter than its number of restart points");
         }
-
-        if num_of_elemnts == 0 {
-            return Block {
-                data: Vec::new(),
-                offsets,
-            };
+        let mut idx = data.len() - 4;
+        let num_of_restarts = (&data[idx..]).get_u32() as usize;
+        if num_of_restarts > idx / 4 {
+            bail!("block is too short for {} restart points", num_of_restarts);
         }
 
-        let mut data_len = offsets[offsets.len() - 1] as usize;
-        // data_len += [ket_len] + [key]
-        data_len += (&data[data_len..data_len + 2]).get_u16() as usize + 2;
-        // data_len += [ket_len] + [key]
-        data_len += (&data[data_len..data_len + 2]).get_u16() as usize + 2;
+        let mut restarts = Vec::with_capacity(num_of_restarts);
 
-        Block {
-            data: data[0..data_len].to_vec(),
-            offsets,
+        idx -= num_of_restarts << 2;
+        for _ in 0..num_of_restarts {
+            restarts.push((&data[idx..idx + 4]).get_u32());
+            idx += 4;
         }
+
+        let data_len = data.len() - 4 - (num_of_restarts << 2);
+        let data = &data[0..data_len];
+        check_entries(data, &restarts)?;
+
+        Ok(Block {
+            data: data.to_vec(),
+            restarts,
+        })
     }
 
-    ///
+    /// Print every entry of the block for debugging.
     #[cfg(test)]
     pub fn dbeug_print(block: std::sync::Arc<Block>) {
         use self::iterator::BlockIterator;
 
         let mut iter = BlockIterator::create_and_seek_to_first(block);
         while iter.is_valid() {
-            let key = Bytes::copy_from_slice(iter.key());
+            let key = iter.key().to_key_bytes();
             let value = Bytes::copy_from_slice(iter.value());
             log::debug!("key:{:?}, value:{:?}", key, value);
             iter.next();
@@ -77,5 +88,78 @@ impl Block {
     }
 }
 
+/// Check that the entries fill `data` exactly and that the restart points are the offsets of
+/// entries storing their key in full.
+fn check_entries(data: &[u8], restarts: &[u32]) -> Result<()> {
+    if restarts.is_empty() != data.is_empty() || restarts.first().is_some_and(|x| *x != 0) {
+        bail!("block does not start with a restart point");
+    }
+    let mut restarts = restarts.iter().map(|x| *x as usize).peekable();
+    let mut buf = data;
+    let mut key_len = 0;
+    while buf.has_remaining() {
+        let offset = data.len() - buf.len();
+        let is_restart = restarts.next_if_eq(&offset).is_some();
+        if restarts.peek().is_some_and(|x| *x <= offset) {
+            bail!("restart point is not at an entry");
+        }
+
+        let read_len = |buf: &mut &[u8]| match get_varint(buf) {
+            Some(len) if len <= buf.len() as u64 => Ok(len as usize),
+            _ => bail!("entry at offset {} is truncated", offset),
+        };
+        let shared = read_len(&mut buf)?;
+        if shared > key_len || (is_restart && shared != 0) {
+            bail!(
+                "entry at offset {} shares too much of the previous key",
+                offset
+            );
+        }
+        let rest = read_len(&mut buf)?;
+        buf.advance(rest);
+        key_len = shared + rest;
+        if buf.len() < 9 {
+            bail!("entry at offset {} is truncated", offset);
+        }
+        buf.advance(9);
+        let value_len = read_len(&mut buf)?;
+        buf.advance(value_len);
+    }
+    if restarts.next().is_some() {
+        bail!("restart point is not at an entry");
+    }
+    Ok(())
+}
+
+/// Append `v` to `buf` as a LEB128 varint.
+pub(crate) fn put_varint(buf: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        buf.push(v as u8 | 0x80);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+/// Read a LEB128 varint from the front of `buf`. Returns None if it is truncated or too long.
+pub(crate) fn get_varint(buf: &mut impl Buf) -> Option<u64> {
+    let mut v = 0;
+    for shift in (0..64).step_by(7) {
+        if !buf.has_remaining() {
+            return None;
+        }
+        let b = buf.get_u8();
+        v |= ((b & 0x7f) as u64) << shift;
+        if b < 0x80 {
+            return Some(v);
+        }
+    }
+    None
+}
+
+/// Number of bytes of `v` encoded as a varint.
+pub(crate) fn varint_len(v: u64) -> usize {
+    (64 - (v | 1).leading_zeros() as usize).div_ceil(7)
+}
+
 #[cfg(test)]
 mod tests;
@@ -1,59 +1,127 @@
-use super::Block;
+use bytes::BufMut;
 
-///
+use super::{put_varint, varint_len, Block, ENTRY_TOMBSTONE, ENTRY_VALUE};
+use crate::key::KeySlice;
+
+/// Default number of entries between two restart points.
+pub const DEFAULT_RESTART_INTERVAL: usize = 16;
+
+/// Builds a block.
 #[derive(Debug)]
 pub struct BlockBuilder {
     data: Vec<u8>,
-    offsets: Vec<u16>,
+    restarts: Vec<u32>,
     block_size: usize,
     curr_size: usize,
+    restart_interval: usize,
+    /// number of entries since the last restart point
+    counter: usize,
+    last_key: Vec<u8>,
 }
 
 impl BlockBuilder {
-    ///
+    /// Creates a new block builder.
     pub fn new(block_size: usize) -> Self {
         Self {
             data: vec![],
-            offsets: vec![],
+            restarts: vec![],
             block_size,
-            curr_size: 2,
+            curr_size: 4,
+            restart_interval: DEFAULT_RESTART_INTERVAL,
+            counter: 0,
+            last_key: vec![],
         }
     }
 
-    ///
-    pub fn add(&mut self, key: &[u8], value: &[u8]) -> bool {
-        let key_len = key.len();
+    /// Set the number of entries between two restart points. Every key is stored in full if it
+    /// is 1.
+    pub fn restart_interval(mut self, restart_interval: usize) -> Self {
+        self.restart_interval = restart_interval.max(1);
+        self
+    }
+
+    /// Adds a key-value pair to the block. Returns false when the block is full. The first entry
+    /// is always added, so an entry larger than the block size occupies a block of its own.
+    pub fn add(&mut self, key: KeySlice, value: &[u8]) -> bool {
+        self.add_entry(key, value, false)
+    }
+
+    /// Adds a tombstone of `key` to the block. Returns false when the block is full.
+    pub fn add_tombstone(&mut self, key: KeySlice) -> bool {
+        self.add_entry(key, &[], true)
+    }
+
+    fn add_entry(&mut self, key: KeySlice, value: &[u8], deleted: bool) -> bool {
+        let restart = self.restarts.is_empty() || self.counter >= self.restart_interval;
+        let shared = if restart {
+            0
+        } else {
+            key.key_ref()
+                .iter()
+                .zip(&self.last_key)
+                .take_while(|(a, b)| a == b)
+                .count()
+        };
+        let rest = &key.key_ref()[shared..];
         let value_len = value.len();
-        let add_len = 6 + key_len + value_len;
 
-        if self.curr_size + add_len > self.block_size {
+        // shared_len + rest_len + ts + flag + value_len, plus the restart offset
+        let mut add_len = varint_len(shared as u64)
+            + varint_len(rest.len() as u64)
+            + 8
+            + 1
+            + varint_len(value_len as u64)
+            + rest.len()
+            + value_len;
+        if restart {
+            add_len += 4;
+        }
+
+        if !self.is_empty() && self.curr_size + add_len > self.block_size {
             return false;
         }
 
-        let curr_offset = self.data.len() as u16;
-        self.offsets.push(curr_offset);
+        if restart {
+            self.restarts.push(self.data.len() as u32);
+            self.counter = 0;
+        }
 
-        self.data.extend_from_slice(&(key_len as u16).to_be_bytes());
-        self.data.extend_from_slice(key);
+        put_varint(&mut self.data, shared as u64);
+        put_varint(&mut self.data, rest.len() as u64);
+        self.data.extend_from_slice(rest);
+        self.data.put_u64(key.ts());
 
-        self.data
-            .extend_from_slice(&(value_len as u16).to_be_bytes());
+        self.data.push(if deleted {
+            ENTRY_TOMBSTONE
+        } else {
+            ENTRY_VALUE
+        });
+
+        put_varint(&mut self.data, value_len as u64);
         self.data.extend_from_slice(value);
 
-        self.curr_size += 6 + key_len + value_len;
+        self.last_key.truncate(shared);
+        self.last_key.extend_from_slice(rest);
+        self.counter += 1;
+        self.curr_size += add_len;
         true
     }
 
+    /// Returns true if no entry is added.
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
     /// Get the estimated size of the block.
     pub fn estimated_size(&self) -> usize {
         self.curr_size
     }
 
-    ///
+    /// Finalize the block.
     pub fn build(self) -> Block {
         Block {
             data: self.data,
-            offsets: self.offsets,
+            restarts: self.restarts,
         }
     }
 }
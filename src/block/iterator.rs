@@ -1,7 +1,9 @@
 use bytes::Buf;
 use std::sync::Arc;
 
-use super::Block;
+use super::{get_varint, Block, ENTRY_TOMBSTONE};
+use crate::iterators::{ensure_valid, StorageIterator};
+use crate::key::{KeySlice, KeyVec};
 
 /// Block Iterator
 #[derive(Debug)]
@@ -10,42 +12,57 @@ pub struct BlockIterator {
     block: Arc<Block>,
 
     /// key
-    key: Vec<u8>,
+    key: KeyVec,
 
     /// value
     value: Vec<u8>,
 
-    /// idx
-    idx: usize,
+    /// whether the current entry is a tombstone
+    deleted: bool,
+
+    /// offset of the current entry
+    offset: usize,
+
+    /// offset of the next entry
+    next_offset: usize,
 }
 
 impl BlockIterator {
     fn new(block: Arc<Block>) -> Self {
         Self {
             block,
-            key: Vec::new(),
+            key: KeyVec::new(),
             value: Vec::new(),
-            idx: 0,
+            deleted: false,
+            offset: 0,
+            next_offset: 0,
         }
     }
 
     /// Creates a block iterator and seek to the first entry.
     pub fn create_and_seek_to_first(block: Arc<Block>) -> Self {
         let mut it = Self::new(block);
-        it.set_entry_idx(0);
+        it.seek_to_first();
+        it
+    }
+
+    /// Creates a block iterator and seek to the last entry.
+    pub fn create_and_seek_to_last(block: Arc<Block>) -> Self {
+        let mut it = Self::new(block);
+        it.seek_to_last();
         it
     }
 
     /// Creates a block iterator and seek to the first key that >= `key`.
-    pub fn create_and_seek_to_key(block: Arc<Block>, key: &[u8]) -> Self {
+    pub fn create_and_seek_to_key(block: Arc<Block>, key: KeySlice) -> Self {
         let mut it = Self::create_and_seek_to_first(block);
         it.seek_to_key(key);
         it
     }
 
     /// Returns the key of the current entry.
-    pub fn key(&self) -> &[u8] {
-        &self.key
+    pub fn key(&self) -> KeySlice<'_> {
+        self.key.as_key_slice()
     }
 
     /// Returns the value of the current entry.
@@ -53,61 +70,185 @@ impl BlockIterator {
         &self.value
     }
 
+    /// Returns true if the current entry is a tombstone.
+    pub fn is_deleted(&self) -> bool {
+        self.deleted
+    }
+
     /// Returns true if the iterator is valid.
     pub fn is_valid(&self) -> bool {
-        self.idx < self.block.offsets.len()
+        self.offset < self.block.data.len()
     }
 
     /// Seeks to the first key in the block.
     pub fn seek_to_first(&mut self) {
-        if !self.block.offsets.is_empty() {
-            self.set_entry_idx(0);
-        }
+        self.seek_to_offset(0);
     }
 
-    /// Seek to the first key that >= `key`.
-    pub fn seek_to_key(&mut self, key: &[u8]) {
+    /// Seek to the first key that >= `key`. The iterator becomes invalid if every key in the
+    /// block is smaller than `key`.
+    pub fn seek_to_key(&mut self, key: KeySlice) {
+        // find the first restart point whose key >= `key`
         let mut l = 0;
-        let mut r = self.block.offsets.len() - 1;
+        let mut r = self.block.restarts.len();
         while l < r {
             let m = (l + r) >> 1;
 
-            let mut office = self.block.offsets[m] as usize;
-            let key_len = (&self.block.data[office..office + 2]).get_u16() as usize;
-            office += 2;
+            // the key of a restart point is stored in full
+            let mut buf = &self.block.data[self.block.restarts[m] as usize..];
+            read_varint(&mut buf);
+            let key_len = read_varint(&mut buf);
+            let ts = (&buf[key_len..]).get_u64();
 
             // arr[m] < key
-            if &self.block.data[office..office + key_len] < key {
+            if KeySlice::from_slice(&buf[..key_len], ts) < key {
                 l = m + 1;
             } else {
                 r = m;
             }
         }
 
-        self.set_entry_idx(l);
+        // the key may be in the interval before that restart point
+        let start = if l == 0 {
+            0
+        } else {
+            self.block.restarts[l - 1] as usize
+        };
+        self.seek_to_offset(start);
+        while self.is_valid() && self.key.as_key_slice() < key {
+            self.next();
+        }
+    }
+
+    /// Seeks to the last key in the block.
+    pub fn seek_to_last(&mut self) {
+        self.move_to_entry_before(self.block.data.len());
+    }
+
+    /// Seek to the last key that <= `key`. The iterator becomes invalid if every key in the
+    /// block is larger than `key`.
+    pub fn seek_for_prev(&mut self, key: KeySlice) {
+        self.seek_to_key(key);
+        if !self.is_valid() || self.key.as_key_slice() > key {
+            self.move_to_entry_before(self.offset);
+        }
     }
 
     /// Move to the next key in the block.
     pub fn next(&mut self) {
-        self.idx += 1;
-        if self.idx < self.block.offsets.len() {
-            self.set_entry_idx(self.idx);
+        self.offset = self.next_offset;
+        if self.is_valid() {
+            self.decode_entry();
+        }
+    }
+
+    /// Move to the previous key in the block. The iterator becomes invalid if it is at the first
+    /// key.
+    pub fn prev(&mut self) {
+        self.move_to_entry_before(self.offset);
+    }
+
+    /// Move to the entry ending at `offset`. As the keys are delta-encoded, the entries are
+    /// decoded from the restart point before it.
+    fn move_to_entry_before(&mut self, offset: usize) {
+        let idx = self
+            .block
+            .restarts
+            .partition_point(|restart| (*restart as usize) < offset);
+        if idx == 0 {
+            // before the first entry
+            self.offset = self.block.data.len();
+            return;
+        }
+        self.seek_to_offset(self.block.restarts[idx - 1] as usize);
+        while self.next_offset < offset {
+            self.next();
+        }
+    }
+
+    /// Seek to the entry at `offset`, which must be a restart point.
+    fn seek_to_offset(&mut self, offset: usize) {
+        self.key = KeyVec::new();
+        self.offset = offset;
+        self.next_offset = offset;
+        if self.is_valid() {
+            self.decode_entry();
         }
     }
 
-    fn set_entry_idx(&mut self, idx: usize) {
-        let mut offset = self.block.offsets[idx] as usize;
+    /// Decode the entry at `self.offset`, whose key is relative to the current key.
+    fn decode_entry(&mut self) {
+        let data = &self.block.data;
+        let mut buf = &data[self.offset..];
 
-        let len = (&self.block.data[offset..offset + 2]).get_u16() as usize;
-        offset += 2;
-        self.key = self.block.data[offset..offset + len].to_vec();
+        let shared = read_varint(&mut buf);
+        let len = read_varint(&mut buf);
+        self.key.truncate(shared);
+        self.key.append(&buf[..len]);
+        buf.advance(len);
+        self.key.set_ts(buf.get_u64());
 
-        offset += len;
+        self.deleted = buf.get_u8() == ENTRY_TOMBSTONE;
 
-        let len = (&self.block.data[offset..offset + 2]).get_u16() as usize;
-        offset += 2;
-        self.value = self.block.data[offset..offset + len].to_vec();
+        let len = read_varint(&mut buf);
+        self.value = buf[..len].to_vec();
+        buf.advance(len);
 
-        self.idx = idx;
+        self.next_offset = data.len() - buf.len();
     }
 }
+
+impl StorageIterator for BlockIterator {
+    fn value(&self) -> &[u8] {
+        BlockIterator::value(self)
+    }
+
+    fn key(&self) -> KeySlice<'_> {
+        BlockIterator::key(self)
+    }
+
+    fn is_valid(&self) -> bool {
+        BlockIterator::is_valid(self)
+    }
+
+    fn is_deleted(&self) -> bool {
+        BlockIterator::is_deleted(self)
+    }
+
+    fn next(&mut self) -> anyhow::Result<()> {
+        ensure_valid(self)?;
+        BlockIterator::next(self);
+        Ok(())
+    }
+
+    fn seek(&mut self, key: KeySlice) -> anyhow::Result<()> {
+        self.seek_to_key(key);
+        Ok(())
+    }
+
+    fn seek_to_first(&mut self) -> anyhow::Result<()> {
+        BlockIterator::seek_to_first(self);
+        Ok(())
+    }
+
+    fn prev(&mut self) -> anyhow::Result<()> {
+        ensure_valid(self)?;
+        BlockIterator::prev(self);
+        Ok(())
+    }
+
+    fn seek_to_last(&mut self) -> anyhow::Result<()> {
+        BlockIterator::seek_to_last(self);
+        Ok(())
+    }
+
+    fn seek_for_prev(&mut self, key: KeySlice) -> anyhow::Result<()> {
+        BlockIterator::seek_for_prev(self, key);
+        Ok(())
+    }
+}
+
+/// Read a varint from a block, whose entries are checked by `Block::decode`.
+fn read_varint(buf: &mut &[u8]) -> usize {
+    get_varint(buf).expect("block entries are checked when decoded") as usize
+}
@@ -2,23 +2,30 @@ use bytes::Bytes;
 use std::sync::Arc;
 
 use super::iterator::BlockIterator;
-use super::{builder::BlockBuilder, Block};
+use super::{builder::BlockBuilder, get_varint, put_varint, varint_len, Block};
+use crate::key::{KeySlice, TS_DEFAULT};
+
+fn ks(key: &[u8]) -> KeySlice<'_> {
+    KeySlice::from_slice(key, TS_DEFAULT)
+}
 
 #[test]
 fn test_block_build_single_key() {
-    // key_len + val_len + num_of_elemnts = 6
+    // shared_len + rest_len + ts + flag + val_len + restart offset = 16
     // key + val = 7
-    // offset = 2
+    // num_of_restarts = 4
     {
-        let mut builder = BlockBuilder::new(6 + 7 + 2);
-        assert!(builder.add(b"123", b"4567"));
-        assert!(!builder.add(b"", b""));
+        let mut builder = BlockBuilder::new(16 + 7 + 4);
+        assert!(builder.add(ks(b"123"), b"4567"));
+        assert!(!builder.add(ks(b""), b""));
         _ = builder.build();
     }
 
+    // the first entry is added even if it is larger than the block
     {
-        let mut builder = BlockBuilder::new(6 + 7 + 1);
-        assert!(!builder.add(b"123", b"4567"));
+        let mut builder = BlockBuilder::new(16 + 7 + 3);
+        assert!(builder.add(ks(b"123"), b"4567"));
+        assert!(!builder.add(ks(b"124"), b""));
         _ = builder.build();
     }
 }
@@ -40,7 +47,7 @@ fn generate_block_size(idx: usize) -> Block {
     for idx in 0..idx {
         let key = key_of(idx);
         let value = value_of(idx);
-        assert!(builder.add(&key[..], &value[..]));
+        assert!(builder.add(ks(&key), &value));
     }
     builder.build()
 }
@@ -60,8 +67,8 @@ fn test_block_encode() {
 fn test_block_decode_empty() {
     let block = generate_block_size(0);
     let encoded = block.encode();
-    let decoded_block = Block::decode(&encoded);
-    assert_eq!(block.offsets, decoded_block.offsets);
+    let decoded_block = Block::decode(&encoded).unwrap();
+    assert_eq!(block.restarts, decoded_block.restarts);
     assert_eq!(block.data, decoded_block.data);
 }
 
@@ -69,8 +76,8 @@ fn test_block_decode_empty() {
 fn test_block_decode_one() {
     let block = generate_block_size(1);
     let encoded = block.encode();
-    let decoded_block = Block::decode(&encoded);
-    assert_eq!(block.offsets, decoded_block.offsets);
+    let decoded_block = Block::decode(&encoded).unwrap();
+    assert_eq!(block.restarts, decoded_block.restarts);
     assert_eq!(block.data, decoded_block.data);
 }
 
@@ -78,24 +85,24 @@ fn test_block_decode_one() {
 fn test_block_decode() {
     let block = generate_block_size(100);
     let encoded = block.encode();
-    let decoded_block = Block::decode(&encoded);
-    assert_eq!(block.offsets, decoded_block.offsets);
+    let decoded_block = Block::decode(&encoded).unwrap();
+    assert_eq!(block.restarts, decoded_block.restarts);
     assert_eq!(block.data, decoded_block.data);
 }
 
 #[test]
 fn test_block_multiple_keys() {
-    let mut builder = BlockBuilder::new(300);
+    let mut builder = BlockBuilder::new(398);
     for idx in 0..11 {
         let key = key_of(idx);
         let value = value_of(idx);
-        assert!(builder.add(&key[..], &value[..]));
+        assert!(builder.add(ks(&key), &value));
     }
     let block = builder.build();
     let block = Arc::new(block);
     let mut iter = BlockIterator::create_and_seek_to_first(block);
     for i in 0..11 {
-        let key = iter.key();
+        let key = iter.key().key_ref();
         let value = iter.value();
         assert_eq!(
             key,
@@ -121,7 +128,7 @@ fn test_block_iterator() {
     let mut iter = BlockIterator::create_and_seek_to_first(block);
     for _ in 0..5 {
         for i in 0..100 {
-            let key = iter.key();
+            let key = iter.key().key_ref();
             let value = iter.value();
             assert_eq!(
                 key,
@@ -152,10 +159,10 @@ fn test_block_seek_key() {
     for _ in 0..5 {
         for start in 0..100 {
             let key = key_of(start);
-            iter.seek_to_key(&key);
+            iter.seek_to_key(ks(&key));
 
             for i in start..100 {
-                let key = iter.key();
+                let key = iter.key().key_ref();
                 let value = iter.value();
                 assert_eq!(
                     key,
@@ -176,3 +183,209 @@ fn test_block_seek_key() {
         }
     }
 }
+
+#[test]
+fn test_block_reverse() {
+    let check = |iter: &BlockIterator, i: usize| {
+        assert!(iter.is_valid(), "{i}");
+        assert_eq!(iter.key().key_ref(), key_of(i));
+        assert_eq!(iter.value(), value_of(i));
+    };
+    let block = Arc::new(generate_block_size(100));
+
+    let mut iter = BlockIterator::create_and_seek_to_last(Arc::clone(&block));
+    for i in (0..100).rev() {
+        check(&iter, i);
+        iter.prev();
+    }
+    assert!(!iter.is_valid());
+
+    for start in 0..100 {
+        iter.seek_for_prev(ks(&key_of(start)));
+        check(&iter, start);
+        // a missing key moves to the previous one
+        iter.seek_for_prev(ks(&[&key_of(start)[..], b"0"].concat()));
+        check(&iter, start);
+        iter.prev();
+        if start > 0 {
+            check(&iter, start - 1);
+            iter.next();
+            check(&iter, start);
+        }
+    }
+    iter.seek_for_prev(ks(b"key"));
+    assert!(!iter.is_valid());
+
+    let empty = Arc::new(Block::decode(&[0; 4]).unwrap());
+    assert!(!BlockIterator::create_and_seek_to_last(empty).is_valid());
+}
+
+#[test]
+fn test_block_tombstone() {
+    let mut builder = BlockBuilder::new(10000);
+    for idx in 0..100 {
+        if idx % 3 == 0 {
+            assert!(builder.add_tombstone(ks(&key_of(idx))));
+        } else {
+            assert!(builder.add(ks(&key_of(idx)), &value_of(idx)));
+        }
+    }
+    let block = Block::decode(&builder.build().encode()).unwrap();
+    let mut iter = BlockIterator::create_and_seek_to_first(Arc::new(block));
+    for i in 0..100 {
+        assert!(iter.is_valid(), "{i}");
+        assert_eq!(iter.key().key_ref(), key_of(i));
+        if i % 3 == 0 {
+            assert!(iter.is_deleted(), "{i}");
+            assert!(iter.value().is_empty());
+        } else {
+            assert!(!iter.is_deleted(), "{i}");
+            assert_eq!(iter.value(), value_of(i));
+        }
+        iter.next();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_block_prefix_compression() {
+    let build = |restart_interval| {
+        let mut builder = BlockBuilder::new(10000).restart_interval(restart_interval);
+        for idx in 0..100 {
+            assert!(builder.add(ks(&key_of(idx)), &value_of(idx)));
+        }
+        builder.build().encode()
+    };
+    let full = build(1);
+    let compressed = build(16);
+    // every key after a restart point shares "key_0" with the previous key
+    assert!(
+        compressed.len() < full.len() - 90 * 5,
+        "{}",
+        compressed.len()
+    );
+
+    for restart_interval in [1, 2, 3, 16, 200] {
+        let encoded = build(restart_interval);
+        let block = Arc::new(Block::decode(&encoded).unwrap());
+        assert_eq!(block.encode(), encoded);
+        assert_eq!(block.restarts.len(), 100usize.div_ceil(restart_interval));
+
+        let mut iter = BlockIterator::create_and_seek_to_first(block);
+        for start in 0..100 {
+            // a key between `start - 1` and `start`
+            let key = match start {
+                0 => b"a".to_vec(),
+                _ => [key_of(start - 1), b"\0".to_vec()].concat(),
+            };
+            iter.seek_to_key(ks(&key));
+            for i in start..100 {
+                assert!(iter.is_valid(), "{restart_interval} {i}");
+                assert_eq!(iter.key().key_ref(), key_of(i));
+                assert_eq!(iter.value(), value_of(i));
+                iter.next();
+            }
+            assert!(!iter.is_valid());
+        }
+        iter.seek_to_key(ks(b"key_999"));
+        assert!(!iter.is_valid());
+    }
+}
+
+#[test]
+fn test_block_versions() {
+    // versions of a key are ordered from the newest to the oldest
+    let mut builder = BlockBuilder::new(10000).restart_interval(4);
+    for idx in 0..20 {
+        for ts in (1..=idx % 4 + 1).rev() {
+            let value = value_of(idx * 10 + ts);
+            assert!(builder.add(KeySlice::from_slice(&key_of(idx), ts as u64), &value));
+        }
+    }
+    let block = Arc::new(Block::decode(&builder.build().encode()).unwrap());
+
+    let mut iter = BlockIterator::create_and_seek_to_first(Arc::clone(&block));
+    for idx in 0..20 {
+        for ts in (1..=idx % 4 + 1).rev() {
+            assert_eq!(iter.key(), KeySlice::from_slice(&key_of(idx), ts as u64));
+            assert_eq!(iter.value(), value_of(idx * 10 + ts));
+            iter.next();
+        }
+    }
+    assert!(!iter.is_valid());
+
+    for idx in 0..20 {
+        for read_ts in 1..6 {
+            // the newest version at or below `read_ts`
+            iter.seek_to_key(KeySlice::from_slice(&key_of(idx), read_ts as u64));
+            let ts = read_ts.min(idx % 4 + 1);
+            assert_eq!(iter.key(), KeySlice::from_slice(&key_of(idx), ts as u64));
+            assert_eq!(iter.value(), value_of(idx * 10 + ts));
+        }
+        iter.seek_to_key(KeySlice::from_slice(&key_of(idx), 0));
+        if idx < 19 {
+            assert_eq!(iter.key().key_ref(), key_of(idx + 1));
+        } else {
+            assert!(!iter.is_valid());
+        }
+    }
+}
+
+#[test]
+fn test_block_varint() {
+    let mut buf = Vec::new();
+    let values = [
+        0,
+        1,
+        127,
+        128,
+        300,
+        65535,
+        65536,
+        3 << 20,
+        u32::MAX as u64,
+        u64::MAX,
+    ];
+    for v in values {
+        let len = buf.len();
+        put_varint(&mut buf, v);
+        assert_eq!(buf.len() - len, varint_len(v), "{v}");
+    }
+    let mut buf = &buf[..];
+    for v in values {
+        assert_eq!(get_varint(&mut buf), Some(v));
+    }
+    assert_eq!(get_varint(&mut buf), None);
+    assert_eq!(get_varint(&mut &[0x80u8, 0x80][..]), None);
+}
+
+#[test]
+fn test_block_decode_invalid() {
+    assert!(Block::decode(&[]).is_err());
+    assert!(Block::decode(&[0, 0, 0, 1]).is_err());
+    assert!(Block::decode(&[0xff; 8]).is_err());
+
+    // a malformed block is rejected or iterated over without panicking
+    let mut builder = BlockBuilder::new(10000);
+    for i in 0..20 {
+        assert!(builder.add(ks(&key_of(i)), &value_of(i)));
+    }
+    let encoded = builder.build().encode();
+    for len in 0..encoded.len() {
+        let _ = Block::decode(&encoded[..len]);
+    }
+    for pos in 0..encoded.len() {
+        for byte in [0x00, 0x01, 0x7f, 0x80, 0xff] {
+            let mut data = encoded.to_vec();
+            data[pos] = byte;
+            if let Ok(block) = Block::decode(&data) {
+                let mut iter = BlockIterator::create_and_seek_to_first(Arc::new(block));
+                while iter.is_valid() {
+                    iter.next();
+                }
+                iter.seek_to_key(ks(&key_of(10)));
+                iter.seek_to_last();
+            }
+        }
+    }
+}
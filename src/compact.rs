@@ -0,0 +1,91 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use crate::lsm_storage::LsmStorageState;
+
+/// Leveled compaction
+pub mod leveled;
+/// Simple leveled compaction
+pub mod simple_leveled;
+/// Tiered compaction
+pub mod tiered;
+
+pub use leveled::{LeveledCompactionController, LeveledCompactionOptions};
+pub use simple_leveled::{SimpleLeveledCompactionController, SimpleLeveledCompactionOptions};
+pub use tiered::{TieredCompactionController, TieredCompactionOptions};
+
+/// Compaction strategy of the storage engine. The strategy cannot be changed once the storage
+/// has SSTs.
+#[derive(Debug, Clone)]
+pub enum CompactionOptions {
+    /// SSTs are never compacted.
+    NoCompaction,
+    /// Merge each level into the next one once it exceeds its target size.
+    Leveled(LeveledCompactionOptions),
+    /// Flush each mem-table to a tier of its own and merge the newest tiers.
+    Tiered(TieredCompactionOptions),
+    /// Merge a whole level into the next one once their size ratio is too small.
+    SimpleLeveled(SimpleLeveledCompactionOptions),
+    /// A user-provided compaction policy.
+    Custom(Arc<dyn CompactionController>),
+}
+
+impl CompactionOptions {
+    /// Create the controller of the strategy, None if SSTs are never compacted.
+    pub fn controller(&self) -> Option<Arc<dyn CompactionController>> {
+        match self {
+            CompactionOptions::NoCompaction => None,
+            CompactionOptions::Leveled(options) => {
+                Some(Arc::new(LeveledCompactionController::new(options.clone())))
+            }
+            CompactionOptions::Tiered(options) => {
+                Some(Arc::new(TieredCompactionController::new(options.clone())))
+            }
+            CompactionOptions::SimpleLeveled(options) => Some(Arc::new(
+                SimpleLeveledCompactionController::new(options.clone()),
+            )),
+            CompactionOptions::Custom(controller) => Some(Arc::clone(controller)),
+        }
+    }
+}
+
+/// A compaction to run, produced by a compaction controller.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompactionTask {
+    /// Input SSTs of each level, 0 for L0. The levels are ordered from the newest data to the
+    /// oldest, and so are the L0 SSTs.
+    pub inputs: Vec<(usize, Vec<usize>)>,
+    /// The level the output SSTs are added to, it must not be L0.
+    pub output_level: usize,
+    /// No level below the output has data, so tombstones can be dropped.
+    pub compact_to_bottom_level: bool,
+}
+
+impl CompactionTask {
+    /// The input SSTs as `(level, sst_id)`, from the newest to the oldest.
+    pub fn input_ssts(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.inputs
+            .iter()
+            .flat_map(|(level, ssts)| ssts.iter().map(move |id| (*level, *id)))
+    }
+}
+
+/// A compaction policy.
+///
+/// Given the current SST layout, the controller decides which SSTs are merged next. The engine
+/// merges the input SSTs, keeping the newest version of each key, replaces them with the output
+/// in the manifest and the state at once, then removes the input files. Levels are searched in
+/// the order of `LsmStorageState::levels`, so the output level must not hold data newer than the
+/// inputs before it in that order.
+pub trait CompactionController: Send + Sync + Debug {
+    /// Get the next compaction to run, None if the SSTs need no compaction.
+    fn generate_compaction_task(&self, state: &LsmStorageState) -> Option<CompactionTask>;
+
+    /// The level a flushed mem-table is added to, 0 for L0.
+    fn flush_level(&self, _memtable_id: usize) -> usize {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,116 @@
+use super::{CompactionController, CompactionTask};
+use crate::lsm_storage::LsmStorageState;
+
+/// Options of leveled compaction.
+#[derive(Debug, Clone)]
+pub struct LeveledCompactionOptions {
+    /// Size ratio between two adjacent levels.
+    pub level_size_multiplier: usize,
+    /// Number of L0 SSTs that triggers an L0 to L1 compaction.
+    pub level0_file_num_compaction_trigger: usize,
+    /// Number of levels below L0.
+    pub max_levels: usize,
+    /// Target size of L1 in bytes.
+    pub base_level_size: u64,
+}
+
+impl Default for LeveledCompactionOptions {
+    fn default() -> Self {
+        Self {
+            level_size_multiplier: 10,
+            level0_file_num_compaction_trigger: 4,
+            max_levels: 6,
+            base_level_size: 16 << 20,
+        }
+    }
+}
+
+/// Picks leveled compactions.
+///
+/// L0 is compacted into L1 once it has `level0_file_num_compaction_trigger` SSTs. Since L0 SSTs
+/// overlap with each other, all of them are compacted at once. Otherwise the level `Ln` whose
+/// size exceeds its target `base_level_size * level_size_multiplier ^ (n - 1)` the most has its
+/// oldest SST merged into the next level.
+#[derive(Debug)]
+pub struct LeveledCompactionController {
+    options: LeveledCompactionOptions,
+}
+
+impl LeveledCompactionController {
+    /// Create a controller.
+    pub fn new(options: LeveledCompactionOptions) -> Self {
+        Self { options }
+    }
+
+    /// Target size of a level in bytes.
+    pub fn target_level_size(&self, level: usize) -> u64 {
+        let multiplier = self.options.level_size_multiplier as u64;
+        self.options.base_level_size * multiplier.pow(level as u32 - 1)
+    }
+
+    fn task(
+        &self,
+        state: &LsmStorageState,
+        upper_level: usize,
+        upper_level_sst_ids: Vec<usize>,
+    ) -> CompactionTask {
+        let lower_level = upper_level + 1;
+
+        let upper_ssts = upper_level_sst_ids.iter().map(|id| &state.sstables[id]);
+        let first_key = upper_ssts.clone().map(|sst| sst.first_key()).min().unwrap();
+        let last_key = upper_ssts.map(|sst| sst.last_key()).max().unwrap();
+        let lower_level_sst_ids = state
+            .level(lower_level)
+            .iter()
+            .filter(|id| {
+                let sst = &state.sstables[*id];
+                sst.first_key() <= last_key && sst.last_key() >= first_key
+            })
+            .copied()
+            .collect();
+
+        let compact_to_bottom_level = state
+            .levels
+            .iter()
+            .all(|(level, ssts)| *level <= lower_level || ssts.is_empty());
+
+        CompactionTask {
+            inputs: vec![
+                (upper_level, upper_level_sst_ids),
+                (lower_level, lower_level_sst_ids),
+            ],
+            output_level: lower_level,
+            compact_to_bottom_level,
+        }
+    }
+}
+
+impl CompactionController for LeveledCompactionController {
+    /// Get the next compaction to run, None if every level is within its target size.
+    fn generate_compaction_task(&self, state: &LsmStorageState) -> Option<CompactionTask> {
+        if !state.l0_sstables.is_empty()
+            && state.l0_sstables.len() >= self.options.level0_file_num_compaction_trigger
+        {
+            return Some(self.task(state, 0, state.l0_sstables.clone()));
+        }
+
+        let mut max_score = 1.0;
+        let mut compact_level = None;
+        for level in 1..self.options.max_levels {
+            let size: u64 = state
+                .level(level)
+                .iter()
+                .map(|id| state.sstables[id].table_size())
+                .sum();
+            let score = size as f64 / self.target_level_size(level) as f64;
+            if score > max_score {
+                max_score = score;
+                compact_level = Some(level);
+            }
+        }
+
+        let level = compact_level?;
+        let oldest = *state.level(level).iter().min()?;
+        Some(self.task(state, level, vec![oldest]))
+    }
+}
@@ -0,0 +1,85 @@
+use super::{CompactionController, CompactionTask};
+use crate::lsm_storage::LsmStorageState;
+
+/// Options of simple leveled compaction.
+#[derive(Debug, Clone)]
+pub struct SimpleLeveledCompactionOptions {
+    /// A level is merged into the next one if the next level is smaller than this percentage of
+    /// its size.
+    pub size_ratio_percent: usize,
+    /// Number of L0 SSTs that triggers an L0 to L1 compaction.
+    pub level0_file_num_compaction_trigger: usize,
+    /// Number of levels below L0.
+    pub max_levels: usize,
+}
+
+impl Default for SimpleLeveledCompactionOptions {
+    fn default() -> Self {
+        Self {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 4,
+            max_levels: 6,
+        }
+    }
+}
+
+/// Picks simple leveled compactions, which always merge a whole level into the whole next level.
+///
+/// L0 is merged into L1 once it has `level0_file_num_compaction_trigger` SSTs. Otherwise the
+/// first level `Ln` whose next level is smaller than `size_ratio_percent` percent of `Ln` is
+/// merged into it.
+#[derive(Debug)]
+pub struct SimpleLeveledCompactionController {
+    options: SimpleLeveledCompactionOptions,
+}
+
+impl SimpleLeveledCompactionController {
+    /// Create a controller.
+    pub fn new(options: SimpleLeveledCompactionOptions) -> Self {
+        Self { options }
+    }
+
+    fn task(&self, state: &LsmStorageState, upper_level: usize) -> CompactionTask {
+        let lower_level = upper_level + 1;
+        CompactionTask {
+            inputs: vec![
+                (upper_level, state.level(upper_level).to_vec()),
+                (lower_level, state.level(lower_level).to_vec()),
+            ],
+            output_level: lower_level,
+            compact_to_bottom_level: state
+                .levels
+                .iter()
+                .all(|(level, ssts)| *level <= lower_level || ssts.is_empty()),
+        }
+    }
+}
+
+impl CompactionController for SimpleLeveledCompactionController {
+    fn generate_compaction_task(&self, state: &LsmStorageState) -> Option<CompactionTask> {
+        if !state.l0_sstables.is_empty()
+            && state.l0_sstables.len() >= self.options.level0_file_num_compaction_trigger
+        {
+            return Some(self.task(state, 0));
+        }
+
+        let level_size = |level| -> u64 {
+            state
+                .level(level)
+                .iter()
+                .map(|id| state.sstables[id].table_size())
+                .sum()
+        };
+        for level in 1..self.options.max_levels {
+            let upper_size = level_size(level);
+            if upper_size == 0 {
+                continue;
+            }
+            let lower_size = level_size(level + 1);
+            if lower_size * 100 < upper_size * self.options.size_ratio_percent as u64 {
+                return Some(self.task(state, level));
+            }
+        }
+        None
+    }
+}
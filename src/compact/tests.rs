@@ -0,0 +1,269 @@
+use std::{collections::HashMap, fs, ops::Range, sync::Arc};
+
+use super::{CompactionController, CompactionOptions, CompactionTask};
+use super::{LeveledCompactionOptions, SimpleLeveledCompactionOptions, TieredCompactionOptions};
+use crate::key::{KeySlice, TS_DEFAULT};
+use crate::lsm_storage::LsmStorageState;
+use crate::mem_table::MemTable;
+use crate::sstable::builder::SSTableBuilder;
+
+fn key_of(val: usize) -> Vec<u8> {
+    format!("key_{:05}", val).into_bytes()
+}
+
+fn value_of(val: usize) -> Vec<u8> {
+    format!("val_{:010}", val).into_bytes()
+}
+
+fn ks(key: &[u8]) -> KeySlice<'_> {
+    KeySlice::from_slice(key, TS_DEFAULT)
+}
+
+/// Id of an SST and the range of its keys.
+type SstKeys = (usize, Range<usize>);
+
+/// Build a state whose SSTs hold the keys of the given ranges.
+fn build_state(
+    name: &str,
+    l0_sstables: &[SstKeys],
+    levels: &[(usize, Vec<SstKeys>)],
+) -> LsmStorageState {
+    let dir = format!("./tmp/{name}");
+    _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    let mut sstables = HashMap::new();
+    let ssts = l0_sstables
+        .iter()
+        .chain(levels.iter().flat_map(|(_, ssts)| ssts.iter()));
+    for (id, keys) in ssts {
+        let mut builder = SSTableBuilder::new(128);
+        for i in keys.clone() {
+            builder.add(ks(&key_of(i)), &value_of(i));
+        }
+        let sst = builder.build(*id, None, format!("{dir}/{id}.sst")).unwrap();
+        sstables.insert(*id, Arc::new(sst));
+    }
+    fs::remove_dir_all(&dir).unwrap();
+
+    LsmStorageState {
+        memtable: Arc::new(MemTable::create(0)),
+        imm_memtables: vec![],
+        l0_sstables: l0_sstables.iter().map(|(id, _)| *id).collect(),
+        levels: levels
+            .iter()
+            .map(|(level, ssts)| (*level, ssts.iter().map(|(id, _)| *id).collect()))
+            .collect(),
+        sstables,
+    }
+}
+
+fn task(
+    inputs: &[(usize, &[usize])],
+    output_level: usize,
+    compact_to_bottom_level: bool,
+) -> Option<CompactionTask> {
+    Some(CompactionTask {
+        inputs: inputs
+            .iter()
+            .map(|(level, ssts)| (*level, ssts.to_vec()))
+            .collect(),
+        output_level,
+        compact_to_bottom_level,
+    })
+}
+
+fn leveled_controller(base_level_size: u64) -> Arc<dyn CompactionController> {
+    let options = LeveledCompactionOptions {
+        level_size_multiplier: 2,
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 3,
+        base_level_size,
+    };
+    CompactionOptions::Leveled(options).controller().unwrap()
+}
+
+#[test]
+fn test_leveled_l0_compaction() {
+    let state = build_state(
+        "compact-leveled-l0",
+        &[(5, 100..200)],
+        &[(1, vec![(1, 0..100), (2, 150..250), (3, 300..400)])],
+    );
+    let controller = leveled_controller(1 << 30);
+    assert_eq!(controller.generate_compaction_task(&state), None);
+
+    let state = build_state(
+        "compact-leveled-l0",
+        &[(6, 250..260), (5, 100..200)],
+        &[
+            (1, vec![(1, 0..100), (2, 150..250), (3, 300..400)]),
+            (2, vec![]),
+        ],
+    );
+    assert_eq!(
+        controller.generate_compaction_task(&state),
+        task(&[(0, &[6, 5]), (1, &[2])], 1, true)
+    );
+}
+
+#[test]
+fn test_leveled_level_compaction() {
+    let state = build_state(
+        "compact-leveled-level",
+        &[(9, 0..10)],
+        &[
+            (1, vec![(4, 0..100), (3, 100..200)]),
+            (2, vec![(1, 0..50), (2, 50..150), (5, 150..300)]),
+            (3, vec![(6, 0..1000)]),
+        ],
+    );
+    let l1_size = state.sstables[&4].table_size() + state.sstables[&3].table_size();
+    let l2_size: u64 = [1, 2, 5]
+        .iter()
+        .map(|id| state.sstables[id].table_size())
+        .sum();
+    assert!(l2_size < l1_size * 2);
+
+    // L1 is over its target, its oldest SST is merged into L2
+    let controller = leveled_controller(l1_size - 1);
+    assert_eq!(
+        controller.generate_compaction_task(&state),
+        task(&[(1, &[3]), (2, &[2, 5])], 2, false)
+    );
+
+    // the last level is never compacted
+    let controller = leveled_controller(l1_size);
+    assert_eq!(controller.generate_compaction_task(&state), None);
+}
+
+fn tiered_controller() -> Arc<dyn CompactionController> {
+    let options = TieredCompactionOptions {
+        num_tiers: 3,
+        max_size_amplification_percent: 200,
+        size_ratio: 1,
+        min_merge_width: 2,
+    };
+    CompactionOptions::Tiered(options).controller().unwrap()
+}
+
+/// Tiers holding one SST each, with the ids and the key ranges of the SSTs from the newest tier.
+fn tiers(ssts: &[SstKeys]) -> Vec<(usize, Vec<SstKeys>)> {
+    ssts.iter()
+        .map(|(id, keys)| (usize::MAX - id, vec![(*id, keys.clone())]))
+        .collect()
+}
+
+fn tiered_task(ids: &[usize], compact_to_bottom_level: bool) -> Option<CompactionTask> {
+    Some(CompactionTask {
+        inputs: ids.iter().map(|id| (usize::MAX - id, vec![*id])).collect(),
+        output_level: usize::MAX - ids[0],
+        compact_to_bottom_level,
+    })
+}
+
+#[test]
+fn test_tiered_compaction() {
+    let controller = tiered_controller();
+    assert_eq!(controller.flush_level(5), usize::MAX - 5);
+
+    let state = build_state("compact-tiered", &[], &tiers(&[(2, 0..300), (1, 0..300)]));
+    assert_eq!(controller.generate_compaction_task(&state), None);
+
+    // space amplification
+    let state = build_state(
+        "compact-tiered",
+        &[],
+        &tiers(&[(3, 0..300), (2, 0..300), (1, 0..200)]),
+    );
+    assert_eq!(
+        controller.generate_compaction_task(&state),
+        tiered_task(&[3, 2, 1], true)
+    );
+
+    // size ratio
+    let state = build_state(
+        "compact-tiered",
+        &[],
+        &tiers(&[(3, 0..100), (2, 100..200), (1, 0..1000)]),
+    );
+    assert_eq!(
+        controller.generate_compaction_task(&state),
+        tiered_task(&[3, 2], false)
+    );
+
+    // number of sorted runs
+    let state = build_state(
+        "compact-tiered",
+        &[],
+        &tiers(&[(4, 0..100), (3, 0..300), (2, 300..600), (1, 0..2000)]),
+    );
+    assert_eq!(
+        controller.generate_compaction_task(&state),
+        tiered_task(&[4, 3, 2], false)
+    );
+
+    // fewer than two tiers are treated as two
+    let options = TieredCompactionOptions {
+        num_tiers: 1,
+        max_size_amplification_percent: 200,
+        size_ratio: 1,
+        min_merge_width: 2,
+    };
+    let controller = CompactionOptions::Tiered(options).controller().unwrap();
+    let state = build_state("compact-tiered", &[], &tiers(&[(2, 0..100), (1, 0..2000)]));
+    assert_eq!(
+        controller.generate_compaction_task(&state),
+        tiered_task(&[2, 1], true)
+    );
+}
+
+#[test]
+fn test_simple_leveled_compaction() {
+    let options = SimpleLeveledCompactionOptions {
+        size_ratio_percent: 200,
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 3,
+    };
+    let controller = CompactionOptions::SimpleLeveled(options)
+        .controller()
+        .unwrap();
+    assert_eq!(controller.flush_level(5), 0);
+
+    let state = build_state(
+        "compact-simple-leveled",
+        &[(9, 0..10)],
+        &[
+            (1, vec![(4, 0..100)]),
+            (2, vec![(1, 0..50), (2, 50..250)]),
+            (3, vec![(3, 0..600)]),
+        ],
+    );
+    assert_eq!(controller.generate_compaction_task(&state), None);
+
+    // L0 is merged into the whole L1
+    let state = build_state(
+        "compact-simple-leveled",
+        &[(10, 500..510), (9, 0..10)],
+        &[(1, vec![(4, 0..100)]), (2, vec![(1, 0..50), (2, 50..250)])],
+    );
+    assert_eq!(
+        controller.generate_compaction_task(&state),
+        task(&[(0, &[10, 9]), (1, &[4])], 1, false)
+    );
+
+    // L3 is less than twice as large as L2
+    let state = build_state(
+        "compact-simple-leveled",
+        &[(9, 0..10)],
+        &[
+            (1, vec![(4, 0..100)]),
+            (2, vec![(1, 0..50), (2, 50..250)]),
+            (3, vec![(3, 0..400)]),
+        ],
+    );
+    assert_eq!(
+        controller.generate_compaction_task(&state),
+        task(&[(2, &[1, 2]), (3, &[3])], 3, true)
+    );
+}
@@ -0,0 +1,111 @@
+use super::{CompactionController, CompactionTask};
+use crate::lsm_storage::LsmStorageState;
+
+/// Options of tiered compaction.
+#[derive(Debug, Clone)]
+pub struct TieredCompactionOptions {
+    /// Number of sorted runs that triggers a compaction, at least 2.
+    pub num_tiers: usize,
+    /// All tiers are merged once the size of the tiers above the last one reaches this percentage
+    /// of the last tier.
+    pub max_size_amplification_percent: usize,
+    /// A tier is merged with the newer tiers if it is at most `100 + size_ratio` percent of their
+    /// total size.
+    pub size_ratio: usize,
+    /// Minimum number of tiers merged by the size ratio rule.
+    pub min_merge_width: usize,
+}
+
+impl Default for TieredCompactionOptions {
+    fn default() -> Self {
+        Self {
+            num_tiers: 8,
+            max_size_amplification_percent: 200,
+            size_ratio: 1,
+            min_merge_width: 2,
+        }
+    }
+}
+
+/// Picks tiered (universal) compactions.
+///
+/// Each flushed mem-table becomes a tier of its own, and a compaction merges the newest tiers
+/// into one. Tiers are numbered after the newest mem-table they hold, counting down from
+/// `usize::MAX`, so that newer tiers come first in `LsmStorageState::levels` like upper levels
+/// do. Once there are `num_tiers` tiers, the first rule that applies picks the compaction:
+///
+/// 1. Space amplification: merge all tiers if the tiers above the last one are too large.
+/// 2. Size ratio: merge the newest tiers, adding older ones while each is not much larger than
+///    the tiers before it.
+/// 3. Otherwise merge the newest tiers so that `num_tiers - 1` tiers are left.
+#[derive(Debug)]
+pub struct TieredCompactionController {
+    options: TieredCompactionOptions,
+}
+
+impl TieredCompactionController {
+    /// Create a controller.
+    pub fn new(options: TieredCompactionOptions) -> Self {
+        Self { options }
+    }
+
+    /// The tier a flushed mem-table is added to.
+    pub fn tier_of_memtable(memtable_id: usize) -> usize {
+        usize::MAX - memtable_id
+    }
+
+    /// Merge the newest `num` tiers into the newest of them, which is newer than the tiers left.
+    fn task(&self, state: &LsmStorageState, num: usize) -> CompactionTask {
+        CompactionTask {
+            inputs: state.levels[..num].to_vec(),
+            output_level: state.levels[0].0,
+            compact_to_bottom_level: num == state.levels.len(),
+        }
+    }
+}
+
+impl CompactionController for TieredCompactionController {
+    /// Get the next compaction to run, None if there are less than `num_tiers` tiers.
+    fn generate_compaction_task(&self, state: &LsmStorageState) -> Option<CompactionTask> {
+        let tiers = &state.levels;
+        // at least two tiers are merged
+        let num_tiers = self.options.num_tiers.max(2);
+        if tiers.len() < num_tiers {
+            return None;
+        }
+
+        let tier_size = |ssts: &Vec<usize>| -> u64 {
+            ssts.iter().map(|id| state.sstables[id].table_size()).sum()
+        };
+        let sizes = tiers
+            .iter()
+            .map(|(_, ssts)| tier_size(ssts))
+            .collect::<Vec<_>>();
+
+        // space amplification
+        let last_size = sizes[sizes.len() - 1];
+        let upper_size: u64 = sizes[..sizes.len() - 1].iter().sum();
+        if upper_size * 100 >= self.options.max_size_amplification_percent as u64 * last_size {
+            return Some(self.task(state, tiers.len()));
+        }
+
+        // size ratio
+        let mut size = sizes[0];
+        let mut num = 1;
+        while num < sizes.len() && sizes[num] * 100 <= size * (100 + self.options.size_ratio as u64)
+        {
+            size += sizes[num];
+            num += 1;
+        }
+        if num >= self.options.min_merge_width.max(2) {
+            return Some(self.task(state, num));
+        }
+
+        // number of sorted runs
+        Some(self.task(state, tiers.len() - num_tiers + 2))
+    }
+
+    fn flush_level(&self, memtable_id: usize) -> usize {
+        Self::tier_of_memtable(memtable_id)
+    }
+}
@@ -1,19 +1,122 @@
-///
+use anyhow::{bail, Result};
+
+use crate::key::KeySlice;
+
+/// merge iterator
 pub mod merge_iterator;
 
-///
+/// merge iterator over two types
+pub mod two_merge_iterator;
+
+/// iterator stopping at an upper bound
+pub mod bounded_iterator;
+
+/// Storage iterator
 pub trait StorageIterator {
     /// Get the current value.
     fn value(&self) -> &[u8];
 
-    /// Get the current key.
-    fn key(&self) -> &[u8];
+    /// Get the current internal key.
+    fn key(&self) -> KeySlice<'_>;
 
     /// Check if the current iterator is valid.
     fn is_valid(&self) -> bool;
 
-    /// Move to the next position.
+    /// Check if the current entry is a tombstone.
+    fn is_deleted(&self) -> bool;
+
+    /// Move to the next position. The iterator becomes invalid if it is at the last entry.
+    /// Moving an invalid iterator is an error, it must be repositioned by a seek first.
     fn next(&mut self) -> anyhow::Result<()>;
+
+    /// Move to the first entry whose internal key is at or after `key`.
+    fn seek(&mut self, key: KeySlice) -> anyhow::Result<()>;
+
+    /// Move to the first entry.
+    fn seek_to_first(&mut self) -> anyhow::Result<()>;
+
+    /// Move to the previous position. The iterator becomes invalid if it is at the first entry.
+    /// Like `next`, an iterator that ran off either end must be repositioned by a seek, e.g.
+    /// `seek_to_last` to iterate backward from the end.
+    fn prev(&mut self) -> anyhow::Result<()>;
+
+    /// Move to the last entry.
+    fn seek_to_last(&mut self) -> anyhow::Result<()>;
+
+    /// Move to the last entry whose internal key is at or before `key`.
+    fn seek_for_prev(&mut self, key: KeySlice) -> anyhow::Result<()>;
+}
+
+/// Move an iterator forward, or backward if `reverse` is set.
+pub(crate) fn step<I: StorageIterator + ?Sized>(iter: &mut I, reverse: bool) -> Result<()> {
+    if reverse {
+        iter.prev()
+    } else {
+        iter.next()
+    }
+}
+
+/// Move an iterator past its current entry, forward or backward if `reverse` is set, if `skip`
+/// holds for the key of the entry.
+pub(crate) fn step_if(
+    iter: &mut impl StorageIterator,
+    reverse: bool,
+    skip: impl FnOnce(KeySlice) -> bool,
+) -> Result<()> {
+    if iter.is_valid() && skip(iter.key()) {
+        step(iter, reverse)?;
+    }
+    Ok(())
+}
+
+/// Fail if `iter` is invalid, as it has no position to move from.
+pub(crate) fn ensure_valid(iter: &impl StorageIterator) -> Result<()> {
+    if !iter.is_valid() {
+        bail!("cannot move an invalid iterator");
+    }
+    Ok(())
+}
+
+/// Tracks whether an iterator has hit an error. A poisoned iterator is invalid and refuses to move
+/// again, as its position is unknown.
+#[derive(Debug, Default)]
+pub(crate) struct Poison(bool);
+
+impl Poison {
+    /// Fail if the iterator is poisoned.
+    pub(crate) fn check(&self) -> Result<()> {
+        if self.0 {
+            bail!("iterator is poisoned by an earlier error");
+        }
+        Ok(())
+    }
+
+    /// Poison the iterator if `result` is an error.
+    pub(crate) fn track<T>(&mut self, result: Result<T>) -> Result<T> {
+        self.0 |= result.is_err();
+        result
+    }
+
+    /// Check if the iterator is poisoned.
+    pub(crate) fn is_poisoned(&self) -> bool {
+        self.0
+    }
+
+    /// Run `f` on `iter` unless it is poisoned, an error poisons it.
+    pub(crate) fn guard<I: Poisonable>(
+        iter: &mut I,
+        f: impl FnOnce(&mut I) -> Result<()>,
+    ) -> Result<()> {
+        iter.poison().check()?;
+        let result = f(iter);
+        iter.poison().track(result)
+    }
+}
+
+/// An iterator that is poisoned by its errors.
+pub(crate) trait Poisonable {
+    /// Get the poison of the iterator.
+    fn poison(&mut self) -> &mut Poison;
 }
 
 #[cfg(test)]
@@ -0,0 +1,92 @@
+use std::ops::Bound;
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use super::{ensure_valid, StorageIterator};
+use crate::key::KeySlice;
+use crate::mem_table::upper_key_bound;
+
+/// An iterator that stops at an upper bound on the user keys. The entries after the bound are
+/// never exposed: the iterator becomes invalid when it reaches them, and backward seeks start at
+/// the bound instead of the end of the inner iterator.
+#[derive(Debug)]
+pub struct BoundedIterator<I: StorageIterator> {
+    inner: I,
+    upper: Bound<Bytes>,
+}
+
+impl<I: StorageIterator> BoundedIterator<I> {
+    /// Bound a positioned iterator.
+    pub fn new(inner: I, upper: Bound<Bytes>) -> Self {
+        Self { inner, upper }
+    }
+
+    /// Check if `key` is at or before the upper bound.
+    fn in_bound(&self, key: &[u8]) -> bool {
+        match &self.upper {
+            Bound::Unbounded => true,
+            Bound::Included(upper) => key <= &upper[..],
+            Bound::Excluded(upper) => key < &upper[..],
+        }
+    }
+}
+
+impl<I: StorageIterator> StorageIterator for BoundedIterator<I> {
+    fn value(&self) -> &[u8] {
+        self.inner.value()
+    }
+
+    fn key(&self) -> KeySlice<'_> {
+        self.inner.key()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.inner.is_valid() && self.in_bound(self.inner.key().key_ref())
+    }
+
+    fn is_deleted(&self) -> bool {
+        self.inner.is_deleted()
+    }
+
+    fn next(&mut self) -> Result<()> {
+        ensure_valid(self)?;
+        self.inner.next()
+    }
+
+    fn seek(&mut self, key: KeySlice) -> Result<()> {
+        self.inner.seek(key)
+    }
+
+    fn seek_to_first(&mut self) -> Result<()> {
+        self.inner.seek_to_first()
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        ensure_valid(self)?;
+        self.inner.prev()
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        let upper = self.upper.clone();
+        match upper_key_bound(upper.as_ref().map(|x| &x[..])) {
+            Bound::Included(key) => self.inner.seek_for_prev(key),
+            Bound::Excluded(key) => {
+                self.inner.seek_for_prev(key)?;
+                while self.inner.is_valid() && self.inner.key().key_ref() == key.key_ref() {
+                    self.inner.prev()?;
+                }
+                Ok(())
+            }
+            Bound::Unbounded => self.inner.seek_to_last(),
+        }
+    }
+
+    fn seek_for_prev(&mut self, key: KeySlice) -> Result<()> {
+        if self.in_bound(key.key_ref()) {
+            self.inner.seek_for_prev(key)
+        } else {
+            self.seek_to_last()
+        }
+    }
+}
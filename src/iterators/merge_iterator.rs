@@ -1,110 +1,124 @@
 use anyhow::Ok;
 
-use super::StorageIterator;
+use super::{ensure_valid, step, Poison, Poisonable, StorageIterator};
+use crate::key::KeySlice;
 use std::{cmp, collections::BinaryHeap};
 
-/// HeapWrapper
+/// HeapWrapper, whose last field tells if the heap is ordered for backward iteration.
 #[derive(Debug)]
-struct HeapWrapper<I: StorageIterator>(usize, Box<I>);
+struct HeapWrapper<I: StorageIterator + ?Sized>(usize, Box<I>, bool);
 
-impl<I: StorageIterator> PartialEq for HeapWrapper<I> {
+impl<I: StorageIterator + ?Sized> PartialEq for HeapWrapper<I> {
     fn eq(&self, other: &Self) -> bool {
-        self.partial_cmp(other).unwrap() == cmp::Ordering::Equal
+        self.cmp(other) == cmp::Ordering::Equal
     }
 }
 
-impl<I: StorageIterator> Eq for HeapWrapper<I> {}
+impl<I: StorageIterator + ?Sized> Eq for HeapWrapper<I> {}
 
-impl<I: StorageIterator> PartialOrd for HeapWrapper<I> {
+impl<I: StorageIterator + ?Sized> PartialOrd for HeapWrapper<I> {
     fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
-        match self.1.key().cmp(other.1.key()) {
-            cmp::Ordering::Equal => self.0.partial_cmp(&other.0),
-            cmp::Ordering::Less => Some(cmp::Ordering::Less),
-            cmp::Ordering::Greater => Some(cmp::Ordering::Greater),
-        }
-        .map(|x| x.reverse())
+        Some(self.cmp(other))
     }
 }
 
-impl<I: StorageIterator> Ord for HeapWrapper<I> {
+impl<I: StorageIterator + ?Sized> Ord for HeapWrapper<I> {
     fn cmp(&self, other: &Self) -> cmp::Ordering {
-        self.partial_cmp(other).unwrap()
+        let key = self.1.key().cmp(&other.1.key());
+        // the smaller index comes first in both directions
+        if self.2 {
+            key.then(other.0.cmp(&self.0))
+        } else {
+            key.then(self.0.cmp(&other.0)).reverse()
+        }
     }
 }
 
 /// Merge multiple iterators of the same type. If the same key occurs multiple times in some
-/// iterators, perfer the one with smaller index.
+/// iterators, perfer the one with smaller index. Iterators of different types can be merged with
+/// `TwoMergeIterator` or by boxing them as `dyn StorageIterator`. The heap is ordered by the
+/// direction of the iteration, changing direction repositions every iterator.
 #[derive(Debug)]
-pub struct MergeIterator<I: StorageIterator> {
+pub struct MergeIterator<I: StorageIterator + ?Sized> {
     iters: BinaryHeap<HeapWrapper<I>>,
     current: Option<HeapWrapper<I>>,
+    /// Exhausted iterators, kept to be repositioned by seeks.
+    exhausted: Vec<HeapWrapper<I>>,
+    reverse: bool,
+    poison: Poison,
 }
 
-impl<I: StorageIterator> MergeIterator<I> {
-    ///
+impl<I: StorageIterator + ?Sized> MergeIterator<I> {
+    /// Create a merge iterator.
     pub fn create(iters: Vec<Box<I>>) -> Self {
-        if iters.is_empty() {
-            return Self {
-                iters: BinaryHeap::new(),
-                current: None,
-            };
-        }
-
-        let mut heap = BinaryHeap::new();
-
-        if iters.iter().all(|x| !x.is_valid()) {
-            let mut iters = iters;
-            return Self {
-                iters: heap,
-                current: Some(HeapWrapper(0, iters.pop().unwrap())),
-            };
-        }
+        let mut iter = Self {
+            iters: BinaryHeap::new(),
+            current: None,
+            exhausted: iters
+                .into_iter()
+                .enumerate()
+                .map(|(idx, iter)| HeapWrapper(idx, iter, false))
+                .collect(),
+            reverse: false,
+            poison: Poison::default(),
+        };
+        iter.build_heap();
+        iter
+    }
 
-        for (idx, iter) in iters.into_iter().enumerate() {
-            if iter.is_valid() {
-                heap.push(HeapWrapper(idx, iter));
+    /// Build the heap from the iterators, which are all in `exhausted`.
+    fn build_heap(&mut self) {
+        for mut iter in std::mem::take(&mut self.exhausted) {
+            iter.2 = self.reverse;
+            if iter.1.is_valid() {
+                self.iters.push(iter);
+            } else {
+                self.exhausted.push(iter);
             }
         }
-
-        let current = heap.pop();
-        Self {
-            iters: heap,
-            current,
-        }
-    }
-}
-
-impl<I: StorageIterator> StorageIterator for MergeIterator<I> {
-    fn key(&self) -> &[u8] {
-        self.current.as_ref().unwrap().1.key()
+        self.current = self.iters.pop();
     }
 
-    fn value(&self) -> &[u8] {
-        self.current.as_ref().unwrap().1.value()
+    /// Reposition every iterator with `seek` and rebuild the heap for the direction.
+    fn seek_all(
+        &mut self,
+        reverse: bool,
+        mut seek: impl FnMut(&mut I) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        self.exhausted.extend(self.current.take());
+        self.exhausted.extend(self.iters.drain());
+        let result = self
+            .exhausted
+            .iter_mut()
+            .try_for_each(|iter| seek(&mut iter.1));
+        self.reverse = reverse;
+        self.build_heap();
+        result
     }
 
-    fn is_valid(&self) -> bool {
-        self.current.is_some()
-    }
-
-    fn next(&mut self) -> anyhow::Result<()> {
-        let mut current = std::mem::replace(&mut self.current, None).unwrap();
+    /// Move the current iterator and the ones at the same key in the direction of the heap.
+    fn advance(&mut self) -> anyhow::Result<()> {
+        let mut current = self.current.take().unwrap();
 
         while !self.iters.is_empty() {
             if self.iters.peek_mut().unwrap().1.key() == current.1.key() {
                 let mut iter = self.iters.pop().unwrap();
-                iter.1.next().unwrap();
+                step(&mut *iter.1, self.reverse)?;
                 if iter.1.is_valid() {
                     self.iters.push(iter);
+                } else {
+                    self.exhausted.push(iter);
                 }
             } else {
                 break;
             }
         }
 
-        current.1.next().unwrap();
+        step(&mut *current.1, self.reverse)?;
         if current.1.is_valid() {
             self.iters.push(current);
+        } else {
+            self.exhausted.push(current);
         }
 
         self.current = self.iters.pop();
@@ -112,3 +126,75 @@ impl<I: StorageIterator> StorageIterator for MergeIterator<I> {
         Ok(())
     }
 }
+
+impl<I: StorageIterator + ?Sized> Poisonable for MergeIterator<I> {
+    fn poison(&mut self) -> &mut Poison {
+        &mut self.poison
+    }
+}
+
+impl<I: StorageIterator + ?Sized> StorageIterator for MergeIterator<I> {
+    fn key(&self) -> KeySlice<'_> {
+        self.current.as_ref().unwrap().1.key()
+    }
+
+    fn value(&self) -> &[u8] {
+        self.current.as_ref().unwrap().1.value()
+    }
+
+    fn is_deleted(&self) -> bool {
+        self.current.as_ref().unwrap().1.is_deleted()
+    }
+
+    fn is_valid(&self) -> bool {
+        !self.poison.is_poisoned()
+            && self
+                .current
+                .as_ref()
+                .map(|x| x.1.is_valid())
+                .unwrap_or(false)
+    }
+
+    fn next(&mut self) -> anyhow::Result<()> {
+        ensure_valid(self)?;
+        Poison::guard(self, |iter| {
+            if iter.reverse {
+                // every iterator moves to the current key, which is skipped below
+                let key = iter.key().to_key_vec();
+                iter.seek_all(false, |iter| iter.seek(key.as_key_slice()))?;
+            }
+            iter.advance()
+        })
+    }
+
+    fn seek(&mut self, key: KeySlice) -> anyhow::Result<()> {
+        Poison::guard(self, |iter| iter.seek_all(false, |iter| iter.seek(key)))
+    }
+
+    fn seek_to_first(&mut self) -> anyhow::Result<()> {
+        Poison::guard(self, |iter| {
+            iter.seek_all(false, |iter| iter.seek_to_first())
+        })
+    }
+
+    fn prev(&mut self) -> anyhow::Result<()> {
+        ensure_valid(self)?;
+        Poison::guard(self, |iter| {
+            if !iter.reverse {
+                let key = iter.key().to_key_vec();
+                iter.seek_all(true, |iter| iter.seek_for_prev(key.as_key_slice()))?;
+            }
+            iter.advance()
+        })
+    }
+
+    fn seek_to_last(&mut self) -> anyhow::Result<()> {
+        Poison::guard(self, |iter| iter.seek_all(true, |iter| iter.seek_to_last()))
+    }
+
+    fn seek_for_prev(&mut self, key: KeySlice) -> anyhow::Result<()> {
+        Poison::guard(self, |iter| {
+            iter.seek_all(true, |iter| iter.seek_for_prev(key))
+        })
+    }
+}
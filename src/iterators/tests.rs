@@ -1,11 +1,16 @@
-use std::{fs, path::Path, sync::Arc};
+use std::{fs, ops::Bound, path::Path, sync::Arc};
 
 use bytes::Bytes;
 use rand::Rng;
 
+use crate::key::{KeySlice, TS_DEFAULT};
+use crate::mem_table::MemTable;
 use crate::sstable::{builder::SSTableBuilder, iterator::SSTableIterator};
 
-use super::{merge_iterator::MergeIterator, StorageIterator};
+use super::{
+    bounded_iterator::BoundedIterator, merge_iterator::MergeIterator,
+    two_merge_iterator::TwoMergeIterator, StorageIterator,
+};
 
 fn key_of(val: usize) -> Vec<u8> {
     format!("key_{:05}", val).into_bytes()
@@ -15,6 +20,10 @@ fn value_of(val: usize) -> Vec<u8> {
     format!("val_{:010}", val).into_bytes()
 }
 
+fn ks(key: &[u8]) -> KeySlice<'_> {
+    KeySlice::from_slice(key, TS_DEFAULT)
+}
+
 fn as_bytes(x: &[u8]) -> Bytes {
     Bytes::copy_from_slice(x)
 }
@@ -86,9 +95,9 @@ fn test_merge_iterator_non_overlap() {
             let key = key_of(i);
             let value = value_of(i);
             if i & 1 == 0 {
-                sst[0].add(&key, &value)
+                sst[0].add(ks(&key), &value)
             } else {
-                sst[1].add(&key, &value)
+                sst[1].add(ks(&key), &value)
             }
         }
     };
@@ -97,9 +106,9 @@ fn test_merge_iterator_non_overlap() {
         let mut iter = MergeIterator::create(iters);
         for i in 0..100 {
             assert!(iter.is_valid(), "{i}");
-            let key = iter.key();
+            let key = iter.key().key_ref();
             let value = iter.value();
-            assert_kv(i, &key, &value);
+            assert_kv(i, key, value);
             iter.next().unwrap();
         }
     };
@@ -110,11 +119,11 @@ fn test_merge_iterator_non_overlap() {
 #[test]
 fn test_merge_iterator_overlap() {
     let map = |sst: &mut Vec<SSTableBuilder>| {
-        for idx in 0..3 {
+        for builder in sst.iter_mut() {
             for i in 0..100 {
                 let key = key_of(i);
                 let value = value_of(0);
-                sst[idx].add(&key, &value);
+                builder.add(ks(&key), &value);
             }
         }
     };
@@ -123,7 +132,7 @@ fn test_merge_iterator_overlap() {
         let mut iter = MergeIterator::create(iters);
         for i in 0..100 {
             assert!(iter.is_valid(), "{i}");
-            let key = iter.key();
+            let key = iter.key().key_ref();
             let value = iter.value();
 
             assert_eq!(
@@ -155,11 +164,11 @@ fn test_merge_iterator_test1() {
         for i in 0..100 {
             let key = key_of(i);
             if i & 1 == 0 {
-                sst[0].add(&key, &value_of(0));
+                sst[0].add(ks(&key), &value_of(0));
             } else {
-                sst[1].add(&key, &value_of(1));
+                sst[1].add(ks(&key), &value_of(1));
             }
-            sst[2].add(&key, &value_of(2));
+            sst[2].add(ks(&key), &value_of(2));
         }
     };
 
@@ -167,7 +176,7 @@ fn test_merge_iterator_test1() {
         let mut iter = MergeIterator::create(iters);
         for i in 0..100 {
             assert!(iter.is_valid(), "{i}");
-            let key = iter.key();
+            let key = iter.key().key_ref();
             let value = iter.value();
 
             assert_eq!(
@@ -202,3 +211,329 @@ fn test_merge_iterator_test1() {
 
     generate_sstable_test(3, map, reduce);
 }
+
+#[test]
+fn test_merge_iterator_seek() {
+    let map = |sst: &mut Vec<SSTableBuilder>| {
+        for i in 0..100 {
+            sst[i % 3].add(ks(&key_of(i)), &value_of(i));
+        }
+    };
+
+    let reduce = |iters: Vec<Box<SSTableIterator>>| {
+        let mut iter = MergeIterator::create(iters);
+        let check_from = |iter: &mut MergeIterator<SSTableIterator>, start: usize| {
+            for i in start..100 {
+                assert!(iter.is_valid(), "{i}");
+                assert_kv(i, iter.key().key_ref(), iter.value());
+                iter.next().unwrap();
+            }
+            assert!(!iter.is_valid());
+        };
+
+        // seek forward and backward within the same iterator
+        for start in [50, 10, 99, 0] {
+            iter.seek(ks(&key_of(start))).unwrap();
+            assert_kv(start, iter.key().key_ref(), iter.value());
+        }
+
+        // the exhausted children are repositioned as well
+        check_from(&mut iter, 0);
+        iter.seek(ks(&key_of(30))).unwrap();
+        check_from(&mut iter, 30);
+        iter.seek(ks(b"key_999999")).unwrap();
+        assert!(!iter.is_valid());
+        iter.seek_to_first().unwrap();
+        check_from(&mut iter, 0);
+    };
+
+    generate_sstable_test(3, map, reduce);
+}
+
+#[test]
+fn test_merge_iterator_reverse() {
+    // every key is in the third table, the even ones in the first and the odd ones in the second
+    let map = |sst: &mut Vec<SSTableBuilder>| {
+        for i in 0..100 {
+            sst[i % 2].add(ks(&key_of(i)), &value_of(i));
+            sst[2].add(ks(&key_of(i)), &value_of(i + 1000));
+        }
+    };
+
+    let reduce = |iters: Vec<Box<SSTableIterator>>| {
+        let mut iter = MergeIterator::create(iters);
+        iter.seek_to_last().unwrap();
+        for i in (0..100).rev() {
+            assert!(iter.is_valid(), "{i}");
+            assert_kv(i, iter.key().key_ref(), iter.value());
+            iter.prev().unwrap();
+        }
+        assert!(!iter.is_valid());
+        assert!(iter.next().is_err());
+        assert!(iter.prev().is_err());
+
+        iter.seek_for_prev(ks(&key_of(50))).unwrap();
+        assert_kv(50, iter.key().key_ref(), iter.value());
+        iter.seek_for_prev(ks(b"key_999999")).unwrap();
+        assert_kv(99, iter.key().key_ref(), iter.value());
+
+        // changing direction skips the duplicates of the current key
+        iter.seek(ks(&key_of(40))).unwrap();
+        let moves = [
+            (true, 41),
+            (true, 42),
+            (false, 41),
+            (false, 40),
+            (false, 39),
+            (true, 40),
+        ];
+        for (forward, i) in moves {
+            if forward {
+                iter.next().unwrap();
+            } else {
+                iter.prev().unwrap();
+            }
+            assert_kv(i, iter.key().key_ref(), iter.value());
+        }
+    };
+
+    generate_sstable_test(3, map, reduce);
+}
+
+#[test]
+fn test_two_merge_iterator() {
+    // a mem-table over an L0 table over a table of a level, the upper ones hiding the lower ones
+    let memtable = MemTable::create(0);
+    for i in (0..100).step_by(4) {
+        memtable.put(ks(&key_of(i)), &value_of(i + 1000)).unwrap();
+    }
+    let map = |sst: &mut Vec<SSTableBuilder>| {
+        for i in 0..100 {
+            if i % 2 == 0 {
+                sst[0].add(ks(&key_of(i)), &value_of(i + 2000));
+            }
+            sst[1].add(ks(&key_of(i)), &value_of(i));
+        }
+    };
+    let value = |i: usize| match i % 4 {
+        0 => value_of(i + 1000),
+        2 => value_of(i + 2000),
+        _ => value_of(i),
+    };
+    let check = |iter: &dyn StorageIterator, i: usize| {
+        assert!(iter.is_valid(), "{i}");
+        assert_eq!(iter.key().key_ref(), key_of(i));
+        assert_eq!(iter.value(), value(i), "{i}");
+    };
+
+    let reduce = |mut iters: Vec<Box<SSTableIterator>>| {
+        let level = *iters.pop().unwrap();
+        let l0 = *iters.pop().unwrap();
+        let memtable = memtable.scan(Bound::Unbounded, Bound::Unbounded);
+        let upper = TwoMergeIterator::create(memtable, l0).unwrap();
+        let mut iter = TwoMergeIterator::create(upper, level).unwrap();
+        for i in 0..100 {
+            check(&iter, i);
+            iter.next().unwrap();
+        }
+        assert!(!iter.is_valid());
+        assert!(iter.prev().is_err());
+
+        iter.seek_to_last().unwrap();
+        for i in (0..100).rev() {
+            check(&iter, i);
+            iter.prev().unwrap();
+        }
+        assert!(!iter.is_valid());
+
+        iter.seek(ks(&key_of(40))).unwrap();
+        check(&iter, 40);
+        iter.seek_for_prev(ks(&key_of(60))).unwrap();
+        check(&iter, 60);
+
+        // changing direction skips the hidden entries of the current key
+        let moves = [
+            (true, 61),
+            (true, 62),
+            (false, 61),
+            (false, 60),
+            (false, 59),
+            (true, 60),
+        ];
+        for (forward, i) in moves {
+            if forward {
+                iter.next().unwrap();
+            } else {
+                iter.prev().unwrap();
+            }
+            check(&iter, i);
+        }
+
+        iter.seek_to_first().unwrap();
+        check(&iter, 0);
+    };
+
+    generate_sstable_test(2, map, reduce);
+}
+
+#[test]
+fn test_bounded_iterator() {
+    let map = |sst: &mut Vec<SSTableBuilder>| {
+        for i in 0..100 {
+            sst[i % 2].add(ks(&key_of(i)), &value_of(i));
+        }
+    };
+    for (upper, end) in [
+        (Bound::Excluded(key_of(50)), 50),
+        (Bound::Included(key_of(50)), 51),
+        (Bound::Unbounded, 100),
+    ] {
+        let reduce = |iters: Vec<Box<SSTableIterator>>| {
+            let iters = iters
+                .into_iter()
+                .map(|iter| Box::new(BoundedIterator::new(*iter, upper.clone().map(Bytes::from))))
+                .collect();
+            let mut iter = MergeIterator::create(iters);
+            for i in 0..end {
+                assert!(iter.is_valid(), "idx:{i}");
+                assert_kv(i, iter.key().key_ref(), iter.value());
+                iter.next().unwrap();
+            }
+            assert!(!iter.is_valid());
+
+            // backward iteration starts at the bound
+            iter.seek_to_last().unwrap();
+            for i in (0..end).rev() {
+                assert!(iter.is_valid(), "idx:{i}");
+                assert_kv(i, iter.key().key_ref(), iter.value());
+                iter.prev().unwrap();
+            }
+            assert!(!iter.is_valid());
+
+            iter.seek_for_prev(ks(&key_of(80))).unwrap();
+            assert_kv(end.min(81) - 1, iter.key().key_ref(), iter.value());
+            iter.seek(ks(&key_of(80))).unwrap();
+            assert_eq!(iter.is_valid(), end > 80);
+        };
+        generate_sstable_test(2, map, reduce);
+    }
+}
+
+/// An iterator over sorted entries whose moves fail once it reaches `error_at`.
+struct MockIterator {
+    data: Vec<(Vec<u8>, Vec<u8>)>,
+    idx: usize,
+    error_at: usize,
+}
+
+impl MockIterator {
+    fn new(data: Vec<(Vec<u8>, Vec<u8>)>, error_at: usize) -> Self {
+        Self {
+            data,
+            idx: 0,
+            error_at,
+        }
+    }
+
+    fn move_to(&mut self, idx: usize) -> anyhow::Result<()> {
+        if idx == self.error_at {
+            anyhow::bail!("mock error");
+        }
+        self.idx = idx;
+        Ok(())
+    }
+}
+
+impl StorageIterator for MockIterator {
+    fn value(&self) -> &[u8] {
+        &self.data[self.idx].1
+    }
+
+    fn key(&self) -> KeySlice<'_> {
+        ks(&self.data[self.idx].0)
+    }
+
+    fn is_valid(&self) -> bool {
+        self.idx < self.data.len()
+    }
+
+    fn is_deleted(&self) -> bool {
+        false
+    }
+
+    fn next(&mut self) -> anyhow::Result<()> {
+        self.move_to(self.idx + 1)
+    }
+
+    fn seek(&mut self, key: KeySlice) -> anyhow::Result<()> {
+        let idx = self.data.partition_point(|(k, _)| ks(k) < key);
+        self.move_to(idx)
+    }
+
+    fn seek_to_first(&mut self) -> anyhow::Result<()> {
+        self.move_to(0)
+    }
+
+    fn prev(&mut self) -> anyhow::Result<()> {
+        self.move_to(self.idx.checked_sub(1).unwrap_or(self.data.len()))
+    }
+
+    fn seek_to_last(&mut self) -> anyhow::Result<()> {
+        self.move_to(self.data.len().saturating_sub(1))
+    }
+
+    fn seek_for_prev(&mut self, key: KeySlice) -> anyhow::Result<()> {
+        let idx = self.data.partition_point(|(k, _)| ks(k) <= key);
+        self.move_to(idx.checked_sub(1).unwrap_or(self.data.len()))
+    }
+}
+
+fn mock_data(range: impl Iterator<Item = usize>) -> Vec<(Vec<u8>, Vec<u8>)> {
+    range.map(|i| (key_of(i), value_of(i))).collect()
+}
+
+/// Check that `iter` fails at `i` and refuses to move afterwards.
+fn assert_poisoned(mut iter: impl StorageIterator, i: usize) {
+    for j in 0..i {
+        assert!(iter.is_valid());
+        assert_kv(j, iter.key().key_ref(), iter.value());
+        assert!(iter.next().is_ok());
+    }
+    assert!(iter.next().is_err());
+    assert!(!iter.is_valid());
+    assert!(iter.next().is_err());
+    assert!(iter.seek_to_first().is_err());
+    assert!(iter.seek(ks(&key_of(0))).is_err());
+    assert!(iter.seek_to_last().is_err());
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_merge_iterator_error() {
+    // the even keys fail when moving to the key 20
+    let even = MockIterator::new(mock_data((0..50).step_by(2)), 10);
+    let odd = MockIterator::new(mock_data((1..50).step_by(2)), 50);
+    let iter = MergeIterator::create(vec![Box::new(even), Box::new(odd)]);
+    assert_poisoned(iter, 18);
+
+    // the odd keys fail when seeking backward to the key 9
+    let even = MockIterator::new(mock_data((0..50).step_by(2)), 50);
+    let odd = MockIterator::new(mock_data((1..50).step_by(2)), 4);
+    let mut iter = MergeIterator::create(vec![Box::new(even), Box::new(odd)]);
+    iter.seek_for_prev(ks(&key_of(10))).unwrap_err();
+    assert!(!iter.is_valid());
+    assert!(iter.prev().is_err());
+}
+
+#[test]
+fn test_two_merge_iterator_error() {
+    let a = MockIterator::new(mock_data((0..50).step_by(2)), 50);
+    let b = MockIterator::new(mock_data(0..50), 31);
+    let iter = TwoMergeIterator::create(a, b).unwrap();
+    assert_poisoned(iter, 29);
+
+    // skipping the entry of B hidden by A fails
+    let a = MockIterator::new(mock_data(0..1), 50);
+    let b = MockIterator::new(mock_data(0..50), 1);
+    assert!(TwoMergeIterator::create(a, b).is_err());
+}
@@ -0,0 +1,187 @@
+use anyhow::Result;
+
+use super::{ensure_valid, step, step_if, Poison, Poisonable, StorageIterator};
+use crate::key::{KeySlice, KeyVec};
+
+/// Merge two iterators of different types. If the same key occurs in both iterators, prefer the
+/// one in A and skip the one in B. Changing the direction of the iteration repositions both
+/// iterators.
+#[derive(Debug)]
+pub struct TwoMergeIterator<A: StorageIterator, B: StorageIterator> {
+    a: A,
+    b: B,
+    /// The current entry is from A.
+    use_a: bool,
+    reverse: bool,
+    poison: Poison,
+}
+
+impl<A: StorageIterator, B: StorageIterator> TwoMergeIterator<A, B> {
+    /// Create a merge iterator over two positioned iterators.
+    pub fn create(a: A, b: B) -> Result<Self> {
+        let mut iter = Self {
+            a,
+            b,
+            use_a: false,
+            reverse: false,
+            poison: Poison::default(),
+        };
+        iter.skip_b()?;
+        iter.choose();
+        Ok(iter)
+    }
+
+    /// Skip the entry of B if A is at the same key.
+    fn skip_b(&mut self) -> Result<()> {
+        if self.a.is_valid() && self.b.is_valid() && self.a.key() == self.b.key() {
+            step(&mut self.b, self.reverse)?;
+        }
+        Ok(())
+    }
+
+    fn choose(&mut self) {
+        self.use_a = self.a.is_valid()
+            && (!self.b.is_valid() || {
+                let order = self.a.key().cmp(&self.b.key());
+                if self.reverse {
+                    order.is_ge()
+                } else {
+                    order.is_le()
+                }
+            });
+    }
+
+    /// Move the iterator of the current entry in the direction of the iteration.
+    fn advance(&mut self) -> Result<()> {
+        if self.use_a {
+            step(&mut self.a, self.reverse)?;
+        } else {
+            step(&mut self.b, self.reverse)?;
+        }
+        self.skip_b()?;
+        self.choose();
+        Ok(())
+    }
+
+    /// Change the direction of the iteration, both iterators move past the current key.
+    fn switch_direction(&mut self) -> Result<()> {
+        let key = self.key().to_key_vec();
+        self.reverse = !self.reverse;
+        reposition(&mut self.a, &key, self.reverse)?;
+        reposition(&mut self.b, &key, self.reverse)?;
+        self.skip_b()?;
+        self.choose();
+        Ok(())
+    }
+
+    /// Position both iterators with `seek` for the direction and pick the current entry.
+    fn seek_both(
+        &mut self,
+        reverse: bool,
+        mut seek: impl FnMut(&mut dyn StorageIterator) -> Result<()>,
+    ) -> Result<()> {
+        self.reverse = reverse;
+        seek(&mut self.a)?;
+        seek(&mut self.b)?;
+        self.skip_b()?;
+        self.choose();
+        Ok(())
+    }
+}
+
+impl<A: StorageIterator, B: StorageIterator> Poisonable for TwoMergeIterator<A, B> {
+    fn poison(&mut self) -> &mut Poison {
+        &mut self.poison
+    }
+}
+
+impl<A: StorageIterator, B: StorageIterator> StorageIterator for TwoMergeIterator<A, B> {
+    fn key(&self) -> KeySlice<'_> {
+        if self.use_a {
+            self.a.key()
+        } else {
+            self.b.key()
+        }
+    }
+
+    fn value(&self) -> &[u8] {
+        if self.use_a {
+            self.a.value()
+        } else {
+            self.b.value()
+        }
+    }
+
+    fn is_deleted(&self) -> bool {
+        if self.use_a {
+            self.a.is_deleted()
+        } else {
+            self.b.is_deleted()
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        if self.poison.is_poisoned() {
+            return false;
+        }
+        if self.use_a {
+            self.a.is_valid()
+        } else {
+            self.b.is_valid()
+        }
+    }
+
+    fn next(&mut self) -> Result<()> {
+        ensure_valid(self)?;
+        Poison::guard(self, |iter| {
+            if iter.reverse {
+                iter.switch_direction()
+            } else {
+                iter.advance()
+            }
+        })
+    }
+
+    fn seek(&mut self, key: KeySlice) -> Result<()> {
+        Poison::guard(self, |iter| iter.seek_both(false, |iter| iter.seek(key)))
+    }
+
+    fn seek_to_first(&mut self) -> Result<()> {
+        Poison::guard(self, |iter| {
+            iter.seek_both(false, |iter| iter.seek_to_first())
+        })
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        ensure_valid(self)?;
+        Poison::guard(self, |iter| {
+            if iter.reverse {
+                iter.advance()
+            } else {
+                iter.switch_direction()
+            }
+        })
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        Poison::guard(self, |iter| {
+            iter.seek_both(true, |iter| iter.seek_to_last())
+        })
+    }
+
+    fn seek_for_prev(&mut self, key: KeySlice) -> Result<()> {
+        Poison::guard(self, |iter| {
+            iter.seek_both(true, |iter| iter.seek_for_prev(key))
+        })
+    }
+}
+
+/// Move an iterator to the first entry after `key` in the direction of the iteration.
+fn reposition(iter: &mut impl StorageIterator, key: &KeyVec, reverse: bool) -> Result<()> {
+    if reverse {
+        iter.seek_for_prev(key.as_key_slice())?;
+    } else {
+        iter.seek(key.as_key_slice())?;
+    }
+    step_if(iter, reverse, |x| x == key.as_key_slice())
+}
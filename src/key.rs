@@ -0,0 +1,155 @@
+use std::cmp::{Ordering, Reverse};
+use std::fmt::Debug;
+
+use bytes::Bytes;
+
+/// The smallest timestamp, older than every version written by the storage engine.
+pub const TS_MIN: u64 = 0;
+
+/// The largest timestamp. `(key, TS_MAX)` sorts before every version of `key`.
+pub const TS_MAX: u64 = u64::MAX;
+
+/// Timestamp of the keys written outside of the storage engine, e.g. by tests.
+pub const TS_DEFAULT: u64 = TS_MIN;
+
+/// An internal key: a user key with the timestamp of its version. Internal keys are ordered by
+/// user key ascending, then by timestamp descending, so the newest version of a key comes first.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Key<T: AsRef<[u8]>>(T, u64);
+
+/// A borrowed internal key.
+pub type KeySlice<'a> = Key<&'a [u8]>;
+
+/// An owned internal key that can be reused.
+pub type KeyVec = Key<Vec<u8>>;
+
+/// A reference-counted internal key.
+pub type KeyBytes = Key<Bytes>;
+
+impl<T: AsRef<[u8]>> Key<T> {
+    /// Get the timestamp.
+    pub fn ts(&self) -> u64 {
+        self.1
+    }
+
+    /// Length of the user key.
+    pub fn key_len(&self) -> usize {
+        self.0.as_ref().len()
+    }
+
+    /// Length of the encoded internal key, the user key followed by the timestamp as a u64.
+    pub fn raw_len(&self) -> usize {
+        self.key_len() + std::mem::size_of::<u64>()
+    }
+
+    /// Borrow the key.
+    pub fn as_key_slice(&self) -> KeySlice<'_> {
+        Key(self.0.as_ref(), self.1)
+    }
+
+    /// Copy the key to a `KeyVec`.
+    pub fn to_key_vec(&self) -> KeyVec {
+        Key(self.0.as_ref().to_vec(), self.1)
+    }
+
+    /// Copy the key to a `KeyBytes`.
+    pub fn to_key_bytes(&self) -> KeyBytes {
+        Key(Bytes::copy_from_slice(self.0.as_ref()), self.1)
+    }
+}
+
+impl<'a> KeySlice<'a> {
+    /// Create a key from a user key and a timestamp.
+    pub fn from_slice(key: &'a [u8], ts: u64) -> Self {
+        Key(key, ts)
+    }
+
+    /// Get the user key.
+    pub fn key_ref(self) -> &'a [u8] {
+        self.0
+    }
+}
+
+impl KeyVec {
+    /// Create an empty key.
+    pub fn new() -> Self {
+        Key(Vec::new(), TS_DEFAULT)
+    }
+
+    /// Create a key from a user key and a timestamp.
+    pub fn from_vec(key: Vec<u8>, ts: u64) -> Self {
+        Key(key, ts)
+    }
+
+    /// Get the user key.
+    pub fn key_ref(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Replace the key with a copy of `key`, reusing the buffer.
+    pub fn set_from_slice(&mut self, key: KeySlice) {
+        self.0.clear();
+        self.0.extend_from_slice(key.0);
+        self.1 = key.1;
+    }
+
+    /// Keep the first `len` bytes of the user key.
+    pub fn truncate(&mut self, len: usize) {
+        self.0.truncate(len);
+    }
+
+    /// Append bytes to the user key.
+    pub fn append(&mut self, data: &[u8]) {
+        self.0.extend_from_slice(data);
+    }
+
+    /// Set the timestamp.
+    pub fn set_ts(&mut self, ts: u64) {
+        self.1 = ts;
+    }
+
+    /// Convert the key to a `KeyBytes`.
+    pub fn into_key_bytes(self) -> KeyBytes {
+        Key(self.0.into(), self.1)
+    }
+}
+
+impl KeyBytes {
+    /// Create a key from a user key and a timestamp.
+    pub fn from_bytes(key: Bytes, ts: u64) -> Self {
+        Key(key, ts)
+    }
+
+    /// Get the user key.
+    pub fn key_ref(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Get the user key.
+    pub fn key(&self) -> &Bytes {
+        &self.0
+    }
+}
+
+impl<T: AsRef<[u8]> + Eq> Ord for Key<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.0.as_ref(), Reverse(self.1)).cmp(&(other.0.as_ref(), Reverse(other.1)))
+    }
+}
+
+impl<T: AsRef<[u8]> + Eq> PartialOrd for Key<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T: AsRef<[u8]>> Debug for Key<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?}@{}",
+            Bytes::copy_from_slice(self.0.as_ref()),
+            self.1
+        )
+    }
+}
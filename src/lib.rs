@@ -1,10 +1,26 @@
 /// block
 pub mod block;
-///
+/// compaction
+pub mod compact;
+/// iterators
 pub mod iterators;
-///
+/// internal keys
+pub mod key;
+/// storage engine iterator
+pub mod lsm_iterator;
+/// storage engine
 pub mod lsm_storage;
-///
+/// manifest
+pub mod manifest;
+/// mem-table
 pub mod mem_table;
-///
+/// multi-version concurrency control
+pub mod mvcc;
+/// range tombstones
+pub mod range_tombstone;
+/// sstable
 pub mod sstable;
+/// write-ahead log
+pub mod wal;
+/// atomic write batches
+pub mod write_batch;
@@ -0,0 +1,290 @@
+use std::ops::Bound;
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use crate::iterators::bounded_iterator::BoundedIterator;
+use crate::iterators::merge_iterator::MergeIterator;
+use crate::iterators::two_merge_iterator::TwoMergeIterator;
+use crate::iterators::{ensure_valid, Poison, Poisonable, StorageIterator};
+use crate::key::{KeySlice, KeyVec};
+use crate::mem_table::{lower_key_bound, upper_key_bound, MemTableIterator};
+use crate::range_tombstone::RangeTombstone;
+use crate::sstable::iterator::SSTableIterator;
+
+/// The mem-tables merged with the SSTs, the mem-tables taking precedence.
+type LsmIteratorInner = TwoMergeIterator<
+    MergeIterator<MemTableIterator>,
+    MergeIterator<BoundedIterator<SSTableIterator>>,
+>;
+
+/// An iterator over the whole storage engine at a read timestamp. Only the newest version of each
+/// key at or below the read timestamp is visible, keys whose visible version is a tombstone or is
+/// covered by a range tombstone are skipped and the iteration stops at the upper bound of the
+/// scan. Iterating backward stops at the lower bound, and seeks never leave the bounds.
+pub struct LsmIterator {
+    inner: LsmIteratorInner,
+    start_bound: Bound<Bytes>,
+    end_bound: Bound<Bytes>,
+    is_valid: bool,
+    read_ts: u64,
+    /// Range tombstones at or below the read timestamp.
+    range_tombstones: Vec<RangeTombstone>,
+    reverse: bool,
+    /// The current entry when iterating backward, the inner iterator is then before the versions
+    /// of its key.
+    key: KeyVec,
+    value: Vec<u8>,
+    poison: Poison,
+}
+
+impl LsmIterator {
+    pub(crate) fn new(
+        iter: LsmIteratorInner,
+        start_bound: Bound<Bytes>,
+        end_bound: Bound<Bytes>,
+        read_ts: u64,
+        range_tombstones: Vec<RangeTombstone>,
+    ) -> Result<Self> {
+        let mut iter = Self {
+            is_valid: iter.is_valid(),
+            inner: iter,
+            start_bound,
+            end_bound,
+            read_ts,
+            range_tombstones,
+            reverse: false,
+            key: KeyVec::new(),
+            value: Vec::new(),
+            poison: Poison::default(),
+        };
+        iter.check_end_bound();
+        iter.move_to_visible()?;
+        Ok(iter)
+    }
+
+    fn check_end_bound(&mut self) {
+        if !self.is_valid {
+            return;
+        }
+        self.is_valid = match &self.end_bound {
+            Bound::Unbounded => true,
+            Bound::Included(key) => self.inner.key().key_ref() <= &key[..],
+            Bound::Excluded(key) => self.inner.key().key_ref() < &key[..],
+        };
+    }
+
+    fn next_inner(&mut self) -> Result<()> {
+        self.inner.next()?;
+        self.is_valid = self.inner.is_valid();
+        self.check_end_bound();
+        Ok(())
+    }
+
+    /// Skip the versions of the current key.
+    fn skip_key(&mut self) -> Result<()> {
+        let key = self.inner.key().key_ref().to_vec();
+        while self.is_valid && self.inner.key().key_ref() == key {
+            self.next_inner()?;
+        }
+        Ok(())
+    }
+
+    /// Move to the visible version of the first key after `key`, or after the start bound if it is
+    /// larger.
+    fn seek_to_key(&mut self, key: Bound<&[u8]>) -> Result<()> {
+        let key = match (key, &self.start_bound) {
+            (_, Bound::Unbounded) => key,
+            (Bound::Unbounded, start) => start.as_ref().map(|x| &x[..]),
+            (Bound::Included(key), Bound::Included(start)) if key < &start[..] => {
+                Bound::Included(&start[..])
+            }
+            (Bound::Included(key), Bound::Excluded(start)) if key <= &start[..] => {
+                Bound::Excluded(&start[..])
+            }
+            _ => key,
+        };
+        match lower_key_bound(key) {
+            Bound::Included(key) => self.inner.seek(key)?,
+            Bound::Excluded(key) => {
+                self.inner.seek(key)?;
+                while self.inner.is_valid() && self.inner.key().key_ref() == key.key_ref() {
+                    self.inner.next()?;
+                }
+            }
+            Bound::Unbounded => self.inner.seek_to_first()?,
+        }
+        self.reverse = false;
+        self.is_valid = self.inner.is_valid();
+        self.check_end_bound();
+        self.move_to_visible()
+    }
+
+    /// Move to the visible version of the last key before `key`, or before the end bound if it is
+    /// smaller.
+    fn seek_for_prev_key(&mut self, key: Bound<&[u8]>) -> Result<()> {
+        let key = match (key, &self.end_bound) {
+            (_, Bound::Unbounded) => key,
+            (Bound::Unbounded, end) => end.as_ref().map(|x| &x[..]),
+            (Bound::Included(key), Bound::Included(end)) if key > &end[..] => {
+                Bound::Included(&end[..])
+            }
+            (Bound::Included(key), Bound::Excluded(end)) if key >= &end[..] => {
+                Bound::Excluded(&end[..])
+            }
+            _ => key,
+        };
+        match upper_key_bound(key) {
+            Bound::Included(key) => self.inner.seek_for_prev(key)?,
+            Bound::Excluded(key) => {
+                self.inner.seek_for_prev(key)?;
+                while self.inner.is_valid() && self.inner.key().key_ref() == key.key_ref() {
+                    self.inner.prev()?;
+                }
+            }
+            Bound::Unbounded => self.inner.seek_to_last()?,
+        }
+        self.reverse = true;
+        self.move_to_visible_back()
+    }
+
+    /// Check if the inner iterator is at or after the start bound.
+    fn check_start_bound(&self) -> bool {
+        match &self.start_bound {
+            Bound::Unbounded => true,
+            Bound::Included(key) => self.inner.key().key_ref() >= &key[..],
+            Bound::Excluded(key) => self.inner.key().key_ref() > &key[..],
+        }
+    }
+
+    /// Check if the version of a key is deleted by a range tombstone.
+    fn is_covered(&self, key: KeySlice) -> bool {
+        self.range_tombstones
+            .iter()
+            .any(|tombstone| tombstone.covers(key.key_ref(), key.ts()))
+    }
+
+    /// Move backward to the visible version of the key of the inner iterator or a preceding key.
+    /// The versions of a key are met from the oldest to the newest, so every one of them is read.
+    fn move_to_visible_back(&mut self) -> Result<()> {
+        loop {
+            if !self.inner.is_valid() || !self.check_start_bound() {
+                self.is_valid = false;
+                return Ok(());
+            }
+            let user_key = self.inner.key().key_ref().to_vec();
+            let mut visible = None;
+            while self.inner.is_valid() && self.inner.key().key_ref() == user_key {
+                let key = self.inner.key();
+                if key.ts() <= self.read_ts {
+                    visible = (!self.inner.is_deleted())
+                        .then(|| (key.to_key_vec(), self.inner.value().to_vec()));
+                }
+                self.inner.prev()?;
+            }
+            if let Some((key, value)) = visible {
+                if !self.is_covered(key.as_key_slice()) {
+                    self.key = key;
+                    self.value = value;
+                    self.is_valid = true;
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Move to the visible version of the current or a following key.
+    fn move_to_visible(&mut self) -> Result<()> {
+        loop {
+            while self.is_valid && self.inner.key().ts() > self.read_ts {
+                self.next_inner()?;
+            }
+            if !self.is_valid {
+                return Ok(());
+            }
+            if !self.inner.is_deleted() && !self.is_covered(self.inner.key()) {
+                return Ok(());
+            }
+            self.skip_key()?;
+        }
+    }
+}
+
+impl Poisonable for LsmIterator {
+    fn poison(&mut self) -> &mut Poison {
+        &mut self.poison
+    }
+}
+
+impl StorageIterator for LsmIterator {
+    fn value(&self) -> &[u8] {
+        if self.reverse {
+            &self.value
+        } else {
+            self.inner.value()
+        }
+    }
+
+    fn key(&self) -> KeySlice<'_> {
+        if self.reverse {
+            self.key.as_key_slice()
+        } else {
+            self.inner.key()
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        !self.poison.is_poisoned() && self.is_valid
+    }
+
+    fn is_deleted(&self) -> bool {
+        false
+    }
+
+    fn next(&mut self) -> Result<()> {
+        ensure_valid(self)?;
+        Poison::guard(self, |iter| {
+            if iter.reverse {
+                let key = iter.key.key_ref().to_vec();
+                return iter.seek_to_key(Bound::Excluded(&key));
+            }
+            iter.skip_key()?;
+            iter.move_to_visible()
+        })
+    }
+
+    /// Only the user key of `key` is used, the iterator moves to the visible version of the first
+    /// key at or after it.
+    fn seek(&mut self, key: KeySlice) -> Result<()> {
+        Poison::guard(self, |iter| {
+            iter.seek_to_key(Bound::Included(key.key_ref()))
+        })
+    }
+
+    fn seek_to_first(&mut self) -> Result<()> {
+        Poison::guard(self, |iter| iter.seek_to_key(Bound::Unbounded))
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        ensure_valid(self)?;
+        Poison::guard(self, |iter| {
+            if !iter.reverse {
+                let key = iter.inner.key().key_ref().to_vec();
+                return iter.seek_for_prev_key(Bound::Excluded(&key));
+            }
+            iter.move_to_visible_back()
+        })
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        Poison::guard(self, |iter| iter.seek_for_prev_key(Bound::Unbounded))
+    }
+
+    /// Only the user key of `key` is used, the iterator moves to the visible version of the last
+    /// key at or before it.
+    fn seek_for_prev(&mut self, key: KeySlice) -> Result<()> {
+        Poison::guard(self, |iter| {
+            iter.seek_for_prev_key(Bound::Included(key.key_ref()))
+        })
+    }
+}
@@ -1,6 +1,1238 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use crossbeam_channel::{Receiver, Sender};
+use parking_lot::{Condvar, Mutex, RwLock};
 
 use crate::block::Block;
+use crate::compact::{
+    CompactionController, CompactionOptions, CompactionTask, LeveledCompactionOptions,
+};
+use crate::iterators::bounded_iterator::BoundedIterator;
+use crate::iterators::merge_iterator::MergeIterator;
+use crate::iterators::two_merge_iterator::TwoMergeIterator;
+use crate::iterators::StorageIterator;
+use crate::key::{KeySlice, KeyVec, TS_MIN};
+use crate::lsm_iterator::LsmIterator;
+use crate::manifest::{Manifest, ManifestRecord, ManifestState};
+use crate::mem_table::{lower_key_bound, map_bound, upper_key_bound, MemTable};
+use crate::mvcc::txn::Transaction;
+use crate::mvcc::{Snapshot, Watermark};
+use crate::range_tombstone::{max_covering_ts, RangeTombstone};
+use crate::sstable::builder::{SSTableBuilder, DEFAULT_BLOOM_BITS_PER_KEY};
+use crate::sstable::compression::CompressionType;
+use crate::sstable::iterator::SSTableIterator;
+use crate::sstable::{FileObject, SSTable};
+use crate::wal::WalSyncMode;
+use crate::write_batch::WriteBatch;
+
+/// Interval at which the background threads check for work without being notified.
+const BACKGROUND_TICK: Duration = Duration::from_millis(50);
+
+/// Delay of a write slowed down by a write stall.
+const WRITE_SLOWDOWN_DELAY: Duration = Duration::from_millis(1);
 
 /// A block
 pub type BlockCache = moka::sync::Cache<(usize, usize), Arc<Block>>;
+
+/// Options of the storage engine.
+#[derive(Debug, Clone)]
+pub struct LsmStorageOptions {
+    /// Block size in bytes.
+    pub block_size: usize,
+    /// SST size in bytes, also the approximate mem-table capacity limit.
+    pub target_sst_size: usize,
+    /// Maximum number of blocks kept in the block cache.
+    pub block_cache_capacity: u64,
+    /// Number of bloom filter bits per key of the SSTs, 0 disables the filters.
+    pub bloom_bits_per_key: usize,
+    /// Compression algorithm of the SST blocks.
+    pub compression: CompressionType,
+    /// Log every write of the mem-tables to a WAL.
+    pub enable_wal: bool,
+    /// When the WAL is synced to the disk.
+    pub wal_sync_mode: WalSyncMode,
+    /// The manifest is rewritten as a snapshot once it grows larger than this size in bytes.
+    pub manifest_max_size: u64,
+    /// Compaction strategy.
+    pub compaction_options: CompactionOptions,
+    /// Freeze and flush mem-tables and run compactions in background threads. Otherwise
+    /// `force_freeze_memtable`, `force_flush_next_imm_memtable` and `trigger_compaction` must be
+    /// called by hand.
+    pub enable_background_jobs: bool,
+    /// Writes are slowed down once there are this many immutable mem-tables.
+    pub imm_memtable_slowdown_trigger: usize,
+    /// Writes are stopped once there are this many immutable mem-tables.
+    pub imm_memtable_stop_trigger: usize,
+    /// Writes are slowed down once there are this many L0 SSTs.
+    pub level0_slowdown_writes_trigger: usize,
+    /// Writes are stopped once there are this many L0 SSTs. The L0 triggers are ignored without
+    /// compaction, and a custom compaction controller must compact L0 before it reaches them.
+    pub level0_stop_writes_trigger: usize,
+}
+
+impl Default for LsmStorageOptions {
+    fn default() -> Self {
+        Self {
+            block_size: 4096,
+            target_sst_size: 2 << 20,
+            block_cache_capacity: 4096,
+            bloom_bits_per_key: DEFAULT_BLOOM_BITS_PER_KEY,
+            compression: CompressionType::Lz4,
+            enable_wal: true,
+            wal_sync_mode: WalSyncMode::GroupCommit,
+            manifest_max_size: 1 << 20,
+            compaction_options: CompactionOptions::Leveled(LeveledCompactionOptions::default()),
+            enable_background_jobs: true,
+            imm_memtable_slowdown_trigger: 4,
+            imm_memtable_stop_trigger: 8,
+            level0_slowdown_writes_trigger: 8,
+            level0_stop_writes_trigger: 12,
+        }
+    }
+}
+
+impl LsmStorageOptions {
+    /// Reject the options under which writes could stop for good: L0 must be compacted before it
+    /// stops the writes.
+    fn validate(&self) -> Result<()> {
+        let l0_compaction_trigger = match &self.compaction_options {
+            CompactionOptions::Leveled(options) => options.level0_file_num_compaction_trigger,
+            CompactionOptions::SimpleLeveled(options) => options.level0_file_num_compaction_trigger,
+            _ => return Ok(()),
+        };
+        if l0_compaction_trigger >= self.level0_stop_writes_trigger {
+            bail!(
+                "L0 compaction trigger {} is not below the L0 stop writes trigger {}",
+                l0_compaction_trigger,
+                self.level0_stop_writes_trigger
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Why a write is stalled. Stalls only happen with background jobs, writes are slowed down by a
+/// short delay or stopped until the flush or the compaction catches up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WriteStallReason {
+    /// Writes are slowed down because of too many immutable mem-tables.
+    ImmMemtableSlowdown,
+    /// Writes are stopped because of too many immutable mem-tables.
+    ImmMemtableStop,
+    /// Writes are slowed down because of too many L0 SSTs.
+    Level0Slowdown,
+    /// Writes are stopped because of too many L0 SSTs.
+    Level0Stop,
+}
+
+impl WriteStallReason {
+    fn is_stop(self) -> bool {
+        matches!(self, Self::ImmMemtableStop | Self::Level0Stop)
+    }
+}
+
+/// Statistics of the writes stalled for one reason.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WriteStallStats {
+    /// Number of stalled writes.
+    pub count: u64,
+    /// Total time the writes were stalled.
+    pub duration: Duration,
+}
+
+/// Statistics of the storage engine.
+#[derive(Debug, Clone, Default)]
+pub struct LsmStorageStats {
+    /// Write stalls by reason.
+    pub write_stalls: HashMap<WriteStallReason, WriteStallStats>,
+}
+
+impl LsmStorageStats {
+    /// Statistics of the writes stalled for a reason.
+    pub fn write_stall(&self, reason: WriteStallReason) -> WriteStallStats {
+        self.write_stalls.get(&reason).copied().unwrap_or_default()
+    }
+
+    /// Total time the writes were stalled.
+    pub fn write_stall_duration(&self) -> Duration {
+        self.write_stalls.values().map(|stats| stats.duration).sum()
+    }
+}
+
+/// The state of the storage engine. Readers take a snapshot of it by cloning the `Arc`.
+#[derive(Clone)]
+pub struct LsmStorageState {
+    /// The current mem-table.
+    pub memtable: Arc<MemTable>,
+    /// Immutable mem-tables, from the newest to the oldest.
+    pub imm_memtables: Vec<Arc<MemTable>>,
+    /// L0 SSTs, from the newest to the oldest.
+    pub l0_sstables: Vec<usize>,
+    /// SSTs of the other levels, from the upper level to the lower level. The SSTs of a level are
+    /// sorted by key.
+    pub levels: Vec<(usize, Vec<usize>)>,
+    /// SST objects.
+    pub sstables: HashMap<usize, Arc<SSTable>>,
+}
+
+impl LsmStorageState {
+    /// Ids of all SSTs, from the newest L0 SST to the lowest level.
+    pub fn sst_ids(&self) -> impl Iterator<Item = &usize> {
+        self.l0_sstables
+            .iter()
+            .chain(self.levels.iter().flat_map(|(_, ssts)| ssts.iter()))
+    }
+
+    /// Ids of the SSTs of a level, 0 for L0.
+    pub fn level(&self, level: usize) -> &[usize] {
+        if level == 0 {
+            return &self.l0_sstables;
+        }
+        self.levels
+            .iter()
+            .find(|(x, _)| *x == level)
+            .map_or(&[], |(_, ssts)| ssts)
+    }
+
+    /// Replace the input SSTs of a compaction with its output.
+    fn apply_compaction_result(&mut self, task: &CompactionTask, output: &[Arc<SSTable>]) {
+        for (level, sst_id) in task.input_ssts() {
+            if level == 0 {
+                self.l0_sstables.retain(|x| *x != sst_id);
+            } else if let Some((_, ssts)) = self.levels.iter_mut().find(|(x, _)| *x == level) {
+                ssts.retain(|x| *x != sst_id);
+            }
+            self.sstables.remove(&sst_id);
+        }
+        self.levels.retain(|(_, ssts)| !ssts.is_empty());
+
+        self.add_ssts(task.output_level, output);
+    }
+
+    /// Add SSTs to a level, L0 SSTs are added as the newest ones.
+    fn add_ssts(&mut self, level: usize, ssts: &[Arc<SSTable>]) {
+        if ssts.is_empty() {
+            return;
+        }
+        for sst in ssts {
+            self.sstables.insert(sst.sst_id(), Arc::clone(sst));
+        }
+        let ids = ssts.iter().map(|sst| sst.sst_id());
+        if level == 0 {
+            for id in ids {
+                self.l0_sstables.insert(0, id);
+            }
+            return;
+        }
+
+        match self.levels.iter_mut().find(|(x, _)| *x == level) {
+            Some((_, level_ssts)) => level_ssts.extend(ids),
+            None => {
+                self.levels.push((level, ids.collect()));
+                self.levels.sort_by_key(|(x, _)| *x);
+            }
+        }
+        self.sort_level(level);
+    }
+
+    /// Sort the SSTs of a level by their first keys.
+    fn sort_level(&mut self, level: usize) {
+        if let Some((_, ssts)) = self.levels.iter_mut().find(|(x, _)| *x == level) {
+            ssts.sort_by(|a, b| {
+                self.sstables[a]
+                    .first_key()
+                    .cmp(self.sstables[b].first_key())
+            });
+        }
+    }
+}
+
+/// The storage engine. Unless disabled by the options, a flush thread freezes the current
+/// mem-table once it reaches `target_sst_size` and flushes the immutable mem-tables, and a
+/// compaction thread runs the compactions. Both are stopped by `close` or on drop.
+pub struct LsmStorage {
+    inner: Arc<LsmStorageInner>,
+    flush_thread: Mutex<Option<(Sender<()>, JoinHandle<()>)>>,
+    compaction_thread: Mutex<Option<(Sender<()>, JoinHandle<()>)>>,
+    closed: AtomicBool,
+}
+
+impl LsmStorage {
+    /// Open the storage engine in the given directory, creating the directory if needed. The SST
+    /// structure is rebuilt from the manifest, and the mem-tables that were not flushed are
+    /// recovered from their WALs as immutable mem-tables.
+    pub fn open(path: impl AsRef<Path>, options: LsmStorageOptions) -> Result<Self> {
+        let (flush_notifier, flush_rx) = crossbeam_channel::bounded(1);
+        let (compaction_notifier, compaction_rx) = crossbeam_channel::bounded(1);
+        let inner = Arc::new(LsmStorageInner::open(
+            path.as_ref(),
+            options,
+            flush_notifier,
+            compaction_notifier,
+        )?);
+
+        let mut storage = Self {
+            inner,
+            flush_thread: Mutex::new(None),
+            compaction_thread: Mutex::new(None),
+            closed: AtomicBool::new(false),
+        };
+        if storage.inner.options.enable_background_jobs {
+            let inner = Arc::clone(&storage.inner);
+            *storage.flush_thread.get_mut() =
+                Some(spawn_worker("lsm-flush", flush_rx, move || {
+                    inner.trigger_flush()
+                })?);
+            let inner = Arc::clone(&storage.inner);
+            *storage.compaction_thread.get_mut() =
+                Some(spawn_worker("lsm-compaction", compaction_rx, move || {
+                    while inner.trigger_compaction()? {}
+                    Ok(())
+                })?);
+        }
+        Ok(storage)
+    }
+
+    /// Get a value by key. Returns `None` if the key does not exist or has been deleted.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        self.inner.get(key)
+    }
+
+    /// Get the newest version of a key at or below `read_ts`, which is clamped to the latest
+    /// commit timestamp. Versions below the lowest live snapshot may be garbage-collected, use a
+    /// `Snapshot` for a stable view.
+    pub fn get_at(&self, key: &[u8], read_ts: u64) -> Result<Option<Bytes>> {
+        self.inner.get_at(key, read_ts)
+    }
+
+    /// Put a key-value pair into the storage engine.
+    pub fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.inner.put(key, value)
+    }
+
+    /// Delete a key from the storage engine by writing a tombstone.
+    pub fn delete(&self, key: &[u8]) -> Result<()> {
+        self.inner.delete(key)
+    }
+
+    /// Apply the writes of a batch atomically.
+    pub fn write_batch(&self, batch: &WriteBatch) -> Result<()> {
+        self.inner.write_batch(batch)?;
+        Ok(())
+    }
+
+    /// Delete the keys in `[start, end)` by writing a range tombstone.
+    pub fn delete_range(&self, start: &[u8], end: &[u8]) -> Result<()> {
+        self.inner.delete_range(start, end)
+    }
+
+    /// Make the writes to the current mem-table durable.
+    pub fn sync(&self) -> Result<()> {
+        self.inner.sync()
+    }
+
+    /// Create an iterator over a range of keys. Newer sources take precedence over older ones.
+    pub fn scan(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<LsmIterator> {
+        self.inner.scan(lower, upper)
+    }
+
+    /// Create an iterator over a range of keys, showing the newest version of each key at or
+    /// below `read_ts`, which is clamped to the latest commit timestamp. Versions below the lowest
+    /// live snapshot may be garbage-collected, use a `Snapshot` for a stable view.
+    pub fn scan_at(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        read_ts: u64,
+    ) -> Result<LsmIterator> {
+        self.inner.scan_at(lower, upper, read_ts)
+    }
+
+    /// Get the timestamp of the latest write. Every write at or below it is visible.
+    pub fn latest_commit_ts(&self) -> u64 {
+        self.inner.latest_commit_ts()
+    }
+
+    /// Take a snapshot at the latest commit timestamp. Compactions keep the versions it can see
+    /// until it is dropped.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::new(Arc::clone(&self.inner))
+    }
+
+    /// Start a transaction reading at the latest commit timestamp.
+    pub fn new_txn(&self) -> Transaction {
+        Transaction::new(Arc::clone(&self.inner))
+    }
+
+    /// Freeze the current mem-table and move it to the immutable mem-tables.
+    pub fn force_freeze_memtable(&self) -> Result<()> {
+        self.inner.force_freeze_memtable()
+    }
+
+    /// Flush the oldest immutable mem-table to an SST. Does nothing if there is no immutable
+    /// mem-table.
+    pub fn force_flush_next_imm_memtable(&self) -> Result<()> {
+        self.inner.force_flush_next_imm_memtable()
+    }
+
+    /// Run a compaction if the compaction strategy finds one necessary. Returns false if there is
+    /// nothing to compact.
+    pub fn trigger_compaction(&self) -> Result<bool> {
+        self.inner.trigger_compaction()
+    }
+
+    /// Get the statistics of the storage engine.
+    pub fn stats(&self) -> LsmStorageStats {
+        self.inner.stats.lock().clone()
+    }
+
+    /// Stop the background threads if any and finish their pending work: the immutable mem-tables
+    /// are flushed and compactions run until none is necessary. Without a WAL, the current
+    /// mem-table is flushed as well. Does nothing if the storage is already closed.
+    pub fn close(&self) -> Result<()> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        let flush_thread = self.flush_thread.lock().take();
+        let compaction_thread = self.compaction_thread.lock().take();
+        for (stop, handle) in flush_thread.into_iter().chain(compaction_thread) {
+            drop(stop);
+            if handle.join().is_err() {
+                bail!("background thread panicked");
+            }
+        }
+
+        if !self.inner.options.enable_wal && !self.inner.snapshot().memtable.is_empty() {
+            self.inner.force_freeze_memtable()?;
+        }
+        self.inner.flush_imm_memtables()?;
+        while self.inner.trigger_compaction()? {}
+        self.inner.sync()?;
+        self.closed.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+impl Drop for LsmStorage {
+    fn drop(&mut self) {
+        if let Err(e) = self.close() {
+            log::error!("failed to close the storage engine: {:#}", e);
+        }
+    }
+}
+
+/// Spawn a thread that runs `job` whenever it is notified and every `BACKGROUND_TICK`, until the
+/// returned sender is dropped.
+fn spawn_worker<F>(
+    name: &str,
+    notified: Receiver<()>,
+    job: F,
+) -> Result<(Sender<()>, JoinHandle<()>)>
+where
+    F: Fn() -> Result<()> + Send + 'static,
+{
+    let (stop, stopped) = crossbeam_channel::bounded::<()>(0);
+    let ticker = crossbeam_channel::tick(BACKGROUND_TICK);
+    let handle = std::thread::Builder::new()
+        .name(name.to_string())
+        .spawn(move || loop {
+            crossbeam_channel::select! {
+                recv(notified) -> _ => {}
+                recv(ticker) -> _ => {}
+                recv(stopped) -> _ => return,
+            }
+            if let Err(e) = job() {
+                log::error!("background job failed: {:#}", e);
+            }
+        })?;
+    Ok((stop, handle))
+}
+
+/// The state and the operations of the storage engine, shared with the background threads.
+pub(crate) struct LsmStorageInner {
+    state: RwLock<Arc<LsmStorageState>>,
+    /// Serializes the operations that modify the structure of the state.
+    state_lock: Mutex<()>,
+    path: PathBuf,
+    manifest: Manifest,
+    block_cache: Arc<BlockCache>,
+    next_sst_id: AtomicUsize,
+    options: LsmStorageOptions,
+    compaction_controller: Option<Arc<dyn CompactionController>>,
+    /// Serializes compactions.
+    compaction_lock: Mutex<()>,
+    /// Wakes up the flush thread.
+    flush_notifier: Sender<()>,
+    /// Wakes up the compaction thread.
+    compaction_notifier: Sender<()>,
+    /// Notified when a flush or a compaction changes the state, stopped writes wait on it.
+    stall_cond: Condvar,
+    stall_lock: Mutex<()>,
+    stats: Mutex<LsmStorageStats>,
+    /// The last timestamp given to a write.
+    next_ts: AtomicU64,
+    /// Every write at or below this timestamp is visible. Writes are published in timestamp
+    /// order, so a read at this timestamp sees no gap.
+    commit_ts: AtomicU64,
+    commit_lock: Mutex<()>,
+    /// Notified when `commit_ts` advances.
+    commit_cond: Condvar,
+    /// Read timestamps of the live snapshots.
+    watermark: Mutex<Watermark>,
+    /// Keys written by the transactions committed above the GC watermark, by commit timestamp.
+    /// Also serializes the commits of transactions.
+    pub(crate) committed_txns: Mutex<BTreeMap<u64, HashSet<Bytes>>>,
+}
+
+impl LsmStorageInner {
+    fn open(
+        path: &Path,
+        options: LsmStorageOptions,
+        flush_notifier: Sender<()>,
+        compaction_notifier: Sender<()>,
+    ) -> Result<Self> {
+        options.validate()?;
+        let path = path.to_path_buf();
+        std::fs::create_dir_all(&path)?;
+
+        let manifest_path = path.join("MANIFEST");
+        let (manifest, manifest_state) = if manifest_path.exists() {
+            Manifest::recover(&manifest_path, options.manifest_max_size)?
+        } else {
+            let manifest = Manifest::create(&manifest_path, options.manifest_max_size)?;
+            (manifest, ManifestState::default())
+        };
+
+        // Files left behind by an interrupted flush or compaction must not be overwritten.
+        let mut next_id = 1;
+        for entry in std::fs::read_dir(&path)? {
+            let file_name = entry?.file_name();
+            let file_name = file_name.to_string_lossy();
+            if let Some(Ok(id)) = file_name.split_once('.').map(|(id, _)| id.parse::<usize>()) {
+                next_id = next_id.max(id + 1);
+            }
+        }
+        let ids = manifest_state
+            .memtables
+            .iter()
+            .chain(manifest_state.l0_sstables.iter())
+            .chain(
+                manifest_state
+                    .levels
+                    .iter()
+                    .flat_map(|(_, ssts)| ssts.iter()),
+            );
+        for id in ids {
+            next_id = next_id.max(id + 1);
+        }
+
+        let block_cache = Arc::new(BlockCache::new(options.block_cache_capacity));
+
+        let mut sstables = HashMap::new();
+        let sst_ids = manifest_state.l0_sstables.iter().chain(
+            manifest_state
+                .levels
+                .iter()
+                .flat_map(|(_, ssts)| ssts.iter()),
+        );
+        for id in sst_ids {
+            let file = FileObject::open(&Self::path_of_sst_static(&path, *id))?;
+            let sst = SSTable::open(*id, Some(Arc::clone(&block_cache)), file)?;
+            sstables.insert(*id, Arc::new(sst));
+        }
+
+        let mut imm_memtables = Vec::new();
+        if options.enable_wal {
+            for id in &manifest_state.memtables {
+                let path = Self::path_of_wal_static(&path, *id);
+                let memtable = MemTable::recover_from_wal(*id, path, options.wal_sync_mode)?;
+                imm_memtables.insert(0, Arc::new(memtable));
+            }
+        } else if !manifest_state.memtables.is_empty() {
+            let records = manifest_state
+                .memtables
+                .iter()
+                .map(|id| ManifestRecord::FlushDone(*id))
+                .collect();
+            manifest.add_edit(records)?;
+        }
+
+        // Remove the output of interrupted flushes and compactions, the inputs of compactions
+        // that were not removed, and the WALs of flushed mem-tables.
+        for entry in std::fs::read_dir(&path)? {
+            let entry = entry?;
+            let file_name = entry.file_name();
+            let obsolete = match file_name.to_string_lossy().split_once('.') {
+                Some((id, "sst")) => id.parse().is_ok_and(|id| !sstables.contains_key(&id)),
+                Some((id, "wal")) => id
+                    .parse()
+                    .is_ok_and(|id| !options.enable_wal || !manifest_state.memtables.contains(&id)),
+                _ => false,
+            };
+            if obsolete {
+                std::fs::remove_file(entry.path())?;
+            }
+        }
+
+        manifest.add_edit(vec![ManifestRecord::NewMemtable(next_id)])?;
+        let memtable = Self::create_memtable(&path, &options, next_id)?;
+
+        // The manifest appends the SSTs of a level in the order they are created.
+        let mut state = LsmStorageState {
+            memtable,
+            imm_memtables,
+            l0_sstables: manifest_state.l0_sstables,
+            levels: manifest_state.levels,
+            sstables,
+        };
+        for level in state
+            .levels
+            .iter()
+            .map(|(level, _)| *level)
+            .collect::<Vec<_>>()
+        {
+            state.sort_level(level);
+        }
+
+        let max_ts = state
+            .imm_memtables
+            .iter()
+            .map(|memtable| memtable.max_ts())
+            .chain(state.sstables.values().map(|sst| sst.max_ts()))
+            .max()
+            .unwrap_or(TS_MIN);
+
+        Ok(Self {
+            state: RwLock::new(Arc::new(state)),
+            state_lock: Mutex::new(()),
+            path,
+            manifest,
+            block_cache,
+            next_sst_id: AtomicUsize::new(next_id + 1),
+            compaction_controller: options.compaction_options.controller(),
+            compaction_lock: Mutex::new(()),
+            flush_notifier,
+            compaction_notifier,
+            stall_cond: Condvar::new(),
+            stall_lock: Mutex::new(()),
+            stats: Mutex::new(LsmStorageStats::default()),
+            next_ts: AtomicU64::new(max_ts),
+            commit_ts: AtomicU64::new(max_ts),
+            commit_lock: Mutex::new(()),
+            commit_cond: Condvar::new(),
+            watermark: Mutex::new(Watermark::new()),
+            committed_txns: Mutex::new(BTreeMap::new()),
+            options,
+        })
+    }
+
+    fn create_memtable(
+        path: &Path,
+        options: &LsmStorageOptions,
+        id: usize,
+    ) -> Result<Arc<MemTable>> {
+        let memtable = if options.enable_wal {
+            let path = Self::path_of_wal_static(path, id);
+            MemTable::create_with_wal(id, path, options.wal_sync_mode)?
+        } else {
+            MemTable::create(id)
+        };
+        Ok(Arc::new(memtable))
+    }
+
+    fn snapshot(&self) -> Arc<LsmStorageState> {
+        Arc::clone(&self.state.read())
+    }
+
+    /// Get a value by key. Returns `None` if the key does not exist or has been deleted.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        let read_ts = self.acquire_read_ts();
+        let result = self.get_at(key, read_ts);
+        self.release_read_ts(read_ts);
+        result
+    }
+
+    /// Get the newest version of a key at or below `read_ts`. A `read_ts` above the latest commit
+    /// timestamp reads at it, so that a batch being written is never seen in part.
+    pub fn get_at(&self, key: &[u8], read_ts: u64) -> Result<Option<Bytes>> {
+        let read_ts = read_ts.min(self.latest_commit_ts());
+        let snapshot = self.snapshot();
+        let internal_key = KeySlice::from_slice(key, read_ts);
+        let memtables = || std::iter::once(&snapshot.memtable).chain(&snapshot.imm_memtables);
+        let sst_tombstones = snapshot
+            .sst_ids()
+            .map(|id| snapshot.sstables[id].range_tombstones());
+        // versions older than this are deleted by a range tombstone
+        let deleted_before = memtables()
+            .map(|memtable| memtable.max_covering_ts(key, read_ts))
+            .chain(sst_tombstones.map(|tombstones| max_covering_ts(tombstones, key, read_ts)))
+            .max()
+            .unwrap_or(TS_MIN);
+
+        for memtable in memtables() {
+            if let Some((ts, value)) = memtable.get_with_ts(internal_key) {
+                return Ok(value.filter(|_| ts >= deleted_before));
+            }
+        }
+
+        for id in snapshot.sst_ids() {
+            let table = Arc::clone(&snapshot.sstables[id]);
+            if !table.may_contain(key) {
+                continue;
+            }
+            let iter = SSTableIterator::create_and_seek_to_key(table, internal_key)?;
+            if iter.is_valid() && iter.key().key_ref() == key {
+                if iter.is_deleted() || iter.key().ts() < deleted_before {
+                    return Ok(None);
+                }
+                return Ok(Some(Bytes::copy_from_slice(iter.value())));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Collect the range tombstones of the mem-tables and the SSTs at or below `read_ts`.
+    fn range_tombstones(snapshot: &LsmStorageState, read_ts: u64) -> Vec<RangeTombstone> {
+        let memtables = std::iter::once(&snapshot.memtable).chain(snapshot.imm_memtables.iter());
+        let mut range_tombstones = Vec::new();
+        for memtable in memtables {
+            range_tombstones.extend(memtable.range_tombstones());
+        }
+        for id in snapshot.sst_ids() {
+            range_tombstones.extend_from_slice(snapshot.sstables[id].range_tombstones());
+        }
+        range_tombstones.retain(|tombstone| tombstone.ts() <= read_ts);
+        range_tombstones
+    }
+
+    /// Put a key-value pair into the storage engine.
+    pub fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.write(1, |memtable, ts| {
+            memtable.put(KeySlice::from_slice(key, ts), value)
+        })?;
+        Ok(())
+    }
+
+    /// Delete a key from the storage engine by writing a tombstone.
+    pub fn delete(&self, key: &[u8]) -> Result<()> {
+        self.write(1, |memtable, ts| {
+            memtable.delete(KeySlice::from_slice(key, ts))
+        })?;
+        Ok(())
+    }
+
+    /// Delete the keys in `[start, end)` by writing a range tombstone.
+    pub fn delete_range(&self, start: &[u8], end: &[u8]) -> Result<()> {
+        if start > end {
+            bail!("invalid range: start {:?} is after end {:?}", start, end);
+        }
+        if start == end {
+            return Ok(());
+        }
+        self.write(1, |memtable, ts| {
+            let (start, end) = (Bytes::copy_from_slice(start), Bytes::copy_from_slice(end));
+            memtable.delete_range(RangeTombstone::new(start, end, ts))
+        })?;
+        Ok(())
+    }
+
+    /// Apply the writes of a batch atomically. Returns the timestamp of the last write, or the
+    /// latest commit timestamp if the batch is empty.
+    pub(crate) fn write_batch(&self, batch: &WriteBatch) -> Result<u64> {
+        if batch.is_empty() {
+            return Ok(self.latest_commit_ts());
+        }
+        self.write(batch.len() as u64, |memtable, ts| {
+            memtable.write_batch(batch, ts)
+        })
+    }
+
+    /// Write to the current mem-table at the next `count` timestamps, then publish them. `write`
+    /// gets the first timestamp. Returns the last timestamp.
+    fn write<F>(&self, count: u64, write: F) -> Result<u64>
+    where
+        F: FnOnce(&MemTable, u64) -> Result<()>,
+    {
+        self.stall_write_if_needed();
+        let (ts, size, result) = {
+            // The mem-table cannot be frozen while the write holds the state.
+            let state = self.state.read();
+            let first_ts = self.next_ts.fetch_add(count, Ordering::SeqCst) + 1;
+            let result = write(&state.memtable, first_ts);
+            // A failed write is published too, or the following writes would never be.
+            let last_ts = first_ts + count - 1;
+            self.publish(first_ts, last_ts);
+            (last_ts, state.memtable.estimated_size(), result)
+        };
+        result?;
+        self.notify_flush_if_full(size);
+        Ok(ts)
+    }
+
+    /// Make the writes from `first_ts` to `last_ts` visible together, once every write with a
+    /// smaller timestamp is visible.
+    fn publish(&self, first_ts: u64, last_ts: u64) {
+        let mut guard = self.commit_lock.lock();
+        while self.commit_ts.load(Ordering::SeqCst) + 1 != first_ts {
+            self.commit_cond.wait(&mut guard);
+        }
+        self.commit_ts.store(last_ts, Ordering::SeqCst);
+        self.commit_cond.notify_all();
+    }
+
+    /// Get the timestamp of the latest write. Every write at or below it is visible.
+    pub fn latest_commit_ts(&self) -> u64 {
+        self.commit_ts.load(Ordering::SeqCst)
+    }
+
+    /// Register a reader at the latest commit timestamp, its versions are kept until
+    /// `release_read_ts` is called.
+    pub(crate) fn acquire_read_ts(&self) -> u64 {
+        let mut watermark = self.watermark.lock();
+        let read_ts = self.latest_commit_ts();
+        watermark.add_reader(read_ts);
+        read_ts
+    }
+
+    /// Unregister a reader registered by `acquire_read_ts`.
+    pub(crate) fn release_read_ts(&self, read_ts: u64) {
+        self.watermark.lock().remove_reader(read_ts);
+    }
+
+    /// Get the timestamp below which only the newest version of a key is visible: the lowest
+    /// read timestamp of the live readers, or the latest commit timestamp without readers.
+    pub(crate) fn gc_watermark(&self) -> u64 {
+        let watermark = self.watermark.lock();
+        watermark
+            .watermark()
+            .unwrap_or_else(|| self.latest_commit_ts())
+    }
+
+    /// Slow down or stop a write while the background jobs fall behind, and record the stall in
+    /// the statistics.
+    fn stall_write_if_needed(&self) {
+        if !self.options.enable_background_jobs {
+            return;
+        }
+        let reason = match self.write_stall_reason() {
+            Some(reason) => reason,
+            None => return,
+        };
+
+        let start = Instant::now();
+        _ = self.flush_notifier.try_send(());
+        _ = self.compaction_notifier.try_send(());
+        if reason.is_stop() {
+            let mut guard = self.stall_lock.lock();
+            while self.write_stall_reason().is_some_and(|x| x.is_stop()) {
+                // Time out in case the notification is sent before waiting.
+                self.stall_cond.wait_for(&mut guard, BACKGROUND_TICK);
+            }
+        } else {
+            std::thread::sleep(WRITE_SLOWDOWN_DELAY);
+        }
+
+        let mut stats = self.stats.lock();
+        let stall = stats.write_stalls.entry(reason).or_default();
+        stall.count += 1;
+        stall.duration += start.elapsed();
+    }
+
+    fn write_stall_reason(&self) -> Option<WriteStallReason> {
+        let snapshot = self.snapshot();
+        let num_imm_memtables = snapshot.imm_memtables.len();
+        // Nothing shrinks L0 without compaction.
+        let num_l0_sstables = match self.compaction_controller {
+            Some(_) => snapshot.l0_sstables.len(),
+            None => 0,
+        };
+        if num_imm_memtables >= self.options.imm_memtable_stop_trigger {
+            Some(WriteStallReason::ImmMemtableStop)
+        } else if num_l0_sstables >= self.options.level0_stop_writes_trigger {
+            Some(WriteStallReason::Level0Stop)
+        } else if num_imm_memtables >= self.options.imm_memtable_slowdown_trigger {
+            Some(WriteStallReason::ImmMemtableSlowdown)
+        } else if num_l0_sstables >= self.options.level0_slowdown_writes_trigger {
+            Some(WriteStallReason::Level0Slowdown)
+        } else {
+            None
+        }
+    }
+
+    /// Wake up the flush thread once the current mem-table reaches `target_sst_size`, without
+    /// waiting for it.
+    fn notify_flush_if_full(&self, memtable_size: usize) {
+        if memtable_size >= self.options.target_sst_size {
+            _ = self.flush_notifier.try_send(());
+        }
+    }
+
+    /// Make the writes to the current mem-table durable.
+    pub fn sync(&self) -> Result<()> {
+        self.state.read().memtable.sync_wal()
+    }
+
+    /// Create an iterator over a range of keys. Newer sources take precedence over older ones.
+    pub fn scan(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<LsmIterator> {
+        // The iterator holds the SSTs it reads, so the read timestamp is only needed until it is
+        // created.
+        let read_ts = self.acquire_read_ts();
+        let result = self.scan_at(lower, upper, read_ts);
+        self.release_read_ts(read_ts);
+        result
+    }
+
+    /// Create an iterator over a range of keys, showing the newest version of each key at or
+    /// below `read_ts`. A `read_ts` above the latest commit timestamp reads at it.
+    pub fn scan_at(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        read_ts: u64,
+    ) -> Result<LsmIterator> {
+        let read_ts = read_ts.min(self.latest_commit_ts());
+        let snapshot = self.snapshot();
+
+        let mut memtable_iters = Vec::new();
+        let memtables = std::iter::once(&snapshot.memtable).chain(snapshot.imm_memtables.iter());
+        for memtable in memtables {
+            memtable_iters.push(Box::new(
+                memtable.scan(lower_key_bound(lower), upper_key_bound(upper)),
+            ));
+        }
+
+        // The range tombstones are collected from every table, the ones outside of the range too.
+        let mut sst_iters = Vec::new();
+        for id in snapshot.sst_ids() {
+            let table = Arc::clone(&snapshot.sstables[id]);
+            if !table.range_overlap(lower, upper) {
+                continue;
+            }
+            let iter = match lower_key_bound(lower) {
+                Bound::Included(key) => SSTableIterator::create_and_seek_to_key(table, key)?,
+                Bound::Excluded(key) => {
+                    let mut iter = SSTableIterator::create_and_seek_to_key(table, key)?;
+                    while iter.is_valid() && iter.key().key_ref() == key.key_ref() {
+                        iter.next()?;
+                    }
+                    iter
+                }
+                Bound::Unbounded => SSTableIterator::create_and_seek_to_first(table)?,
+            };
+            sst_iters.push(Box::new(BoundedIterator::new(iter, map_bound(upper))));
+        }
+
+        let iter = TwoMergeIterator::create(
+            MergeIterator::create(memtable_iters),
+            MergeIterator::create(sst_iters),
+        )?;
+        LsmIterator::new(
+            iter,
+            map_bound(lower),
+            map_bound(upper),
+            read_ts,
+            Self::range_tombstones(&snapshot, read_ts),
+        )
+    }
+
+    /// Freeze the current mem-table and move it to the immutable mem-tables.
+    pub fn force_freeze_memtable(&self) -> Result<()> {
+        let _state_lock = self.state_lock.lock();
+        self.freeze_memtable()
+    }
+
+    /// Freeze the current mem-table, the caller holds `state_lock`.
+    fn freeze_memtable(&self) -> Result<()> {
+        let id = self.next_sst_id();
+        self.manifest
+            .add_edit(vec![ManifestRecord::NewMemtable(id)])?;
+        let new_memtable = Self::create_memtable(&self.path, &self.options, id)?;
+
+        let mut guard = self.state.write();
+        let mut snapshot = guard.as_ref().clone();
+        let memtable = std::mem::replace(&mut snapshot.memtable, new_memtable);
+        snapshot.imm_memtables.insert(0, memtable);
+        *guard = Arc::new(snapshot);
+
+        Ok(())
+    }
+
+    /// Flush the oldest immutable mem-table to an SST with the same id as the mem-table, record it
+    /// in the manifest, then remove the WAL. The SST is added to L0, or to a new tier with tiered
+    /// compaction. Does nothing if there is no immutable mem-table.
+    pub fn force_flush_next_imm_memtable(&self) -> Result<()> {
+        let _state_lock = self.state_lock.lock();
+
+        let memtable = match self.snapshot().imm_memtables.last() {
+            Some(memtable) => Arc::clone(memtable),
+            None => return Ok(()),
+        };
+
+        let sst = if memtable.is_empty() {
+            None
+        } else {
+            let mut builder = self.sst_builder();
+            memtable.flush(&mut builder)?;
+            Some(self.build_sst(builder, memtable.id())?)
+        };
+
+        let level = self
+            .compaction_controller
+            .as_ref()
+            .map_or(0, |controller| controller.flush_level(memtable.id()));
+        let mut records = Vec::new();
+        if let Some(sst) = &sst {
+            self.sync_dir()?;
+            records.push(ManifestRecord::AddSst {
+                level,
+                sst_id: sst.sst_id(),
+            });
+        }
+        records.push(ManifestRecord::FlushDone(memtable.id()));
+        self.manifest.add_edit(records)?;
+
+        let mut guard = self.state.write();
+        let mut snapshot = guard.as_ref().clone();
+        snapshot.imm_memtables.pop();
+        if let Some(sst) = sst {
+            snapshot.add_ssts(level, &[sst]);
+        }
+        *guard = Arc::new(snapshot);
+        drop(guard);
+        self.stall_cond.notify_all();
+
+        if self.options.enable_wal {
+            std::fs::remove_file(self.path_of_wal(memtable.id()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Flush all immutable mem-tables, from the oldest to the newest.
+    fn flush_imm_memtables(&self) -> Result<bool> {
+        let mut flushed = false;
+        while !self.snapshot().imm_memtables.is_empty() {
+            self.force_flush_next_imm_memtable()?;
+            flushed = true;
+        }
+        Ok(flushed)
+    }
+
+    /// Freeze the current mem-table if it reached `target_sst_size`, then flush the immutable
+    /// mem-tables and wake up the compaction thread.
+    fn trigger_flush(&self) -> Result<()> {
+        if self.snapshot().memtable.estimated_size() >= self.options.target_sst_size {
+            let _state_lock = self.state_lock.lock();
+            if self.snapshot().memtable.estimated_size() >= self.options.target_sst_size {
+                self.freeze_memtable()?;
+            }
+        }
+        if self.flush_imm_memtables()? {
+            _ = self.compaction_notifier.try_send(());
+        }
+        Ok(())
+    }
+
+    /// Run a compaction if the compaction strategy finds one necessary. The output SSTs replace
+    /// the input SSTs in the manifest and in the state, then the input files are removed. Returns
+    /// false if there is nothing to compact.
+    pub fn trigger_compaction(&self) -> Result<bool> {
+        let _compaction_lock = self.compaction_lock.lock();
+
+        let controller = match &self.compaction_controller {
+            Some(controller) => controller,
+            None => return Ok(false),
+        };
+        let snapshot = self.snapshot();
+        let task = match controller.generate_compaction_task(&snapshot) {
+            Some(task) => task,
+            None => return Ok(false),
+        };
+        if task.output_level == 0 {
+            bail!("compaction output cannot be added to L0");
+        }
+        for (level, sst_id) in task.input_ssts() {
+            if !snapshot.level(level).contains(&sst_id) {
+                bail!("compaction input SST {} is not in level {}", sst_id, level);
+            }
+        }
+
+        let output = self.compact(&snapshot, &task)?;
+
+        let _state_lock = self.state_lock.lock();
+
+        self.sync_dir()?;
+        let mut records = Vec::new();
+        for (level, sst_id) in task.input_ssts() {
+            records.push(ManifestRecord::RemoveSst { level, sst_id });
+        }
+        for sst in &output {
+            records.push(ManifestRecord::AddSst {
+                level: task.output_level,
+                sst_id: sst.sst_id(),
+            });
+        }
+        records.push(ManifestRecord::CompactionDone);
+        self.manifest.add_edit(records)?;
+
+        let mut guard = self.state.write();
+        let mut snapshot = guard.as_ref().clone();
+        snapshot.apply_compaction_result(&task, &output);
+        *guard = Arc::new(snapshot);
+        drop(guard);
+        self.stall_cond.notify_all();
+
+        for (_, sst_id) in task.input_ssts() {
+            std::fs::remove_file(self.path_of_sst(sst_id))?;
+        }
+
+        Ok(true)
+    }
+
+    /// Merge the input SSTs of a compaction into new SSTs of about `target_sst_size`. The versions
+    /// of a key are never split across SSTs. The versions above the GC watermark are kept, below
+    /// it only the newest one is. At the bottom level, tombstones older than every other version
+    /// of their key are dropped.
+    fn compact(
+        &self,
+        snapshot: &LsmStorageState,
+        task: &CompactionTask,
+    ) -> Result<Vec<Arc<SSTable>>> {
+        let mut iters = Vec::new();
+        let mut range_tombstones = Vec::new();
+        for (_, sst_id) in task.input_ssts() {
+            let table = Arc::clone(&snapshot.sstables[&sst_id]);
+            range_tombstones.extend_from_slice(table.range_tombstones());
+            iters.push(Box::new(SSTableIterator::create_and_seek_to_first(table)?));
+        }
+        let mut iter = MergeIterator::<SSTableIterator>::create(iters);
+        let watermark = self.gc_watermark();
+        // range tombstones at or below the watermark have nothing left to hide at the bottom level
+        let kept_tombstones: Vec<_> = range_tombstones
+            .iter()
+            .filter(|tombstone| !task.compact_to_bottom_level || tombstone.ts() > watermark)
+            .cloned()
+            .collect();
+
+        let mut output = Vec::new();
+        let mut builder = None;
+        // lower bound of the key range of the SST being built
+        let mut lower: Option<Bytes> = None;
+        // versions of the current key, from the newest to the oldest, `None` for tombstones
+        let mut versions: Vec<(KeyVec, Option<Vec<u8>>)> = Vec::new();
+        loop {
+            let key_done = versions
+                .first()
+                .is_some_and(|(key, _)| !iter.is_valid() || iter.key().key_ref() != key.key_ref());
+            if key_done {
+                let user_key = Bytes::copy_from_slice(versions[0].0.key_ref());
+                let deleted_before = max_covering_ts(&range_tombstones, &user_key, watermark);
+                if let Some(pos) = versions.iter().position(|(key, _)| key.ts() <= watermark) {
+                    if versions[pos].0.ts() < deleted_before {
+                        versions.truncate(pos);
+                    } else {
+                        versions.truncate(pos + 1);
+                    }
+                }
+                if task.compact_to_bottom_level {
+                    while versions.last().is_some_and(|(_, value)| value.is_none()) {
+                        versions.pop();
+                    }
+                }
+                if !versions.is_empty() {
+                    let inner = builder.get_or_insert_with(|| self.sst_builder());
+                    for (key, value) in versions.drain(..) {
+                        match value {
+                            Some(value) => inner.add(key.as_key_slice(), &value),
+                            None => inner.add_tombstone(key.as_key_slice()),
+                        }
+                    }
+                    if inner.estimated_size() >= self.options.target_sst_size {
+                        // the SST covers the keys up to and including the current key
+                        let upper = Bytes::from([&user_key[..], &[0]].concat());
+                        let mut inner = builder.take().unwrap();
+                        for tombstone in &kept_tombstones {
+                            if let Some(tombstone) = tombstone.clip(lower.as_ref(), Some(&upper)) {
+                                inner.add_range_tombstone(tombstone);
+                            }
+                        }
+                        let id = self.next_sst_id();
+                        output.push(self.build_sst(inner, id)?);
+                        lower = Some(upper);
+                    }
+                }
+            }
+            if !iter.is_valid() {
+                break;
+            }
+
+            let value = (!iter.is_deleted()).then(|| iter.value().to_vec());
+            versions.push((iter.key().to_key_vec(), value));
+            iter.next()?;
+        }
+        let mut builder = builder.unwrap_or_else(|| self.sst_builder());
+        for tombstone in &kept_tombstones {
+            if let Some(tombstone) = tombstone.clip(lower.as_ref(), None) {
+                builder.add_range_tombstone(tombstone);
+            }
+        }
+        if !builder.is_empty() {
+            let id = self.next_sst_id();
+            output.push(self.build_sst(builder, id)?);
+        }
+
+        Ok(output)
+    }
+
+    fn sst_builder(&self) -> SSTableBuilder {
+        SSTableBuilder::new(self.options.block_size)
+            .bloom_bits_per_key(self.options.bloom_bits_per_key)
+            .compression(self.options.compression)
+    }
+
+    fn build_sst(&self, builder: SSTableBuilder, id: usize) -> Result<Arc<SSTable>> {
+        let sst = builder.build(
+            id,
+            Some(Arc::clone(&self.block_cache)),
+            self.path_of_sst(id),
+        )?;
+        Ok(Arc::new(sst))
+    }
+
+    fn sync_dir(&self) -> Result<()> {
+        std::fs::File::open(&self.path)?.sync_all()?;
+        Ok(())
+    }
+
+    fn next_sst_id(&self) -> usize {
+        self.next_sst_id.fetch_add(1, Ordering::SeqCst)
+    }
+
+    fn path_of_sst_static(path: &Path, id: usize) -> PathBuf {
+        path.join(format!("{:05}.sst", id))
+    }
+
+    fn path_of_sst(&self, id: usize) -> PathBuf {
+        Self::path_of_sst_static(&self.path, id)
+    }
+
+    fn path_of_wal_static(path: &Path, id: usize) -> PathBuf {
+        path.join(format!("{:05}.wal", id))
+    }
+
+    fn path_of_wal(&self, id: usize) -> PathBuf {
+        Self::path_of_wal_static(&self.path, id)
+    }
+}
+
+#[cfg(test)]
+mod tests;
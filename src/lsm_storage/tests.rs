@@ -0,0 +1,1231 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    ops::Bound,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+
+use super::{LsmStorage, LsmStorageOptions, LsmStorageState, WriteStallReason};
+use crate::compact::{
+    CompactionController, CompactionOptions, CompactionTask, LeveledCompactionOptions,
+    SimpleLeveledCompactionOptions, TieredCompactionOptions,
+};
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::lsm_iterator::LsmIterator;
+use crate::mvcc::Snapshot;
+use crate::sstable::iterator::SSTableIterator;
+use crate::wal::WalSyncMode;
+use crate::write_batch::WriteBatch;
+
+fn key_of(val: usize) -> Vec<u8> {
+    format!("key_{:05}", val).into_bytes()
+}
+
+fn value_of(val: usize) -> Vec<u8> {
+    format!("val_{:010}", val).into_bytes()
+}
+
+fn as_bytes(x: &[u8]) -> Bytes {
+    Bytes::copy_from_slice(x)
+}
+
+fn test_options() -> LsmStorageOptions {
+    LsmStorageOptions {
+        block_size: 128,
+        wal_sync_mode: WalSyncMode::NoSync,
+        ..Default::default()
+    }
+}
+
+fn storage_test<T>(name: &str, test: T)
+where
+    T: Fn(&LsmStorage),
+{
+    let path = format!("./tmp/{name}");
+    _ = fs::remove_dir_all(&path);
+
+    let storage = LsmStorage::open(&path, test_options()).unwrap();
+
+    test(&storage);
+
+    drop(storage);
+    fs::remove_dir_all(&path).unwrap();
+}
+
+fn check_scan<I: StorageIterator>(mut iter: I, expected: &[(Vec<u8>, Vec<u8>)]) {
+    for (key, value) in expected {
+        assert!(iter.is_valid(), "expected key: {:?}", as_bytes(key));
+        assert_eq!(
+            iter.key().key_ref(),
+            &key[..],
+            "expected key: {:?}, actual key: {:?}",
+            as_bytes(key),
+            as_bytes(iter.key().key_ref())
+        );
+        assert_eq!(
+            iter.value(),
+            &value[..],
+            "expected value: {:?}, actual value: {:?}",
+            as_bytes(value),
+            as_bytes(iter.value())
+        );
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_storage_get_put_delete() {
+    storage_test("storage-get-put-delete", |storage| {
+        for i in 0..100 {
+            storage.put(&key_of(i), &value_of(i)).unwrap();
+        }
+        for i in 0..100 {
+            assert_eq!(storage.get(&key_of(i)).unwrap().unwrap(), value_of(i));
+        }
+
+        for i in 0..50 {
+            storage.delete(&key_of(i)).unwrap();
+        }
+        for i in 0..100 {
+            let value = storage.get(&key_of(i)).unwrap();
+            if i < 50 {
+                assert!(value.is_none(), "{i}");
+            } else {
+                assert_eq!(value.unwrap(), value_of(i));
+            }
+        }
+
+        assert!(storage.get(b"missing").unwrap().is_none());
+
+        storage.put(b"empty", b"").unwrap();
+        assert_eq!(storage.get(b"empty").unwrap().unwrap(), Bytes::new());
+    });
+}
+
+#[test]
+fn test_storage_scan() {
+    storage_test("storage-scan", |storage| {
+        for i in 0..100 {
+            storage.put(&key_of(i), &value_of(i)).unwrap();
+        }
+        for i in (0..100).step_by(3) {
+            storage.delete(&key_of(i)).unwrap();
+        }
+
+        let expected = (0..100)
+            .filter(|i| i % 3 != 0)
+            .map(|i| (key_of(i), value_of(i)))
+            .collect::<Vec<_>>();
+        check_scan(
+            storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+            &expected,
+        );
+
+        let expected = (10..40)
+            .filter(|i| i % 3 != 0)
+            .map(|i| (key_of(i), value_of(i)))
+            .collect::<Vec<_>>();
+        check_scan(
+            storage
+                .scan(Bound::Included(&key_of(10)), Bound::Excluded(&key_of(40)))
+                .unwrap(),
+            &expected,
+        );
+    });
+}
+
+#[test]
+fn test_storage_scan_seek() {
+    storage_test("storage-scan-seek", |storage| {
+        for i in 0..100 {
+            storage.put(&key_of(i), &value_of(i)).unwrap();
+            if i % 10 == 9 {
+                storage.force_freeze_memtable().unwrap();
+                storage.force_flush_next_imm_memtable().unwrap();
+            }
+        }
+        for i in (0..100).step_by(3) {
+            storage.put(&key_of(i), &value_of(i + 1000)).unwrap();
+        }
+        for i in (0..100).step_by(5) {
+            storage.delete(&key_of(i)).unwrap();
+        }
+        let expected = |from: usize, to: usize| {
+            (from..to)
+                .filter(|i| i % 5 != 0)
+                .map(|i| (key_of(i), value_of(if i % 3 == 0 { i + 1000 } else { i })))
+                .collect::<Vec<_>>()
+        };
+
+        let mut iter = storage
+            .scan(Bound::Excluded(&key_of(21)), Bound::Excluded(&key_of(80)))
+            .unwrap();
+        // a deleted key moves to the next visible one, a key before the range to its start
+        for (seek, from) in [(62, 62), (60, 61), (10, 22), (21, 22), (22, 22)] {
+            iter.seek(KeySlice::from_slice(&key_of(seek), 0)).unwrap();
+            assert_eq!(iter.key().key_ref(), key_of(from), "{seek}");
+        }
+        iter.seek(KeySlice::from_slice(&key_of(33), 0)).unwrap();
+        check_scan(iter, &expected(33, 80));
+
+        let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+        iter.seek(KeySlice::from_slice(&key_of(100), 0)).unwrap();
+        assert!(!iter.is_valid());
+        iter.seek_to_first().unwrap();
+        check_scan(iter, &expected(0, 100));
+    });
+}
+
+#[test]
+fn test_storage_scan_reverse() {
+    storage_test("storage-scan-reverse", |storage| {
+        for i in 0..100 {
+            storage.put(&key_of(i), &value_of(i)).unwrap();
+            if i % 10 == 9 {
+                storage.force_freeze_memtable().unwrap();
+                storage.force_flush_next_imm_memtable().unwrap();
+            }
+        }
+        let snapshot = storage.snapshot();
+        for i in (0..100).step_by(3) {
+            storage.put(&key_of(i), &value_of(i + 1000)).unwrap();
+        }
+        storage.force_freeze_memtable().unwrap();
+        for i in (0..100).step_by(5) {
+            storage.delete(&key_of(i)).unwrap();
+        }
+        storage.delete_range(&key_of(70), &key_of(75)).unwrap();
+        let deleted = |i: usize| i.is_multiple_of(5) || (70..75).contains(&i);
+        let expected = |from: usize, to: usize| {
+            (from..to)
+                .rev()
+                .filter(|i| !deleted(*i))
+                .map(|i| (key_of(i), value_of(if i % 3 == 0 { i + 1000 } else { i })))
+                .collect::<Vec<_>>()
+        };
+        let collect_reverse = |mut iter: LsmIterator| {
+            let mut result = Vec::new();
+            iter.seek_to_last().unwrap();
+            while iter.is_valid() {
+                result.push((iter.key().key_ref().to_vec(), iter.value().to_vec()));
+                iter.prev().unwrap();
+            }
+            result
+        };
+
+        let iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+        assert_eq!(collect_reverse(iter), expected(0, 100));
+        let iter = storage
+            .scan(Bound::Excluded(&key_of(21)), Bound::Included(&key_of(76)))
+            .unwrap();
+        assert_eq!(collect_reverse(iter), expected(22, 77));
+        let iter = snapshot.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+        let all = (0..100).rev().map(|i| (key_of(i), value_of(i)));
+        assert_eq!(collect_reverse(iter), all.collect::<Vec<_>>());
+
+        // the latest keys before a bound
+        let mut iter = storage
+            .scan(Bound::Unbounded, Bound::Excluded(&key_of(78)))
+            .unwrap();
+        iter.seek_to_last().unwrap();
+        let mut latest = Vec::new();
+        for _ in 0..3 {
+            latest.push(iter.key().key_ref().to_vec());
+            iter.prev().unwrap();
+        }
+        assert_eq!(latest, vec![key_of(77), key_of(76), key_of(69)]);
+
+        // changing direction
+        iter.seek_for_prev(KeySlice::from_slice(&key_of(60), 0))
+            .unwrap();
+        let moves = [(true, 61), (false, 59), (false, 58), (true, 59), (true, 61)];
+        for (forward, i) in moves {
+            if forward {
+                iter.next().unwrap();
+            } else {
+                iter.prev().unwrap();
+            }
+            assert_eq!(iter.key().key_ref(), key_of(i), "{i}");
+        }
+        iter.seek_for_prev(KeySlice::from_slice(&key_of(0), 0))
+            .unwrap();
+        assert!(!iter.is_valid());
+
+        // an iterator that ran off an end must be repositioned by a seek
+        assert!(iter.next().is_err());
+        assert!(iter.prev().is_err());
+        let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+        while iter.is_valid() {
+            iter.next().unwrap();
+        }
+        assert!(iter.prev().is_err());
+        iter.seek_to_last().unwrap();
+        assert_eq!(iter.key().key_ref(), key_of(99));
+    });
+}
+
+#[test]
+fn test_storage_flush() {
+    storage_test("storage-flush", |storage| {
+        for i in 0..100 {
+            storage.put(&key_of(i), &value_of(i)).unwrap();
+        }
+        storage.force_freeze_memtable().unwrap();
+        storage.force_flush_next_imm_memtable().unwrap();
+
+        for i in 0..50 {
+            storage.put(&key_of(i), &value_of(i + 1000)).unwrap();
+        }
+        storage.force_freeze_memtable().unwrap();
+
+        for i in 0..25 {
+            storage.delete(&key_of(i)).unwrap();
+        }
+        storage.put(&key_of(100), b"").unwrap();
+
+        for i in 0..100 {
+            let value = storage.get(&key_of(i)).unwrap();
+            match i {
+                0..=24 => assert!(value.is_none(), "{i}"),
+                25..=49 => assert_eq!(value.unwrap(), value_of(i + 1000)),
+                _ => assert_eq!(value.unwrap(), value_of(i)),
+            }
+        }
+
+        storage.force_flush_next_imm_memtable().unwrap();
+        storage.force_freeze_memtable().unwrap();
+        storage.force_flush_next_imm_memtable().unwrap();
+
+        let expected = (25..101)
+            .map(|i| {
+                if i < 50 {
+                    (key_of(i), value_of(i + 1000))
+                } else if i == 100 {
+                    (key_of(i), vec![])
+                } else {
+                    (key_of(i), value_of(i))
+                }
+            })
+            .collect::<Vec<_>>();
+        check_scan(
+            storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+            &expected,
+        );
+
+        check_scan(
+            storage
+                .scan(Bound::Excluded(&key_of(48)), Bound::Included(&key_of(51)))
+                .unwrap(),
+            &expected[24..27],
+        );
+    });
+}
+
+#[test]
+fn test_storage_recover_from_wal() {
+    let path = "./tmp/storage-recover-from-wal";
+    _ = fs::remove_dir_all(path);
+
+    {
+        let storage = LsmStorage::open(path, test_options()).unwrap();
+        for i in 0..100 {
+            storage.put(&key_of(i), &value_of(i)).unwrap();
+        }
+        storage.force_freeze_memtable().unwrap();
+        for i in 0..50 {
+            storage.delete(&key_of(i)).unwrap();
+        }
+        storage.sync().unwrap();
+    }
+
+    {
+        let storage = LsmStorage::open(path, test_options()).unwrap();
+        for i in 0..100 {
+            let value = storage.get(&key_of(i)).unwrap();
+            if i < 50 {
+                assert!(value.is_none(), "{i}");
+            } else {
+                assert_eq!(value.unwrap(), value_of(i));
+            }
+        }
+        storage.put(&key_of(0), &value_of(0)).unwrap();
+    }
+
+    {
+        let storage = LsmStorage::open(path, test_options()).unwrap();
+        assert_eq!(storage.get(&key_of(0)).unwrap().unwrap(), value_of(0));
+        assert!(storage.get(&key_of(1)).unwrap().is_none());
+    }
+
+    fs::remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn test_storage_reopen() {
+    let path = "./tmp/storage-reopen";
+    _ = fs::remove_dir_all(path);
+
+    let expected = |storage: &LsmStorage| {
+        for i in 0..300 {
+            let value = storage.get(&key_of(i)).unwrap();
+            match i {
+                0..=49 => assert!(value.is_none(), "{i}"),
+                50..=99 => assert_eq!(value.unwrap(), value_of(i + 1000)),
+                _ => assert_eq!(value.unwrap(), value_of(i)),
+            }
+        }
+    };
+
+    {
+        let storage = LsmStorage::open(path, test_options()).unwrap();
+        for i in 0..300 {
+            storage.put(&key_of(i), &value_of(i)).unwrap();
+        }
+        storage.force_freeze_memtable().unwrap();
+        storage.force_flush_next_imm_memtable().unwrap();
+        for i in 50..100 {
+            storage.put(&key_of(i), &value_of(i + 1000)).unwrap();
+        }
+        storage.force_freeze_memtable().unwrap();
+        storage.force_flush_next_imm_memtable().unwrap();
+        for i in 0..50 {
+            storage.delete(&key_of(i)).unwrap();
+        }
+        storage.sync().unwrap();
+    }
+
+    {
+        let storage = LsmStorage::open(path, test_options()).unwrap();
+        expected(&storage);
+        storage.force_flush_next_imm_memtable().unwrap();
+        storage.force_flush_next_imm_memtable().unwrap();
+    }
+
+    {
+        let storage = LsmStorage::open(path, test_options()).unwrap();
+        expected(&storage);
+        assert_eq!(storage.inner.snapshot().l0_sstables.len(), 3);
+    }
+
+    fs::remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn test_storage_close_without_background_jobs() {
+    let path = "./tmp/storage-close-without-background-jobs";
+    _ = fs::remove_dir_all(path);
+
+    // without a WAL, only the flush on close keeps the current mem-table
+    let options = || LsmStorageOptions {
+        enable_wal: false,
+        enable_background_jobs: false,
+        ..test_options()
+    };
+    {
+        let storage = LsmStorage::open(path, options()).unwrap();
+        storage.put(b"a", b"1").unwrap();
+        storage.close().unwrap();
+    }
+    {
+        let storage = LsmStorage::open(path, options()).unwrap();
+        assert_eq!(storage.get(b"a").unwrap().unwrap(), "1");
+        storage.put(b"b", b"2").unwrap();
+    }
+    {
+        let storage = LsmStorage::open(path, options()).unwrap();
+        assert_eq!(storage.get(b"a").unwrap().unwrap(), "1");
+        assert_eq!(storage.get(b"b").unwrap().unwrap(), "2");
+    }
+
+    fs::remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn test_storage_mvcc() {
+    let path = "./tmp/storage-mvcc";
+    _ = fs::remove_dir_all(path);
+
+    // `value_of(i + round * 1000)` is written in each round, keys 0..50 are deleted in round 2
+    let expected = |round: usize, i: usize| match round {
+        0 => None,
+        2 if i < 50 => None,
+        _ => Some(value_of(i + round * 1000)),
+    };
+    let check = |storage: &LsmStorage, read_ts: &[u64]| {
+        for (round, ts) in read_ts.iter().enumerate() {
+            for i in 0..100 {
+                let value = storage.get_at(&key_of(i), *ts).unwrap();
+                assert_eq!(value.map(|x| x.to_vec()), expected(round, i), "{round} {i}");
+            }
+            let scanned = (0..100)
+                .filter_map(|i| Some((key_of(i), expected(round, i)?)))
+                .collect::<Vec<_>>();
+            check_scan(
+                storage
+                    .scan_at(Bound::Unbounded, Bound::Unbounded, *ts)
+                    .unwrap(),
+                &scanned,
+            );
+            check_scan(
+                storage
+                    .scan_at(
+                        Bound::Excluded(&key_of(49)),
+                        Bound::Included(&key_of(60)),
+                        *ts,
+                    )
+                    .unwrap(),
+                &scanned
+                    .iter()
+                    .filter(|(key, _)| key > &key_of(49) && key <= &key_of(60))
+                    .cloned()
+                    .collect::<Vec<_>>(),
+            );
+        }
+    };
+
+    let mut read_ts = Vec::new();
+    {
+        let storage = LsmStorage::open(path, test_options()).unwrap();
+        read_ts.push(storage.latest_commit_ts());
+        for round in 1..=2 {
+            for i in 0..100 {
+                storage
+                    .put(&key_of(i), &value_of(i + round * 1000))
+                    .unwrap();
+            }
+            if round == 2 {
+                for i in 0..50 {
+                    storage.delete(&key_of(i)).unwrap();
+                }
+            }
+            read_ts.push(storage.latest_commit_ts());
+        }
+        assert_eq!(read_ts, vec![0, 100, 250]);
+        check(&storage, &read_ts);
+
+        storage.force_freeze_memtable().unwrap();
+        storage.force_flush_next_imm_memtable().unwrap();
+        check(&storage, &read_ts);
+
+        storage.put(&key_of(0), &value_of(0)).unwrap();
+        storage.force_freeze_memtable().unwrap();
+        storage.force_flush_next_imm_memtable().unwrap();
+        while storage.trigger_compaction().unwrap() {}
+        check(&storage, &read_ts);
+        storage.close().unwrap();
+    }
+
+    let storage = LsmStorage::open(path, test_options()).unwrap();
+    assert_eq!(storage.latest_commit_ts(), 251);
+    check(&storage, &read_ts);
+    assert_eq!(storage.get(&key_of(0)).unwrap().unwrap(), value_of(0));
+    storage.put(&key_of(1), &value_of(1)).unwrap();
+    assert_eq!(storage.latest_commit_ts(), 252);
+    assert!(storage.get_at(&key_of(1), 251).unwrap().is_none());
+    drop(storage);
+
+    fs::remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn test_storage_write_batch() {
+    let path = "./tmp/storage-write-batch";
+    _ = fs::remove_dir_all(path);
+
+    let expected = (0..100)
+        .filter(|i| i % 10 != 0)
+        .map(|i| (key_of(i), value_of(i + 99)))
+        .collect::<Vec<_>>();
+    {
+        let storage = LsmStorage::open(path, test_options()).unwrap();
+        let mut batch = WriteBatch::new();
+        for i in 0..100 {
+            batch.put(&key_of(i), &value_of(i));
+        }
+        for i in (0..100).step_by(10) {
+            batch.delete(&key_of(i));
+        }
+        for i in 0..100 {
+            batch.put(&key_of(i), &value_of(i + 99));
+        }
+        for i in (0..100).step_by(10) {
+            batch.delete(&key_of(i));
+        }
+        storage.write_batch(&batch).unwrap();
+        assert_eq!(storage.latest_commit_ts(), batch.len() as u64);
+        check_scan(
+            storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+            &expected,
+        );
+
+        storage.write_batch(&WriteBatch::new()).unwrap();
+        assert_eq!(storage.latest_commit_ts(), batch.len() as u64);
+    }
+
+    let storage = LsmStorage::open(path, test_options()).unwrap();
+    check_scan(
+        storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        &expected,
+    );
+    storage.put(&key_of(0), &value_of(0)).unwrap();
+    assert_eq!(storage.latest_commit_ts(), 221);
+    drop(storage);
+
+    fs::remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn test_storage_write_batch_atomic() {
+    storage_test("storage-write-batch-atomic", |storage| {
+        let done = AtomicBool::new(false);
+        thread::scope(|s| {
+            for writer in 0..2 {
+                let done = &done;
+                s.spawn(move || {
+                    // every batch sets all keys to the same value
+                    for round in 0..200 {
+                        let mut batch = WriteBatch::new();
+                        for i in 0..20 {
+                            batch.put(&key_of(i), &value_of(writer * 1000 + round));
+                        }
+                        storage.write_batch(&batch).unwrap();
+                    }
+                    done.store(true, Ordering::SeqCst);
+                });
+            }
+
+            while !done.load(Ordering::SeqCst) {
+                // a read timestamp above the latest commit must not see a batch in part either
+                for read_ts in [storage.latest_commit_ts(), u64::MAX] {
+                    let mut iter = storage
+                        .scan_at(Bound::Unbounded, Bound::Unbounded, read_ts)
+                        .unwrap();
+                    let mut values = Vec::new();
+                    while iter.is_valid() {
+                        values.push(iter.value().to_vec());
+                        iter.next().unwrap();
+                    }
+                    assert!(values.is_empty() || values.len() == 20);
+                    assert!(values.windows(2).all(|pair| pair[0] == pair[1]));
+                }
+            }
+        });
+    });
+}
+
+/// Write overlapping rounds of puts and deletes, flushing and compacting after each round, then
+/// check the data before and after reopening. `check` is called on the state after each round.
+fn compaction_test<T>(name: &str, compaction_options: CompactionOptions, check: T)
+where
+    T: Fn(&LsmStorageState),
+{
+    let path = format!("./tmp/{name}");
+    _ = fs::remove_dir_all(&path);
+
+    let options = LsmStorageOptions {
+        target_sst_size: 1 << 10,
+        compaction_options,
+        enable_background_jobs: false,
+        ..test_options()
+    };
+
+    let mut expected = BTreeMap::new();
+    {
+        let storage = LsmStorage::open(&path, options.clone()).unwrap();
+        for round in 0..20 {
+            for i in (round * 37..round * 37 + 300).map(|x| x % 1000) {
+                if i % 7 == round % 7 {
+                    storage.delete(&key_of(i)).unwrap();
+                    expected.remove(&key_of(i));
+                } else {
+                    storage.put(&key_of(i), &value_of(i + round)).unwrap();
+                    expected.insert(key_of(i), value_of(i + round));
+                }
+            }
+            storage.force_freeze_memtable().unwrap();
+            storage.force_flush_next_imm_memtable().unwrap();
+            while storage.trigger_compaction().unwrap() {}
+
+            let snapshot = storage.inner.snapshot();
+            for (_, ssts) in &snapshot.levels {
+                for pair in ssts.windows(2) {
+                    let (a, b) = (&snapshot.sstables[&pair[0]], &snapshot.sstables[&pair[1]]);
+                    assert!(a.last_key() < b.first_key());
+                }
+            }
+            check(&snapshot);
+        }
+
+        let expected = expected.clone().into_iter().collect::<Vec<_>>();
+        check_scan(
+            storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+            &expected,
+        );
+
+        // obsolete SSTs are removed
+        let num_ssts = fs::read_dir(&path)
+            .unwrap()
+            .filter(|entry| {
+                let name = entry.as_ref().unwrap().file_name();
+                name.to_string_lossy().ends_with(".sst")
+            })
+            .count();
+        assert_eq!(num_ssts, storage.inner.snapshot().sst_ids().count());
+    }
+
+    let storage = LsmStorage::open(&path, options).unwrap();
+    for i in 0..1000 {
+        assert_eq!(
+            storage.get(&key_of(i)).unwrap(),
+            expected.get(&key_of(i)).map(|x| Bytes::copy_from_slice(x)),
+            "{i}"
+        );
+    }
+    let expected = expected.into_iter().collect::<Vec<_>>();
+    check_scan(
+        storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        &expected,
+    );
+    drop(storage);
+
+    fs::remove_dir_all(&path).unwrap();
+}
+
+#[test]
+fn test_storage_leveled_compaction() {
+    let options = CompactionOptions::Leveled(LeveledCompactionOptions {
+        level_size_multiplier: 2,
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 3,
+        base_level_size: 4 << 10,
+    });
+    compaction_test("storage-leveled-compaction", options, |state| {
+        assert!(state.l0_sstables.len() < 2);
+        assert!(state
+            .levels
+            .iter()
+            .all(|(level, _)| (1..=3).contains(level)));
+    });
+}
+
+#[test]
+fn test_storage_tiered_compaction() {
+    let options = CompactionOptions::Tiered(TieredCompactionOptions {
+        num_tiers: 3,
+        max_size_amplification_percent: 200,
+        size_ratio: 1,
+        min_merge_width: 2,
+    });
+    compaction_test("storage-tiered-compaction", options, |state| {
+        assert!(state.l0_sstables.is_empty());
+        assert!(state.levels.len() < 3);
+    });
+}
+
+#[test]
+fn test_storage_simple_leveled_compaction() {
+    let options = CompactionOptions::SimpleLeveled(SimpleLeveledCompactionOptions {
+        size_ratio_percent: 200,
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 3,
+    });
+    compaction_test("storage-simple-leveled-compaction", options, |state| {
+        assert!(state.l0_sstables.len() < 2);
+        assert!(state
+            .levels
+            .iter()
+            .all(|(level, _)| (1..=3).contains(level)));
+    });
+}
+
+/// Merges all SSTs into L1 once there are two L0 SSTs.
+#[derive(Debug)]
+struct FullCompactionController;
+
+impl CompactionController for FullCompactionController {
+    fn generate_compaction_task(&self, state: &LsmStorageState) -> Option<CompactionTask> {
+        if state.l0_sstables.len() < 2 {
+            return None;
+        }
+        Some(CompactionTask {
+            inputs: vec![(0, state.l0_sstables.clone()), (1, state.level(1).to_vec())],
+            output_level: 1,
+            compact_to_bottom_level: true,
+        })
+    }
+}
+
+/// Returns a task with an SST that does not exist.
+#[derive(Debug)]
+struct InvalidCompactionController;
+
+impl CompactionController for InvalidCompactionController {
+    fn generate_compaction_task(&self, _state: &LsmStorageState) -> Option<CompactionTask> {
+        Some(CompactionTask {
+            inputs: vec![(1, vec![12345])],
+            output_level: 2,
+            compact_to_bottom_level: true,
+        })
+    }
+}
+
+#[test]
+fn test_storage_custom_compaction() {
+    let options = CompactionOptions::Custom(Arc::new(FullCompactionController));
+    compaction_test("storage-custom-compaction", options, |state| {
+        assert!(state.l0_sstables.len() < 2);
+        assert!(state.levels.len() <= 1);
+    });
+
+    let path = "./tmp/storage-invalid-compaction";
+    _ = fs::remove_dir_all(path);
+    let options = LsmStorageOptions {
+        compaction_options: CompactionOptions::Custom(Arc::new(InvalidCompactionController)),
+        enable_background_jobs: false,
+        ..test_options()
+    };
+    let storage = LsmStorage::open(path, options).unwrap();
+    assert!(storage.trigger_compaction().is_err());
+    drop(storage);
+    fs::remove_dir_all(path).unwrap();
+}
+
+/// Count the entries of all SSTs, every version and tombstone included.
+fn num_sst_entries(storage: &LsmStorage) -> usize {
+    let snapshot = storage.inner.snapshot();
+    let mut count = 0;
+    for id in snapshot.sst_ids() {
+        let table = Arc::clone(&snapshot.sstables[id]);
+        let mut iter = SSTableIterator::create_and_seek_to_first(table).unwrap();
+        while iter.is_valid() {
+            count += 1;
+            iter.next().unwrap();
+        }
+    }
+    count
+}
+
+#[test]
+fn test_storage_snapshot() {
+    let path = "./tmp/storage-snapshot";
+    _ = fs::remove_dir_all(path);
+
+    let options = LsmStorageOptions {
+        compaction_options: CompactionOptions::Custom(Arc::new(FullCompactionController)),
+        enable_background_jobs: false,
+        ..test_options()
+    };
+    let storage = LsmStorage::open(path, options).unwrap();
+    let flush = |extra_key: usize| {
+        storage.put(&key_of(extra_key), b"").unwrap();
+        storage.force_freeze_memtable().unwrap();
+        storage.force_flush_next_imm_memtable().unwrap();
+    };
+    let round = |round: usize| {
+        (0..100)
+            .filter(|i| round != 2 || *i >= 50)
+            .map(|i| (key_of(i), value_of(i + round * 1000)))
+            .collect::<Vec<_>>()
+    };
+
+    for i in 0..100 {
+        storage.put(&key_of(i), &value_of(i + 1000)).unwrap();
+    }
+    let snapshot1 = storage.snapshot();
+    assert_eq!(snapshot1.read_ts(), storage.latest_commit_ts());
+    for i in 0..100 {
+        storage.put(&key_of(i), &value_of(i + 2000)).unwrap();
+    }
+    for i in 0..50 {
+        storage.delete(&key_of(i)).unwrap();
+    }
+    let snapshot2 = storage.snapshot();
+    flush(100);
+    for i in 0..100 {
+        storage.put(&key_of(i), &value_of(i + 3000)).unwrap();
+    }
+    flush(101);
+
+    let check = |snapshot: &Snapshot, expected: &[(Vec<u8>, Vec<u8>)]| {
+        for i in 0..100 {
+            let value = expected.iter().find(|(key, _)| *key == key_of(i));
+            assert_eq!(
+                snapshot.get(&key_of(i)).unwrap(),
+                value.map(|(_, value)| Bytes::copy_from_slice(value)),
+                "{i}"
+            );
+        }
+        check_scan(
+            snapshot
+                .scan(Bound::Unbounded, Bound::Excluded(&key_of(100)))
+                .unwrap(),
+            expected,
+        );
+    };
+
+    // versions visible to a snapshot are kept by compactions
+    assert!(storage.trigger_compaction().unwrap());
+    check(&snapshot1, &round(1));
+    check(&snapshot2, &round(2));
+    check(&storage.snapshot(), &round(3));
+    assert_eq!(num_sst_entries(&storage), 350 + 2);
+
+    // only the newest version at or below the watermark is kept
+    drop(snapshot1);
+    flush(102);
+    flush(103);
+    assert!(storage.trigger_compaction().unwrap());
+    check(&snapshot2, &round(2));
+    check(&storage.snapshot(), &round(3));
+    assert_eq!(num_sst_entries(&storage), 150 + 4);
+
+    // dropping the last snapshot reclaims the old versions
+    drop(snapshot2);
+    flush(104);
+    flush(105);
+    assert!(storage.trigger_compaction().unwrap());
+    check(&storage.snapshot(), &round(3));
+    assert_eq!(num_sst_entries(&storage), 100 + 6);
+
+    drop(storage);
+    fs::remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn test_storage_delete_range() {
+    let path = "./tmp/storage-delete-range";
+    _ = fs::remove_dir_all(path);
+
+    let options = LsmStorageOptions {
+        compaction_options: CompactionOptions::Custom(Arc::new(FullCompactionController)),
+        enable_background_jobs: false,
+        ..test_options()
+    };
+    let mut storage = LsmStorage::open(path, options.clone()).unwrap();
+    let flush = |storage: &LsmStorage| {
+        storage.force_freeze_memtable().unwrap();
+        while !storage.inner.snapshot().imm_memtables.is_empty() {
+            storage.force_flush_next_imm_memtable().unwrap();
+        }
+    };
+    let range_tombstones = |storage: &LsmStorage| {
+        let snapshot = storage.inner.snapshot();
+        let ids = snapshot.sst_ids().collect::<Vec<_>>();
+        ids.into_iter()
+            .map(|id| snapshot.sstables[id].range_tombstones().len())
+            .sum::<usize>()
+    };
+    let check = |snapshot: &Snapshot, deleted: &dyn Fn(usize) -> bool| {
+        let expected = (0..100)
+            .filter(|i| !deleted(*i))
+            .map(|i| (key_of(i), value_of(i)))
+            .collect::<Vec<_>>();
+        for i in 0..100 {
+            let value = (!deleted(i)).then(|| Bytes::from(value_of(i)));
+            assert_eq!(snapshot.get(&key_of(i)).unwrap(), value, "{i}");
+        }
+        check_scan(
+            snapshot
+                .scan(Bound::Unbounded, Bound::Excluded(&key_of(100)))
+                .unwrap(),
+            &expected,
+        );
+        let from = expected
+            .iter()
+            .position(|(key, _)| *key >= key_of(25))
+            .unwrap();
+        check_scan(
+            snapshot
+                .scan(Bound::Included(&key_of(25)), Bound::Excluded(&key_of(60)))
+                .unwrap(),
+            &expected[from..expected.len() - 40],
+        );
+    };
+    let deleted = |i: usize| (20..40).contains(&i) && i != 30 || (50..55).contains(&i);
+
+    assert!(storage.delete_range(&key_of(2), &key_of(1)).is_err());
+    let ts = storage.latest_commit_ts();
+    storage.delete_range(&key_of(1), &key_of(1)).unwrap();
+    assert_eq!(storage.latest_commit_ts(), ts);
+
+    for i in 0..100 {
+        storage.put(&key_of(i), &value_of(i)).unwrap();
+    }
+    let before = storage.snapshot();
+    flush(&storage);
+    storage.delete_range(&key_of(20), &key_of(40)).unwrap();
+    storage.put(&key_of(30), &value_of(30)).unwrap();
+    check(&storage.snapshot(), &|i| (20..40).contains(&i) && i != 30);
+    storage.delete_range(&key_of(50), &key_of(55)).unwrap();
+    check(&before, &|_| false);
+    check(&storage.snapshot(), &deleted);
+
+    // range tombstones are persisted by flushes and recovered from the WAL
+    flush(&storage);
+    storage.delete_range(&key_of(200), &key_of(300)).unwrap();
+    check(&storage.snapshot(), &deleted);
+    drop(before);
+    drop(storage);
+    storage = LsmStorage::open(path, options.clone()).unwrap();
+    check(&storage.snapshot(), &deleted);
+
+    // covered versions visible to a snapshot are kept by compactions
+    let snapshot = storage.snapshot();
+    storage.delete_range(&key_of(0), &key_of(10)).unwrap();
+    flush(&storage);
+    assert!(storage.trigger_compaction().unwrap());
+    let deleted_all = |i: usize| deleted(i) || i < 10;
+    check(&snapshot, &deleted);
+    check(&storage.snapshot(), &deleted_all);
+    assert_eq!(num_sst_entries(&storage), 100 - 25 + 1);
+    assert_eq!(range_tombstones(&storage), 1);
+
+    // the covered versions and the tombstones are dropped below the watermark
+    drop(snapshot);
+    for extra_key in [100, 101] {
+        storage.put(&key_of(extra_key), b"").unwrap();
+        flush(&storage);
+    }
+    assert!(storage.trigger_compaction().unwrap());
+    check(&storage.snapshot(), &deleted_all);
+    assert_eq!(num_sst_entries(&storage), 100 - 35 + 3);
+    assert_eq!(range_tombstones(&storage), 0);
+
+    drop(storage);
+    storage = LsmStorage::open(path, options).unwrap();
+    check(&storage.snapshot(), &deleted_all);
+
+    drop(storage);
+    fs::remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn test_storage_remove_obsolete_files() {
+    let path = "./tmp/storage-remove-obsolete-files";
+    _ = fs::remove_dir_all(path);
+
+    {
+        let storage = LsmStorage::open(path, test_options()).unwrap();
+        storage.put(&key_of(0), &value_of(0)).unwrap();
+        storage.force_freeze_memtable().unwrap();
+        storage.force_flush_next_imm_memtable().unwrap();
+    }
+    fs::write(format!("{path}/00100.sst"), b"partial").unwrap();
+    fs::write(format!("{path}/00101.wal"), b"").unwrap();
+
+    let storage = LsmStorage::open(path, test_options()).unwrap();
+    assert!(!Path::new(&format!("{path}/00100.sst")).exists());
+    assert!(!Path::new(&format!("{path}/00101.wal")).exists());
+    assert_eq!(storage.get(&key_of(0)).unwrap().unwrap(), value_of(0));
+    drop(storage);
+
+    fs::remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn test_storage_background_flush() {
+    let path = "./tmp/storage-background-flush";
+    _ = fs::remove_dir_all(path);
+
+    let options = LsmStorageOptions {
+        target_sst_size: 4 << 10,
+        compaction_options: CompactionOptions::Leveled(LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            base_level_size: 16 << 10,
+            ..Default::default()
+        }),
+        ..test_options()
+    };
+
+    {
+        let storage = LsmStorage::open(path, options.clone()).unwrap();
+        for i in 0..1000 {
+            storage.put(&key_of(i), &value_of(i)).unwrap();
+        }
+
+        // the mem-table is frozen and flushed without being asked to
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while storage.inner.snapshot().levels.is_empty() {
+            assert!(
+                Instant::now() < deadline,
+                "nothing is flushed and compacted"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+        for i in 0..1000 {
+            assert_eq!(storage.get(&key_of(i)).unwrap().unwrap(), value_of(i));
+        }
+
+        for i in 0..500 {
+            storage.delete(&key_of(i)).unwrap();
+        }
+        storage.force_freeze_memtable().unwrap();
+        storage.close().unwrap();
+
+        // the immutable mem-tables are flushed on close
+        let snapshot = storage.inner.snapshot();
+        assert!(snapshot.imm_memtables.is_empty());
+        assert!(snapshot.l0_sstables.len() < 2);
+        let num_wals = fs::read_dir(path)
+            .unwrap()
+            .filter(|entry| {
+                let name = entry.as_ref().unwrap().file_name();
+                name.to_string_lossy().ends_with(".wal")
+            })
+            .count();
+        assert_eq!(num_wals, 1);
+    }
+
+    let storage = LsmStorage::open(path, options).unwrap();
+    let expected = (500..1000)
+        .map(|i| (key_of(i), value_of(i)))
+        .collect::<Vec<_>>();
+    check_scan(
+        storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        &expected,
+    );
+    drop(storage);
+
+    fs::remove_dir_all(path).unwrap();
+}
+
+/// Merges all L0 SSTs into L1 once it is opened.
+#[derive(Debug)]
+struct GatedCompactionController {
+    open: Arc<AtomicBool>,
+}
+
+impl CompactionController for GatedCompactionController {
+    fn generate_compaction_task(&self, state: &LsmStorageState) -> Option<CompactionTask> {
+        if !self.open.load(Ordering::SeqCst) {
+            return None;
+        }
+        FullCompactionController.generate_compaction_task(state)
+    }
+}
+
+#[test]
+fn test_storage_no_compaction_write_stall() {
+    let path = "./tmp/storage-no-compaction-write-stall";
+    _ = fs::remove_dir_all(path);
+
+    let options = LsmStorageOptions {
+        target_sst_size: 1 << 10,
+        compaction_options: CompactionOptions::NoCompaction,
+        level0_slowdown_writes_trigger: 2,
+        level0_stop_writes_trigger: 4,
+        ..test_options()
+    };
+    let storage = Arc::new(LsmStorage::open(path, options).unwrap());
+
+    // nothing shrinks L0, so the L0 triggers must not stop the writes filling a mem-table each
+    let (tx, rx) = std::sync::mpsc::channel();
+    let writer = Arc::clone(&storage);
+    thread::spawn(move || {
+        for i in 0..100 {
+            writer.put(&key_of(i), &value_of(i).repeat(100)).unwrap();
+            thread::sleep(Duration::from_millis(1));
+        }
+        tx.send(()).unwrap();
+    });
+    rx.recv_timeout(Duration::from_secs(10))
+        .expect("writes are stopped");
+
+    let stats = storage.stats();
+    assert_eq!(stats.write_stall(WriteStallReason::Level0Stop).count, 0);
+    assert_eq!(stats.write_stall(WriteStallReason::Level0Slowdown).count, 0);
+    for i in 0..100 {
+        assert_eq!(
+            storage.get(&key_of(i)).unwrap().unwrap(),
+            value_of(i).repeat(100)
+        );
+    }
+    storage.close().unwrap();
+    assert!(storage.inner.snapshot().l0_sstables.len() > 4);
+    drop(storage);
+
+    // leveled compaction would only start once the writes are stopped
+    let options = LsmStorageOptions {
+        compaction_options: CompactionOptions::Leveled(LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 4,
+            ..Default::default()
+        }),
+        level0_stop_writes_trigger: 4,
+        ..test_options()
+    };
+    assert!(LsmStorage::open(path, options).is_err());
+
+    fs::remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn test_storage_write_stall() {
+    let path = "./tmp/storage-write-stall";
+    _ = fs::remove_dir_all(path);
+
+    let open = Arc::new(AtomicBool::new(false));
+    let options = LsmStorageOptions {
+        target_sst_size: 1 << 10,
+        compaction_options: CompactionOptions::Custom(Arc::new(GatedCompactionController {
+            open: Arc::clone(&open),
+        })),
+        level0_slowdown_writes_trigger: 2,
+        level0_stop_writes_trigger: 4,
+        ..test_options()
+    };
+    let storage = LsmStorage::open(path, options).unwrap();
+
+    let done = AtomicBool::new(false);
+    thread::scope(|s| {
+        s.spawn(|| {
+            // every write fills a mem-table
+            for i in 0..100 {
+                storage.put(&key_of(i), &value_of(i).repeat(100)).unwrap();
+                thread::sleep(Duration::from_millis(1));
+            }
+            done.store(true, Ordering::SeqCst);
+        });
+
+        // writes stop until the compaction catches up
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while storage.inner.snapshot().l0_sstables.len() < 4 {
+            assert!(Instant::now() < deadline, "L0 SSTs do not pile up");
+            thread::sleep(Duration::from_millis(10));
+        }
+        thread::sleep(Duration::from_millis(200));
+        let stopped = !done.load(Ordering::SeqCst);
+        open.store(true, Ordering::SeqCst);
+        assert!(stopped);
+    });
+
+    let stats = storage.stats();
+    let stop = stats.write_stall(WriteStallReason::Level0Stop);
+    assert!(stop.count > 0);
+    assert!(stop.duration >= Duration::from_millis(200));
+    assert!(stats.write_stall(WriteStallReason::Level0Slowdown).count > 0);
+    assert!(stats.write_stall_duration() >= stop.duration);
+    assert_eq!(
+        stats.write_stall(WriteStallReason::ImmMemtableStop).count,
+        0
+    );
+
+    for i in 0..100 {
+        assert_eq!(
+            storage.get(&key_of(i)).unwrap().unwrap(),
+            value_of(i).repeat(100)
+        );
+    }
+    drop(storage);
+
+    fs::remove_dir_all(path).unwrap();
+}
@@ -0,0 +1,384 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Result};
+use bytes::{Buf, BufMut};
+use parking_lot::Mutex;
+
+/// Size of the edit header: `len(u32) | crc32(u32)`.
+const HEADER_SIZE: usize = 8;
+
+const RECORD_NEW_MEMTABLE: u8 = 0;
+const RECORD_ADD_SST: u8 = 1;
+const RECORD_REMOVE_SST: u8 = 2;
+const RECORD_FLUSH_DONE: u8 = 3;
+const RECORD_COMPACTION_DONE: u8 = 4;
+
+/// A change to the structure of the storage engine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ManifestRecord {
+    /// A mem-table with the id is created, its WAL has the same id.
+    NewMemtable(usize),
+    /// An SST is added to a level. L0 SSTs are added as the newest ones, SSTs of other levels are
+    /// appended to the level.
+    AddSst {
+        /// Level of the SST.
+        level: usize,
+        /// Id of the SST.
+        sst_id: usize,
+    },
+    /// An SST is removed from a level.
+    RemoveSst {
+        /// Level of the SST.
+        level: usize,
+        /// Id of the SST.
+        sst_id: usize,
+    },
+    /// The mem-table with the id is flushed and its WAL is no longer needed.
+    FlushDone(usize),
+    /// Marks an edit as the result of a compaction. The SST changes are in the same edit.
+    CompactionDone,
+}
+
+/// A batch of records applied atomically, tagged with the version it produces.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionEdit {
+    /// Version of the structure after applying the edit.
+    pub version: u64,
+    /// Records of the edit.
+    pub records: Vec<ManifestRecord>,
+}
+
+/// The structure of the storage engine rebuilt by replaying the manifest.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ManifestState {
+    /// Version of the latest edit.
+    pub version: u64,
+    /// Mem-tables that are not flushed yet, from the oldest to the newest.
+    pub memtables: Vec<usize>,
+    /// L0 SSTs, from the newest to the oldest.
+    pub l0_sstables: Vec<usize>,
+    /// SSTs of the other levels, sorted by level. Empty levels are removed.
+    pub levels: Vec<(usize, Vec<usize>)>,
+}
+
+impl ManifestState {
+    /// Apply an edit to the state.
+    pub fn apply(&mut self, edit: &VersionEdit) {
+        for record in &edit.records {
+            match *record {
+                ManifestRecord::NewMemtable(id) => self.memtables.push(id),
+                ManifestRecord::AddSst { level: 0, sst_id } => self.l0_sstables.insert(0, sst_id),
+                ManifestRecord::AddSst { level, sst_id } => {
+                    match self.levels.iter_mut().find(|(x, _)| *x == level) {
+                        Some((_, ssts)) => ssts.push(sst_id),
+                        None => {
+                            self.levels.push((level, vec![sst_id]));
+                            self.levels.sort_by_key(|(x, _)| *x);
+                        }
+                    }
+                }
+                ManifestRecord::RemoveSst { level: 0, sst_id } => {
+                    self.l0_sstables.retain(|x| *x != sst_id)
+                }
+                ManifestRecord::RemoveSst { level, sst_id } => {
+                    if let Some((_, ssts)) = self.levels.iter_mut().find(|(x, _)| *x == level) {
+                        ssts.retain(|x| *x != sst_id);
+                    }
+                    self.levels.retain(|(_, ssts)| !ssts.is_empty());
+                }
+                ManifestRecord::FlushDone(id) => self.memtables.retain(|x| *x != id),
+                ManifestRecord::CompactionDone => {}
+            }
+        }
+        self.version = edit.version;
+    }
+
+    /// The records that rebuild this state from an empty one.
+    fn snapshot_records(&self) -> Vec<ManifestRecord> {
+        let mut records = Vec::new();
+        for id in &self.memtables {
+            records.push(ManifestRecord::NewMemtable(*id));
+        }
+        for sst_id in self.l0_sstables.iter().rev() {
+            records.push(ManifestRecord::AddSst {
+                level: 0,
+                sst_id: *sst_id,
+            });
+        }
+        for (level, ssts) in &self.levels {
+            for sst_id in ssts {
+                records.push(ManifestRecord::AddSst {
+                    level: *level,
+                    sst_id: *sst_id,
+                });
+            }
+        }
+        records
+    }
+}
+
+struct ManifestInner {
+    file: File,
+    size: u64,
+    state: ManifestState,
+}
+
+/// An append-only log of the edits to the structure of the storage engine.
+///
+/// Each edit is encoded as `len(u32) | crc32(u32) | version(u64) | num_records(u32) | records`.
+/// Once the file grows larger than `max_size`, it is rewritten as a single edit holding a
+/// snapshot of the current state.
+pub struct Manifest {
+    path: PathBuf,
+    max_size: u64,
+    inner: Mutex<ManifestInner>,
+}
+
+impl Manifest {
+    /// Create a new manifest file.
+    pub fn create(path: impl AsRef<Path>, max_size: u64) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new()
+            .create_new(true)
+            .append(true)
+            .open(&path)?;
+        sync_parent_dir(&path)?;
+        Ok(Self {
+            path,
+            max_size,
+            inner: Mutex::new(ManifestInner {
+                file,
+                size: 0,
+                state: ManifestState::default(),
+            }),
+        })
+    }
+
+    /// Replay an existing manifest file and open it for appending. An invalid edit that no valid
+    /// edit follows is a torn tail and is truncated, an invalid edit followed by valid ones is
+    /// reported as an error.
+    pub fn recover(path: impl AsRef<Path>, max_size: u64) -> Result<(Self, ManifestState)> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new().read(true).append(true).open(&path)?;
+
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+
+        let mut state = ManifestState::default();
+        let mut buf = &data[..];
+        while buf.has_remaining() {
+            let result = match next_edit(buf) {
+                Some((payload, rest)) => decode_edit(payload).map(|edit| (edit, rest)),
+                None => Err(anyhow!("checksum mismatch")),
+            };
+            match result {
+                Ok((edit, rest)) => {
+                    state.apply(&edit);
+                    buf = rest;
+                }
+                Err(e) if has_valid_edit(buf) => {
+                    return Err(e.context(format!("corrupted edit in manifest {}", path.display())));
+                }
+                Err(_) => break,
+            }
+        }
+
+        let size = (data.len() - buf.remaining()) as u64;
+        file.set_len(size)?;
+
+        let manifest = Self {
+            path,
+            max_size,
+            inner: Mutex::new(ManifestInner {
+                file,
+                size,
+                state: state.clone(),
+            }),
+        };
+        Ok((manifest, state))
+    }
+
+    /// Durably append an edit made of `records` and return its version. The edit is applied once
+    /// it is appended: a failed snapshot rewrite afterwards is only logged, as the manifest is
+    /// still complete and the rewrite is retried by the next edit.
+    pub fn add_edit(&self, records: Vec<ManifestRecord>) -> Result<u64> {
+        let mut inner = self.inner.lock();
+        let edit = VersionEdit {
+            version: inner.state.version + 1,
+            records,
+        };
+
+        let data = encode_edit(&edit);
+        let result = inner
+            .file
+            .write_all(&data)
+            .and_then(|_| inner.file.sync_data());
+        if let Err(e) = result {
+            // Cut the partly written edit, so that the next edit is not appended after it.
+            inner.file.set_len(inner.size)?;
+            return Err(e.into());
+        }
+        inner.size += data.len() as u64;
+        inner.state.apply(&edit);
+
+        if inner.size > self.max_size {
+            if let Err(e) = self.write_snapshot(&mut inner) {
+                log::warn!("failed to rewrite the manifest as a snapshot: {:#}", e);
+            }
+        }
+
+        Ok(edit.version)
+    }
+
+    /// Rewrite the manifest as a snapshot of the current state.
+    pub fn snapshot(&self) -> Result<()> {
+        let mut inner = self.inner.lock();
+        self.write_snapshot(&mut inner)
+    }
+
+    /// Get the state recorded in the manifest.
+    pub fn state(&self) -> ManifestState {
+        self.inner.lock().state.clone()
+    }
+
+    fn write_snapshot(&self, inner: &mut ManifestInner) -> Result<()> {
+        let edit = VersionEdit {
+            version: inner.state.version,
+            records: inner.state.snapshot_records(),
+        };
+        let data = encode_edit(&edit);
+
+        // The file stays open across the rename, so the manifest is switched to it as soon as it
+        // replaces the old one.
+        let tmp_path = self.path.with_extension("tmp");
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp_path, &self.path)?;
+
+        inner.file = file;
+        inner.size = data.len() as u64;
+        sync_parent_dir(&self.path)
+    }
+}
+
+fn sync_parent_dir(path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        let parent = if parent.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            parent
+        };
+        File::open(parent)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Split the payload of the first edit off `buf`, None if the edit is truncated or its checksum
+/// does not match.
+fn next_edit(buf: &[u8]) -> Option<(&[u8], &[u8])> {
+    if buf.len() < HEADER_SIZE {
+        return None;
+    }
+    let len = (&buf[..4]).get_u32() as usize;
+    let checksum = (&buf[4..8]).get_u32();
+    let payload = buf[HEADER_SIZE..].get(..len)?;
+    (crc32fast::hash(payload) == checksum).then(|| (payload, &buf[HEADER_SIZE + len..]))
+}
+
+/// Check if a valid edit follows the invalid edit at the start of `buf`.
+fn has_valid_edit(mut buf: &[u8]) -> bool {
+    while buf.len() >= HEADER_SIZE {
+        let len = (&buf[..4]).get_u32() as usize;
+        buf = match buf.get(HEADER_SIZE + len..) {
+            Some(rest) => rest,
+            None => return false,
+        };
+        if next_edit(buf).is_some_and(|(payload, _)| decode_edit(payload).is_ok()) {
+            return true;
+        }
+    }
+    false
+}
+
+fn encode_edit(edit: &VersionEdit) -> Vec<u8> {
+    let mut payload = Vec::new();
+    payload.put_u64(edit.version);
+    payload.put_u32(edit.records.len() as u32);
+    for record in &edit.records {
+        match *record {
+            ManifestRecord::NewMemtable(id) => {
+                payload.put_u8(RECORD_NEW_MEMTABLE);
+                payload.put_u64(id as u64);
+            }
+            ManifestRecord::AddSst { level, sst_id } => {
+                payload.put_u8(RECORD_ADD_SST);
+                payload.put_u64(level as u64);
+                payload.put_u64(sst_id as u64);
+            }
+            ManifestRecord::RemoveSst { level, sst_id } => {
+                payload.put_u8(RECORD_REMOVE_SST);
+                payload.put_u64(level as u64);
+                payload.put_u64(sst_id as u64);
+            }
+            ManifestRecord::FlushDone(id) => {
+                payload.put_u8(RECORD_FLUSH_DONE);
+                payload.put_u64(id as u64);
+            }
+            ManifestRecord::CompactionDone => payload.put_u8(RECORD_COMPACTION_DONE),
+        }
+    }
+
+    let mut data = Vec::with_capacity(HEADER_SIZE + payload.len());
+    data.put_u32(payload.len() as u32);
+    data.put_u32(crc32fast::hash(&payload));
+    data.put_slice(&payload);
+    data
+}
+
+fn decode_edit(mut payload: &[u8]) -> Result<VersionEdit> {
+    fn get_u64(buf: &mut &[u8]) -> Result<usize> {
+        if buf.remaining() < 8 {
+            bail!("manifest edit is too short");
+        }
+        Ok(buf.get_u64() as usize)
+    }
+
+    if payload.remaining() < 12 {
+        bail!("manifest edit is too short");
+    }
+    let version = payload.get_u64();
+    let num_records = payload.get_u32() as usize;
+
+    let mut records = Vec::with_capacity(num_records);
+    for _ in 0..num_records {
+        if !payload.has_remaining() {
+            bail!("manifest edit is too short");
+        }
+        let record = match payload.get_u8() {
+            RECORD_NEW_MEMTABLE => ManifestRecord::NewMemtable(get_u64(&mut payload)?),
+            RECORD_ADD_SST => ManifestRecord::AddSst {
+                level: get_u64(&mut payload)?,
+                sst_id: get_u64(&mut payload)?,
+            },
+            RECORD_REMOVE_SST => ManifestRecord::RemoveSst {
+                level: get_u64(&mut payload)?,
+                sst_id: get_u64(&mut payload)?,
+            },
+            RECORD_FLUSH_DONE => ManifestRecord::FlushDone(get_u64(&mut payload)?),
+            RECORD_COMPACTION_DONE => ManifestRecord::CompactionDone,
+            x => bail!("unknown manifest record type {}", x),
+        };
+        records.push(record);
+    }
+
+    if payload.has_remaining() {
+        bail!("manifest edit has trailing bytes");
+    }
+    Ok(VersionEdit { version, records })
+}
+
+#[cfg(test)]
+mod tests;
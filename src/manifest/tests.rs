@@ -0,0 +1,215 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::Path,
+};
+
+use super::{Manifest, ManifestRecord, ManifestState};
+
+fn manifest_test<T>(name: &str, test: T)
+where
+    T: Fn(&Path),
+{
+    fs::create_dir_all("./tmp").unwrap();
+    let path = format!("./tmp/{name}.manifest");
+    let path = Path::new(&path);
+    _ = fs::remove_file(path);
+
+    test(path);
+
+    fs::remove_file(path).unwrap();
+}
+
+fn add_sst(level: usize, sst_id: usize) -> ManifestRecord {
+    ManifestRecord::AddSst { level, sst_id }
+}
+
+fn remove_sst(level: usize, sst_id: usize) -> ManifestRecord {
+    ManifestRecord::RemoveSst { level, sst_id }
+}
+
+/// Flush mem-tables 1..=4 to L0, then compact 1 and 2 into L1 SSTs 5 and 6.
+fn write_edits(manifest: &Manifest) {
+    for id in 1..=4 {
+        manifest
+            .add_edit(vec![ManifestRecord::NewMemtable(id)])
+            .unwrap();
+    }
+    manifest
+        .add_edit(vec![ManifestRecord::NewMemtable(7)])
+        .unwrap();
+    for id in 1..=4 {
+        manifest
+            .add_edit(vec![add_sst(0, id), ManifestRecord::FlushDone(id)])
+            .unwrap();
+    }
+    manifest
+        .add_edit(vec![
+            remove_sst(0, 1),
+            remove_sst(0, 2),
+            add_sst(1, 5),
+            add_sst(1, 6),
+            ManifestRecord::CompactionDone,
+        ])
+        .unwrap();
+}
+
+fn expected_state() -> ManifestState {
+    ManifestState {
+        version: 10,
+        memtables: vec![7],
+        l0_sstables: vec![4, 3],
+        levels: vec![(1, vec![5, 6])],
+    }
+}
+
+#[test]
+fn test_manifest_recover() {
+    manifest_test("manifest-recover", |path| {
+        {
+            let manifest = Manifest::create(path, 1 << 20).unwrap();
+            write_edits(&manifest);
+            assert_eq!(manifest.state(), expected_state());
+        }
+
+        let (manifest, state) = Manifest::recover(path, 1 << 20).unwrap();
+        assert_eq!(state, expected_state());
+
+        manifest
+            .add_edit(vec![remove_sst(1, 5), ManifestRecord::CompactionDone])
+            .unwrap();
+        drop(manifest);
+
+        let (_, state) = Manifest::recover(path, 1 << 20).unwrap();
+        assert_eq!(state.version, 11);
+        assert_eq!(state.levels, vec![(1, vec![6])]);
+    });
+}
+
+#[test]
+fn test_manifest_snapshot() {
+    manifest_test("manifest-snapshot", |path| {
+        {
+            let manifest = Manifest::create(path, 64).unwrap();
+            write_edits(&manifest);
+        }
+        assert!(fs::metadata(path).unwrap().len() <= 128);
+
+        let (manifest, state) = Manifest::recover(path, 64).unwrap();
+        assert_eq!(state, expected_state());
+
+        manifest.snapshot().unwrap();
+        drop(manifest);
+
+        let (_, state) = Manifest::recover(path, 64).unwrap();
+        assert_eq!(state, expected_state());
+    });
+}
+
+#[test]
+fn test_manifest_snapshot_failure() {
+    manifest_test("manifest-snapshot-failure", |path| {
+        // the snapshot cannot be written while its temporary file is a directory
+        let tmp_path = path.with_extension("tmp");
+        fs::create_dir_all(&tmp_path).unwrap();
+        {
+            let manifest = Manifest::create(path, 64).unwrap();
+            write_edits(&manifest);
+            assert_eq!(manifest.state(), expected_state());
+            assert!(manifest.snapshot().is_err());
+        }
+        assert!(fs::metadata(path).unwrap().len() > 128);
+
+        // every edit is recorded once
+        let (manifest, state) = Manifest::recover(path, 64).unwrap();
+        assert_eq!(state, expected_state());
+
+        fs::remove_dir(&tmp_path).unwrap();
+        manifest
+            .add_edit(vec![remove_sst(1, 5), ManifestRecord::CompactionDone])
+            .unwrap();
+        drop(manifest);
+        assert!(fs::metadata(path).unwrap().len() <= 128);
+
+        let (_, state) = Manifest::recover(path, 64).unwrap();
+        assert_eq!(state.version, 11);
+        assert_eq!(state.levels, vec![(1, vec![6])]);
+    });
+}
+
+#[test]
+fn test_manifest_torn_tail() {
+    manifest_test("manifest-torn-tail", |path| {
+        {
+            let manifest = Manifest::create(path, 1 << 20).unwrap();
+            write_edits(&manifest);
+        }
+
+        let len = fs::metadata(path).unwrap().len();
+        let file = OpenOptions::new().write(true).open(path).unwrap();
+        file.set_len(len - 1).unwrap();
+
+        let (manifest, state) = Manifest::recover(path, 1 << 20).unwrap();
+        assert_eq!(state.version, 9);
+        assert_eq!(state.l0_sstables, vec![4, 3, 2, 1]);
+
+        manifest
+            .add_edit(vec![ManifestRecord::NewMemtable(8)])
+            .unwrap();
+        drop(manifest);
+
+        let (_, state) = Manifest::recover(path, 1 << 20).unwrap();
+        assert_eq!(state.version, 10);
+        assert_eq!(state.memtables, vec![7, 8]);
+    });
+}
+
+#[test]
+fn test_manifest_torn_edit() {
+    manifest_test("manifest-torn-edit", |path| {
+        {
+            let manifest = Manifest::create(path, 1 << 20).unwrap();
+            write_edits(&manifest);
+        }
+        let data = fs::read(path).unwrap();
+        let len = data.len();
+
+        // a zero-filled tail
+        let mut file = OpenOptions::new().append(true).open(path).unwrap();
+        file.write_all(&[0; 16]).unwrap();
+        let (_, state) = Manifest::recover(path, 1 << 20).unwrap();
+        assert_eq!(state, expected_state());
+        assert_eq!(fs::metadata(path).unwrap().len(), len as u64);
+
+        // the last edit garbled
+        let mut torn = data.clone();
+        torn[len - 1] ^= 0xff;
+        fs::write(path, &torn).unwrap();
+        let (_, state) = Manifest::recover(path, 1 << 20).unwrap();
+        assert_eq!(state.version, 9);
+        assert!(fs::metadata(path).unwrap().len() < len as u64);
+    });
+}
+
+#[test]
+fn test_manifest_corrupted() {
+    manifest_test("manifest-corrupted", |path| {
+        {
+            let manifest = Manifest::create(path, 1 << 20).unwrap();
+            write_edits(&manifest);
+        }
+
+        let mut data = fs::read(path).unwrap();
+        data[10] ^= 0xff;
+        fs::write(path, &data).unwrap();
+
+        assert!(Manifest::recover(path, 1 << 20).is_err());
+
+        // an edit that passes its checksum but cannot be decoded, followed by valid ones
+        data[10] ^= 0xff;
+        data.splice(..0, [0; 8]);
+        fs::write(path, &data).unwrap();
+
+        assert!(Manifest::recover(path, 1 << 20).is_err());
+    });
+}
@@ -3,15 +3,28 @@ use bytes::Bytes;
 use crossbeam_skiplist::map::Entry;
 use crossbeam_skiplist::SkipMap;
 use ouroboros::self_referencing;
-use std::{ops::Bound, sync::Arc};
+use parking_lot::RwLock;
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
 
-use crate::iterators::StorageIterator;
+use crate::iterators::{ensure_valid, StorageIterator};
+use crate::key::{KeyBytes, KeySlice, TS_MAX, TS_MIN};
+use crate::range_tombstone::{max_covering_ts, RangeTombstone};
 use crate::sstable::builder::SSTableBuilder;
+use crate::wal::{Wal, WalSyncMode};
+use crate::write_batch::{WriteBatch, WriteBatchRecord};
 
-/// A basic mem-table based on crossbeam-skiplist
+/// A basic mem-table based on crossbeam-skiplist, keyed by internal keys. A deleted key is stored
+/// as a tombstone with `None` as its value. Range tombstones are kept apart from the keys.
 pub struct MemTable {
-    map: Arc<SkipMap<Bytes, Bytes>>,
-    estimated_size: usize,
+    map: Arc<SkipMap<KeyBytes, Option<Bytes>>>,
+    range_tombstones: RwLock<Vec<RangeTombstone>>,
+    wal: Option<Wal>,
+    id: usize,
+    estimated_size: AtomicUsize,
+    max_ts: AtomicU64,
 }
 
 pub(crate) fn map_bound(bound: Bound<&[u8]>) -> Bound<Bytes> {
@@ -22,91 +35,388 @@ pub(crate) fn map_bound(bound: Bound<&[u8]>) -> Bound<Bytes> {
     }
 }
 
+pub(crate) fn map_key_bound(bound: Bound<KeySlice<'_>>) -> Bound<KeyBytes> {
+    match bound {
+        Bound::Included(x) => Bound::Included(x.to_key_bytes()),
+        Bound::Excluded(x) => Bound::Excluded(x.to_key_bytes()),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+/// Map the lower bound of a user key range to internal keys, covering every version of the key.
+pub(crate) fn lower_key_bound(bound: Bound<&[u8]>) -> Bound<KeySlice<'_>> {
+    match bound {
+        Bound::Included(x) => Bound::Included(KeySlice::from_slice(x, TS_MAX)),
+        Bound::Excluded(x) => Bound::Excluded(KeySlice::from_slice(x, TS_MIN)),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+/// Map the upper bound of a user key range to internal keys, covering every version of the key.
+pub(crate) fn upper_key_bound(bound: Bound<&[u8]>) -> Bound<KeySlice<'_>> {
+    match bound {
+        Bound::Included(x) => Bound::Included(KeySlice::from_slice(x, TS_MIN)),
+        Bound::Excluded(x) => Bound::Excluded(KeySlice::from_slice(x, TS_MAX)),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
 impl MemTable {
     /// Create a new mem-table.
-    pub fn create() -> Self {
+    pub fn create(id: usize) -> Self {
         MemTable {
             map: Arc::new(SkipMap::new()),
-            estimated_size: 0,
+            range_tombstones: RwLock::new(Vec::new()),
+            wal: None,
+            id,
+            estimated_size: AtomicUsize::new(0),
+            max_ts: AtomicU64::new(0),
         }
     }
 
-    /// Get a value by key.
-    pub fn get(&self, key: &[u8]) -> Option<Bytes> {
-        self.map.get(key).map(|kv| kv.value().clone())
+    /// Create a new mem-table that logs every write to a WAL at `path`.
+    pub fn create_with_wal(
+        id: usize,
+        path: impl AsRef<Path>,
+        sync_mode: WalSyncMode,
+    ) -> Result<Self> {
+        Ok(MemTable {
+            map: Arc::new(SkipMap::new()),
+            range_tombstones: RwLock::new(Vec::new()),
+            wal: Some(Wal::create(path, sync_mode)?),
+            id,
+            estimated_size: AtomicUsize::new(0),
+            max_ts: AtomicU64::new(0),
+        })
+    }
+
+    /// Recreate a mem-table from the WAL at `path`. Later writes are appended to the same WAL.
+    pub fn recover_from_wal(
+        id: usize,
+        path: impl AsRef<Path>,
+        sync_mode: WalSyncMode,
+    ) -> Result<Self> {
+        let map = Arc::new(SkipMap::new());
+        let mut range_tombstones = Vec::new();
+        let wal = Wal::recover(path, sync_mode, &map, &mut range_tombstones)?;
+        let estimated_size = map
+            .iter()
+            .map(|x| x.key().raw_len() + x.value().as_ref().map_or(0, |v| v.len()))
+            .chain(range_tombstones.iter().map(range_tombstone_size))
+            .sum();
+        let max_ts = map
+            .iter()
+            .map(|x| x.key().ts())
+            .chain(range_tombstones.iter().map(|x| x.ts()))
+            .max()
+            .unwrap_or(0);
+        Ok(MemTable {
+            map,
+            range_tombstones: RwLock::new(range_tombstones),
+            wal: Some(wal),
+            id,
+            estimated_size: AtomicUsize::new(estimated_size),
+            max_ts: AtomicU64::new(max_ts),
+        })
+    }
+
+    /// Get the id of the mem-table.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Get the newest version of the user key of `key` whose timestamp is at most the timestamp
+    /// of `key`. Returns `Some(None)` if that version is a tombstone. Range tombstones are not
+    /// checked.
+    pub fn get(&self, key: KeySlice) -> Option<Option<Bytes>> {
+        self.get_with_ts(key).map(|(_, value)| value)
+    }
+
+    /// Like `get`, also returning the timestamp of the version.
+    pub fn get_with_ts(&self, key: KeySlice) -> Option<(u64, Option<Bytes>)> {
+        let lower = Bound::Included(key.to_key_bytes());
+        let entry = self.map.range((lower, Bound::Unbounded)).next()?;
+        if entry.key().key_ref() != key.key_ref() {
+            return None;
+        }
+        Some((entry.key().ts(), entry.value().clone()))
     }
 
     /// Put a key-value pair into the mem-table.
-    pub fn put(&mut self, key: &[u8], value: &[u8]) {
-        let key = Bytes::from(key.to_vec());
-        let value = Bytes::from(value.to_vec());
-        self.estimated_size += key.len() + value.len();
-        self.map.insert(key, value);
+    pub fn put(&self, key: KeySlice, value: &[u8]) -> Result<()> {
+        if let Some(wal) = &self.wal {
+            wal.put(key, value)?;
+        }
+        self.insert(key, Some(Bytes::copy_from_slice(value)));
+        Ok(())
     }
 
-    /// Get an iterator over a range of keys.
-    pub fn scan(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> MemTableIterator {
-        let (lower, upper) = (map_bound(lower), map_bound(upper));
+    /// Put a tombstone of `key` into the mem-table.
+    pub fn delete(&self, key: KeySlice) -> Result<()> {
+        if let Some(wal) = &self.wal {
+            wal.delete(key)?;
+        }
+        self.insert(key, None);
+        Ok(())
+    }
 
-        let mut iter = MemTableIteratorBuilder {
-            map: self.map.clone(),
-            iter_builder: |map| map.range((lower, upper)),
-            item: (Bytes::from_static(&[]), Bytes::from_static(&[])),
+    /// Put a range tombstone into the mem-table.
+    pub fn delete_range(&self, tombstone: RangeTombstone) -> Result<()> {
+        if let Some(wal) = &self.wal {
+            wal.delete_range(&tombstone)?;
         }
-        .build();
+        self.estimated_size
+            .fetch_add(range_tombstone_size(&tombstone), Ordering::Relaxed);
+        self.max_ts.fetch_max(tombstone.ts(), Ordering::Relaxed);
+        self.range_tombstones.write().push(tombstone);
+        Ok(())
+    }
 
-        let entry = iter.with_iter_mut(|iter| MemTableIterator::entry_to_item(iter.next()));
-        iter.with_mut(|x| *x.item = entry);
-        iter
+    /// Get the range tombstones of the mem-table.
+    pub fn range_tombstones(&self) -> Vec<RangeTombstone> {
+        self.range_tombstones.read().clone()
+    }
+
+    /// Get the newest timestamp at or below `read_ts` of the range tombstones containing `key`,
+    /// `TS_MIN` if there is none.
+    pub fn max_covering_ts(&self, key: &[u8], read_ts: u64) -> u64 {
+        max_covering_ts(self.range_tombstones.read().iter(), key, read_ts)
+    }
+
+    /// Apply a write batch, its writes get consecutive timestamps starting at `ts`. The batch is
+    /// logged to the WAL as a single record.
+    pub fn write_batch(&self, batch: &WriteBatch, ts: u64) -> Result<()> {
+        if let Some(wal) = &self.wal {
+            wal.write_batch(batch, ts)?;
+        }
+        for (record, ts) in batch.records().iter().zip(ts..) {
+            let key = KeySlice::from_slice(record.key(), ts);
+            match record {
+                WriteBatchRecord::Put(_, value) => self.insert(key, Some(value.clone())),
+                WriteBatchRecord::Delete(_) => self.insert(key, None),
+            }
+        }
+        Ok(())
+    }
+
+    fn insert(&self, key: KeySlice, value: Option<Bytes>) {
+        let size = key.raw_len() + value.as_ref().map_or(0, |v| v.len());
+        self.estimated_size.fetch_add(size, Ordering::Relaxed);
+        self.max_ts.fetch_max(key.ts(), Ordering::Relaxed);
+        self.map.insert(key.to_key_bytes(), value);
+    }
+
+    /// Make the writes to the WAL durable. Does nothing if the mem-table has no WAL.
+    pub fn sync_wal(&self) -> Result<()> {
+        if let Some(wal) = &self.wal {
+            wal.sync()?;
+        }
+        Ok(())
+    }
+
+    /// Get the estimated size of the mem-table.
+    pub fn estimated_size(&self) -> usize {
+        self.estimated_size.load(Ordering::Relaxed)
+    }
+
+    /// Get the largest timestamp of the keys and the range tombstones in the mem-table.
+    pub fn max_ts(&self) -> u64 {
+        self.max_ts.load(Ordering::Relaxed)
+    }
+
+    /// Check if the mem-table is empty.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty() && self.range_tombstones.read().is_empty()
+    }
+
+    /// Get an iterator over a range of internal keys.
+    pub fn scan(&self, lower: Bound<KeySlice<'_>>, upper: Bound<KeySlice<'_>>) -> MemTableIterator {
+        let (lower, upper) = (map_key_bound(lower), map_key_bound(upper));
+        let range = (lower.clone(), upper.clone());
+        MemTableIterator::create(self.map.clone(), range, lower, upper, false)
     }
 
     /// Flush the mem-table to SSTable.
     pub fn flush(&self, builder: &mut SSTableBuilder) -> Result<()> {
         for entry in self.map.iter() {
-            builder.add(&entry.key()[..], &entry.value()[..]);
+            match entry.value() {
+                Some(value) => builder.add(entry.key().as_key_slice(), &value[..]),
+                None => builder.add_tombstone(entry.key().as_key_slice()),
+            }
+        }
+        for tombstone in self.range_tombstones() {
+            builder.add_range_tombstone(tombstone);
         }
         Ok(())
     }
 }
 
-type SkipMapRangeIter<'a> =
-    crossbeam_skiplist::map::Range<'a, Bytes, (Bound<Bytes>, Bound<Bytes>), Bytes, Bytes>;
+fn range_tombstone_size(tombstone: &RangeTombstone) -> usize {
+    tombstone.start().len() + tombstone.end().len() + std::mem::size_of::<u64>()
+}
+
+type SkipMapRangeIter<'a> = crossbeam_skiplist::map::Range<
+    'a,
+    KeyBytes,
+    (Bound<KeyBytes>, Bound<KeyBytes>),
+    KeyBytes,
+    Option<Bytes>,
+>;
 
-/// An iterator over a range of `SkipMap`.
+/// An iterator over a range of `SkipMap`. It takes the entries from the back of the range when
+/// iterating backward.
 #[self_referencing]
 pub struct MemTableIterator {
-    map: std::sync::Arc<crossbeam_skiplist::SkipMap<Bytes, Bytes>>,
+    map: std::sync::Arc<crossbeam_skiplist::SkipMap<KeyBytes, Option<Bytes>>>,
+    /// The bounds of the scan, a seek never leaves them.
+    lower: Bound<KeyBytes>,
+    upper: Bound<KeyBytes>,
+    reverse: bool,
     #[borrows(map)]
     #[not_covariant]
     iter: SkipMapRangeIter<'this>,
-    item: (Bytes, Bytes),
+    item: Option<(KeyBytes, Option<Bytes>)>,
 }
 
 impl MemTableIterator {
-    fn entry_to_item(entry: Option<Entry<Bytes, Bytes>>) -> (Bytes, Bytes) {
-        entry
-            .map(|x| (x.key().clone(), x.value().clone()))
-            .unwrap_or_else(|| (Bytes::from_static(&[]), Bytes::from_static(&[])))
+    /// Create an iterator over `range`, which is within the scan range, positioned at its first
+    /// entry, or at its last one if `reverse` is set.
+    fn create(
+        map: Arc<SkipMap<KeyBytes, Option<Bytes>>>,
+        range: (Bound<KeyBytes>, Bound<KeyBytes>),
+        lower: Bound<KeyBytes>,
+        upper: Bound<KeyBytes>,
+        reverse: bool,
+    ) -> Self {
+        let mut iter = MemTableIteratorBuilder {
+            map,
+            lower,
+            upper,
+            reverse,
+            iter_builder: |map| map.range(range),
+            item: None,
+        }
+        .build();
+
+        iter.move_to_next_entry();
+        iter
+    }
+
+    /// Recreate the iterator over a part of the scan range.
+    fn recreate(&mut self, range: (Bound<KeyBytes>, Bound<KeyBytes>), reverse: bool) {
+        let map = self.borrow_map().clone();
+        let (lower, upper) = (self.borrow_lower().clone(), self.borrow_upper().clone());
+        *self = Self::create(map, range, lower, upper, reverse);
+    }
+
+    /// Take the next entry of the range in the direction of the iterator.
+    fn move_to_next_entry(&mut self) {
+        let reverse = *self.borrow_reverse();
+        let entry = self.with_iter_mut(|iter| {
+            let entry = if reverse {
+                iter.next_back()
+            } else {
+                iter.next()
+            };
+            MemTableIterator::entry_to_item(entry)
+        });
+        self.with_mut(|x| *x.item = entry);
+    }
+
+    /// Get the current key, which bounds the entries on one side of it.
+    fn current_bound(&self) -> Option<Bound<KeyBytes>> {
+        let item = self.borrow_item().as_ref()?;
+        Some(Bound::Excluded(item.0.clone()))
+    }
+
+    fn entry_to_item(
+        entry: Option<Entry<KeyBytes, Option<Bytes>>>,
+    ) -> Option<(KeyBytes, Option<Bytes>)> {
+        entry.map(|x| (x.key().clone(), x.value().clone()))
     }
 }
 
 impl StorageIterator for MemTableIterator {
     fn value(&self) -> &[u8] {
-        &self.borrow_item().1[..]
+        self.borrow_item()
+            .as_ref()
+            .and_then(|x| x.1.as_deref())
+            .unwrap_or_default()
     }
 
-    fn key(&self) -> &[u8] {
-        &self.borrow_item().0[..]
+    fn key(&self) -> KeySlice<'_> {
+        self.borrow_item().as_ref().unwrap().0.as_key_slice()
     }
 
     fn is_valid(&self) -> bool {
-        !self.borrow_item().0.is_empty()
+        self.borrow_item().is_some()
+    }
+
+    fn is_deleted(&self) -> bool {
+        self.borrow_item().as_ref().is_some_and(|x| x.1.is_none())
     }
 
     fn next(&mut self) -> Result<()> {
-        let entry = self.with_iter_mut(|iter| iter.next());
-        let entry = MemTableIterator::entry_to_item(entry);
-        self.with_mut(|x| *x.item = entry);
+        ensure_valid(self)?;
+        if *self.borrow_reverse() {
+            if let Some(current) = self.current_bound() {
+                self.recreate((current, self.borrow_upper().clone()), false);
+            }
+            return Result::Ok(());
+        }
+        self.move_to_next_entry();
+        Result::Ok(())
+    }
+
+    fn seek(&mut self, key: KeySlice) -> Result<()> {
+        let key = key.to_key_bytes();
+        let start = match self.borrow_lower() {
+            Bound::Included(lower) | Bound::Excluded(lower) if *lower >= key => {
+                self.borrow_lower().clone()
+            }
+            _ => Bound::Included(key),
+        };
+        self.recreate((start, self.borrow_upper().clone()), false);
+        Result::Ok(())
+    }
+
+    fn seek_to_first(&mut self) -> Result<()> {
+        self.recreate(
+            (self.borrow_lower().clone(), self.borrow_upper().clone()),
+            false,
+        );
+        Result::Ok(())
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        ensure_valid(self)?;
+        if !*self.borrow_reverse() {
+            if let Some(current) = self.current_bound() {
+                self.recreate((self.borrow_lower().clone(), current), true);
+            }
+            return Result::Ok(());
+        }
+        self.move_to_next_entry();
+        Result::Ok(())
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        self.recreate(
+            (self.borrow_lower().clone(), self.borrow_upper().clone()),
+            true,
+        );
+        Result::Ok(())
+    }
+
+    fn seek_for_prev(&mut self, key: KeySlice) -> Result<()> {
+        let key = key.to_key_bytes();
+        let end = match self.borrow_upper() {
+            Bound::Included(upper) | Bound::Excluded(upper) if *upper <= key => {
+                self.borrow_upper().clone()
+            }
+            _ => Bound::Included(key),
+        };
+        self.recreate((self.borrow_lower().clone(), end), true);
         Result::Ok(())
     }
 }
@@ -5,9 +5,14 @@ use bytes::Bytes;
 use super::MemTable;
 use crate::{
     iterators::StorageIterator,
+    key::{KeySlice, TS_DEFAULT},
     sstable::{builder::SSTableBuilder, iterator::SSTableIterator},
 };
 
+fn ks(key: &[u8]) -> KeySlice<'_> {
+    KeySlice::from_slice(key, TS_DEFAULT)
+}
+
 fn key_of(val: usize) -> Vec<u8> {
     format!("key_{:05}", val).into_bytes()
 }
@@ -40,40 +45,76 @@ fn assert_kv(i: usize, key: &[u8], value: &[u8]) {
 
 #[test]
 fn test_memtable_get() {
-    let mut memtable = MemTable::create();
+    let memtable = MemTable::create(0);
 
     for i in 0..100 {
         let key = key_of(i);
         let val = value_of(i);
-        memtable.put(&key, &val);
+        memtable.put(ks(&key), &val).unwrap();
     }
 
     for i in 0..100 {
         let key = key_of(i);
-        let val = memtable.get(&key).unwrap();
+        let val = memtable.get(ks(&key)).unwrap().unwrap();
         assert_eq!(val, value_of(i));
     }
 
     for i in 0..50 {
         let key = key_of(i);
         let val = value_of(i + 100);
-        memtable.put(&key, &val);
+        memtable.put(ks(&key), &val).unwrap();
     }
 
     for i in 0..50 {
         let key = key_of(i);
-        let val = memtable.get(&key).unwrap();
+        let val = memtable.get(ks(&key)).unwrap().unwrap();
         assert_eq!(val, value_of(i + 100));
     }
 }
 
+#[test]
+fn test_memtable_delete() {
+    let memtable = MemTable::create(0);
+    for i in 0..100 {
+        memtable.put(ks(&key_of(i)), &value_of(i)).unwrap();
+    }
+    for i in (0..100).step_by(2) {
+        memtable.delete(ks(&key_of(i))).unwrap();
+    }
+    memtable.put(ks(b""), b"").unwrap();
+
+    for i in 0..100 {
+        let val = memtable.get(ks(&key_of(i))).unwrap();
+        if i % 2 == 0 {
+            assert!(val.is_none(), "{i}");
+        } else {
+            assert_eq!(val.unwrap(), value_of(i));
+        }
+    }
+    assert_eq!(memtable.get(ks(b"")).unwrap().unwrap(), Bytes::new());
+    assert!(memtable.get(ks(b"missing")).is_none());
+
+    let mut iter = memtable.scan(Bound::Unbounded, Bound::Unbounded);
+    assert!(iter.is_valid());
+    assert_eq!(iter.key().key_ref(), b"");
+    assert!(!iter.is_deleted());
+    iter.next().unwrap();
+    for i in 0..100 {
+        assert!(iter.is_valid(), "{i}");
+        assert_eq!(iter.key().key_ref(), key_of(i));
+        assert_eq!(iter.is_deleted(), i % 2 == 0);
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
 #[test]
 fn test_memtable_iter() {
-    let mut memtable = MemTable::create();
+    let memtable = MemTable::create(0);
     for i in 0..100 {
         let key = key_of(i);
         let val = value_of(i);
-        memtable.put(&key, &val);
+        memtable.put(ks(&key), &val).unwrap();
     }
 
     {
@@ -88,7 +129,7 @@ fn test_memtable_iter() {
     }
 
     for idx in 0..100 {
-        let mut iter = memtable.scan(Bound::Included(&key_of(idx)), Bound::Unbounded);
+        let mut iter = memtable.scan(Bound::Included(ks(&key_of(idx))), Bound::Unbounded);
         for i in idx..100 {
             assert!(iter.is_valid(), "{i}");
             let key = key_of(i);
@@ -99,7 +140,10 @@ fn test_memtable_iter() {
     }
 
     {
-        let mut iter = memtable.scan(Bound::Included(&key_of(12)), Bound::Excluded(&key_of(46)));
+        let mut iter = memtable.scan(
+            Bound::Included(ks(&key_of(12))),
+            Bound::Excluded(ks(&key_of(46))),
+        );
         for i in 12..46 {
             assert!(iter.is_valid(), "{i}");
             let key = key_of(i);
@@ -111,15 +155,88 @@ fn test_memtable_iter() {
     }
 }
 
+#[test]
+fn test_memtable_iter_seek() {
+    let memtable = MemTable::create(0);
+    for i in 0..100 {
+        memtable.put(ks(&key_of(i)), &value_of(i)).unwrap();
+    }
+
+    let mut iter = memtable.scan(
+        Bound::Excluded(ks(&key_of(12))),
+        Bound::Included(ks(&key_of(46))),
+    );
+    for (seek, expected) in [(30, 30), (20, 20), (12, 13), (0, 13)] {
+        iter.seek(ks(&key_of(seek))).unwrap();
+        assert!(iter.is_valid(), "{seek}");
+        assert_kv(expected, iter.key().key_ref(), iter.value());
+    }
+
+    // a seek never leaves the scan range
+    iter.seek(ks(&key_of(47))).unwrap();
+    assert!(!iter.is_valid());
+    iter.seek_to_first().unwrap();
+    for i in 13..=46 {
+        assert!(iter.is_valid(), "{i}");
+        assert_kv(i, iter.key().key_ref(), iter.value());
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_memtable_iter_reverse() {
+    let memtable = MemTable::create(0);
+    for i in 0..100 {
+        memtable.put(ks(&key_of(i)), &value_of(i)).unwrap();
+    }
+
+    let mut iter = memtable.scan(
+        Bound::Excluded(ks(&key_of(12))),
+        Bound::Included(ks(&key_of(46))),
+    );
+    iter.seek_to_last().unwrap();
+    for i in (13..=46).rev() {
+        assert!(iter.is_valid(), "{i}");
+        assert_kv(i, iter.key().key_ref(), iter.value());
+        iter.prev().unwrap();
+    }
+    assert!(!iter.is_valid());
+
+    // a seek never leaves the scan range
+    for (seek, expected) in [(30, 30), (99, 46), (46, 46), (13, 13)] {
+        iter.seek_for_prev(ks(&key_of(seek))).unwrap();
+        assert!(iter.is_valid(), "{seek}");
+        assert_kv(expected, iter.key().key_ref(), iter.value());
+    }
+    iter.seek_for_prev(ks(&key_of(12))).unwrap();
+    assert!(!iter.is_valid());
+
+    // changing direction
+    iter.seek(ks(&key_of(20))).unwrap();
+    for i in [21, 22] {
+        iter.next().unwrap();
+        assert_kv(i, iter.key().key_ref(), iter.value());
+    }
+    for i in [21, 20, 19] {
+        iter.prev().unwrap();
+        assert_kv(i, iter.key().key_ref(), iter.value());
+    }
+    iter.next().unwrap();
+    assert_kv(20, iter.key().key_ref(), iter.value());
+}
+
 #[test]
 fn test_memtable_to_sst() {
-    let mut memtable = MemTable::create();
+    let memtable = MemTable::create(0);
     for i in 0..100 {
         let key = key_of(i);
         let val = value_of(i);
-        memtable.put(&key, &val);
+        memtable.put(ks(&key), &val).unwrap();
     }
 
+    memtable.delete(ks(&key_of(100))).unwrap();
+
     let mut builder = SSTableBuilder::new(100);
     memtable.flush(&mut builder).unwrap();
     let sst = builder.build(1, None, Path::new("./tmp/test")).unwrap();
@@ -129,6 +246,56 @@ fn test_memtable_to_sst() {
         let key = key_of(i);
         let val = value_of(i);
         assert_kv(i, &key, &val);
+        assert!(!iter.is_deleted());
+        iter.next().unwrap();
+    }
+    assert!(iter.is_valid());
+    assert_eq!(iter.key().key_ref(), key_of(100));
+    assert!(iter.is_deleted());
+    iter.next().unwrap();
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_memtable_versions() {
+    let memtable = MemTable::create(0);
+    for ts in 1..=3 {
+        for i in 0..10 {
+            let key = key_of(i);
+            memtable
+                .put(
+                    KeySlice::from_slice(&key, ts),
+                    &value_of(i * 10 + ts as usize),
+                )
+                .unwrap();
+        }
+    }
+    memtable
+        .delete(KeySlice::from_slice(&key_of(5), 4))
+        .unwrap();
+    assert_eq!(memtable.max_ts(), 4);
+
+    // the newest version at or below the timestamp
+    for i in 0..10 {
+        assert!(memtable.get(KeySlice::from_slice(&key_of(i), 0)).is_none());
+        for ts in 1..=5 {
+            let value = memtable.get(KeySlice::from_slice(&key_of(i), ts)).unwrap();
+            if i == 5 && ts >= 4 {
+                assert!(value.is_none());
+            } else {
+                assert_eq!(value.unwrap(), value_of(i * 10 + ts.min(3) as usize));
+            }
+        }
+    }
+
+    let mut iter = memtable.scan(
+        Bound::Included(KeySlice::from_slice(&key_of(5), u64::MAX)),
+        Bound::Included(KeySlice::from_slice(&key_of(6), 2)),
+    );
+    let expected = [(5, 4), (5, 3), (5, 2), (5, 1), (6, 3), (6, 2)];
+    for (i, ts) in expected {
+        assert_eq!(iter.key(), KeySlice::from_slice(&key_of(i), ts));
+        assert_eq!(iter.is_deleted(), ts == 4);
         iter.next().unwrap();
     }
     assert!(!iter.is_valid());
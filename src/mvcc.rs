@@ -0,0 +1,53 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use crate::lsm_iterator::LsmIterator;
+use crate::lsm_storage::LsmStorageInner;
+
+/// Serializable transactions
+pub mod txn;
+/// Watermark of the live snapshots
+pub mod watermark;
+
+pub use watermark::Watermark;
+
+/// A consistent view of the storage engine at a read timestamp. The versions visible to a live
+/// snapshot are kept by compactions, dropping the snapshot lets them be garbage-collected.
+pub struct Snapshot {
+    inner: Arc<LsmStorageInner>,
+    read_ts: u64,
+}
+
+impl Snapshot {
+    pub(crate) fn new(inner: Arc<LsmStorageInner>) -> Self {
+        let read_ts = inner.acquire_read_ts();
+        Self { inner, read_ts }
+    }
+
+    /// Get the read timestamp. Every write at or below it is visible to the snapshot.
+    pub fn read_ts(&self) -> u64 {
+        self.read_ts
+    }
+
+    /// Get a value by key. Returns `None` if the key does not exist or has been deleted.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        self.inner.get_at(key, self.read_ts)
+    }
+
+    /// Create an iterator over a range of keys.
+    pub fn scan(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<LsmIterator> {
+        self.inner.scan_at(lower, upper, self.read_ts)
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        self.inner.release_read_ts(self.read_ts);
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,284 @@
+use std::fs;
+use std::ops::Bound;
+
+use bytes::Bytes;
+
+use super::Watermark;
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
+use crate::wal::WalSyncMode;
+
+fn storage_test<T>(name: &str, test: T)
+where
+    T: Fn(&LsmStorage),
+{
+    let path = format!("./tmp/{name}");
+    _ = fs::remove_dir_all(&path);
+
+    let options = LsmStorageOptions {
+        wal_sync_mode: WalSyncMode::NoSync,
+        ..Default::default()
+    };
+    let storage = LsmStorage::open(&path, options).unwrap();
+
+    test(&storage);
+
+    drop(storage);
+    fs::remove_dir_all(&path).unwrap();
+}
+
+fn collect<I: StorageIterator>(mut iter: I) -> Vec<(Bytes, Bytes)> {
+    let mut result = Vec::new();
+    while iter.is_valid() {
+        result.push((
+            Bytes::copy_from_slice(iter.key().key_ref()),
+            Bytes::copy_from_slice(iter.value()),
+        ));
+        iter.next().unwrap();
+    }
+    result
+}
+
+fn kv(key: &str, value: &str) -> (Bytes, Bytes) {
+    (
+        Bytes::copy_from_slice(key.as_bytes()),
+        Bytes::copy_from_slice(value.as_bytes()),
+    )
+}
+
+#[test]
+fn test_watermark() {
+    let mut watermark = Watermark::new();
+    assert_eq!(watermark.watermark(), None);
+
+    watermark.add_reader(3);
+    watermark.add_reader(1);
+    watermark.add_reader(3);
+    watermark.add_reader(2);
+    assert_eq!(watermark.watermark(), Some(1));
+    assert_eq!(watermark.num_retained_snapshots(), 4);
+
+    watermark.remove_reader(1);
+    assert_eq!(watermark.watermark(), Some(2));
+    watermark.remove_reader(2);
+    watermark.remove_reader(3);
+    assert_eq!(watermark.watermark(), Some(3));
+    assert_eq!(watermark.num_retained_snapshots(), 1);
+
+    // removing an unknown reader does nothing
+    watermark.remove_reader(5);
+    assert_eq!(watermark.watermark(), Some(3));
+
+    watermark.remove_reader(3);
+    assert_eq!(watermark.watermark(), None);
+    assert_eq!(watermark.num_retained_snapshots(), 0);
+}
+
+#[test]
+fn test_txn_read_own_writes() {
+    storage_test("txn-read-own-writes", |storage| {
+        storage.put(b"a", b"1").unwrap();
+        storage.put(b"b", b"2").unwrap();
+        storage.put(b"d", b"4").unwrap();
+
+        let txn = storage.new_txn();
+        txn.put(b"a", b"10").unwrap();
+        txn.delete(b"b").unwrap();
+        txn.put(b"c", b"3").unwrap();
+        txn.put(b"e", b"").unwrap();
+        txn.delete(b"f").unwrap();
+
+        assert_eq!(txn.get(b"a").unwrap().unwrap(), "10");
+        assert!(txn.get(b"b").unwrap().is_none());
+        assert_eq!(txn.get(b"c").unwrap().unwrap(), "3");
+        assert_eq!(txn.get(b"d").unwrap().unwrap(), "4");
+        assert_eq!(txn.get(b"e").unwrap().unwrap(), "");
+        assert!(txn.get(b"f").unwrap().is_none());
+        assert_eq!(
+            collect(txn.scan(Bound::Unbounded, Bound::Unbounded).unwrap()),
+            vec![kv("a", "10"), kv("c", "3"), kv("d", "4"), kv("e", "")]
+        );
+        assert_eq!(
+            collect(
+                txn.scan(Bound::Excluded(b"a"), Bound::Included(b"d"))
+                    .unwrap()
+            ),
+            vec![kv("c", "3"), kv("d", "4")]
+        );
+
+        // the writes are invisible until the commit
+        assert_eq!(storage.get(b"a").unwrap().unwrap(), "1");
+        assert!(storage.get(b"c").unwrap().is_none());
+        txn.commit().unwrap();
+        assert_eq!(
+            collect(storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap()),
+            vec![kv("a", "10"), kv("c", "3"), kv("d", "4"), kv("e", "")]
+        );
+
+        assert!(txn.get(b"a").is_err());
+        assert!(txn.put(b"a", b"1").is_err());
+        assert!(txn.commit().is_err());
+    });
+}
+
+#[test]
+fn test_txn_scan_reverse() {
+    storage_test("txn-scan-reverse", |storage| {
+        for key in ["a", "b", "d", "f"] {
+            storage.put(key.as_bytes(), key.as_bytes()).unwrap();
+        }
+
+        let txn = storage.new_txn();
+        txn.put(b"a", b"1").unwrap();
+        txn.delete(b"b").unwrap();
+        txn.put(b"c", b"3").unwrap();
+        txn.put(b"g", b"7").unwrap();
+
+        let mut iter = txn.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+        iter.seek_to_last().unwrap();
+        let mut result = Vec::new();
+        while iter.is_valid() {
+            result.push(String::from_utf8(iter.key().key_ref().to_vec()).unwrap());
+            iter.prev().unwrap();
+        }
+        assert_eq!(result, ["g", "f", "d", "c", "a"]);
+
+        // changing direction
+        iter.seek_for_prev(KeySlice::from_slice(b"e", 0)).unwrap();
+        for (forward, key, value) in [(false, "c", "3"), (true, "d", "d"), (true, "f", "f")] {
+            if forward {
+                iter.next().unwrap();
+            } else {
+                iter.prev().unwrap();
+            }
+            assert_eq!(iter.key().key_ref(), key.as_bytes());
+            assert_eq!(iter.value(), value.as_bytes());
+        }
+        iter.prev().unwrap();
+        iter.prev().unwrap();
+        assert_eq!(iter.key().key_ref(), b"c");
+    });
+}
+
+#[test]
+fn test_txn_snapshot_isolation() {
+    storage_test("txn-snapshot-isolation", |storage| {
+        storage.put(b"a", b"1").unwrap();
+        let txn1 = storage.new_txn();
+        storage.put(b"a", b"2").unwrap();
+        storage.put(b"b", b"2").unwrap();
+        let txn2 = storage.new_txn();
+        storage.delete(b"a").unwrap();
+
+        assert_eq!(txn1.get(b"a").unwrap().unwrap(), "1");
+        assert!(txn1.get(b"b").unwrap().is_none());
+        assert_eq!(
+            collect(txn2.scan(Bound::Unbounded, Bound::Unbounded).unwrap()),
+            vec![kv("a", "2"), kv("b", "2")]
+        );
+        assert!(storage.get(b"a").unwrap().is_none());
+    });
+}
+
+#[test]
+fn test_txn_conflict() {
+    storage_test("txn-conflict", |storage| {
+        storage.put(b"x", b"0").unwrap();
+        storage.put(b"y", b"0").unwrap();
+
+        // read-modify-write of the same key
+        let txn1 = storage.new_txn();
+        let txn2 = storage.new_txn();
+        txn1.get(b"x").unwrap();
+        txn2.get(b"x").unwrap();
+        txn1.put(b"x", b"1").unwrap();
+        txn2.put(b"x", b"2").unwrap();
+        txn1.commit().unwrap();
+        assert!(txn2.commit().is_err());
+        assert_eq!(storage.get(b"x").unwrap().unwrap(), "1");
+
+        // write skew: each transaction reads the key the other one writes
+        let txn1 = storage.new_txn();
+        let txn2 = storage.new_txn();
+        txn1.get(b"x").unwrap();
+        txn2.get(b"y").unwrap();
+        txn1.put(b"y", b"1").unwrap();
+        txn2.put(b"x", b"2").unwrap();
+        txn1.commit().unwrap();
+        assert!(txn2.commit().is_err());
+
+        // keys read by a scan are checked too
+        let txn1 = storage.new_txn();
+        let txn2 = storage.new_txn();
+        assert_eq!(
+            collect(txn1.scan(Bound::Unbounded, Bound::Unbounded).unwrap()).len(),
+            2
+        );
+        txn1.put(b"z", b"1").unwrap();
+        txn2.put(b"y", b"2").unwrap();
+        txn2.commit().unwrap();
+        assert!(txn1.commit().is_err());
+
+        // phantom: each transaction scans an empty range the other one writes into
+        let txn1 = storage.new_txn();
+        let txn2 = storage.new_txn();
+        let range = (Bound::Included(&b"a"[..]), Bound::Excluded(&b"b"[..]));
+        assert!(collect(txn1.scan(range.0, range.1).unwrap()).is_empty());
+        assert!(collect(txn2.scan(range.0, range.1).unwrap()).is_empty());
+        txn1.put(b"a1", b"1").unwrap();
+        txn2.put(b"a2", b"2").unwrap();
+        txn1.commit().unwrap();
+        assert!(txn2.commit().is_err());
+        assert_eq!(storage.get(b"a2").unwrap(), None);
+
+        // a write outside of the scanned range does not conflict
+        let txn1 = storage.new_txn();
+        let txn2 = storage.new_txn();
+        assert_eq!(
+            collect(txn1.scan(range.0, range.1).unwrap()),
+            vec![kv("a1", "1")]
+        );
+        txn1.put(b"a3", b"3").unwrap();
+        txn2.put(b"b", b"3").unwrap();
+        txn2.commit().unwrap();
+        txn1.commit().unwrap();
+        storage.delete(b"a1").unwrap();
+        storage.delete(b"a3").unwrap();
+        storage.delete(b"b").unwrap();
+
+        // a blind write conflicts with the readers of its keys, read-only transactions commit
+        let txn1 = storage.new_txn();
+        let txn2 = storage.new_txn();
+        let txn3 = storage.new_txn();
+        txn1.get(b"x").unwrap();
+        txn1.put(b"x", b"3").unwrap();
+        txn2.put(b"x", b"4").unwrap();
+        txn2.put(b"y", b"4").unwrap();
+        txn3.get(b"x").unwrap();
+        txn2.commit().unwrap();
+        assert!(txn1.commit().is_err());
+        txn3.commit().unwrap();
+
+        // disjoint reads and writes
+        let txn1 = storage.new_txn();
+        let txn2 = storage.new_txn();
+        txn1.get(b"x").unwrap();
+        txn1.put(b"x", b"5").unwrap();
+        txn2.get(b"y").unwrap();
+        txn2.put(b"y", b"5").unwrap();
+        txn1.commit().unwrap();
+        txn2.commit().unwrap();
+        assert_eq!(
+            collect(storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap()),
+            vec![kv("x", "5"), kv("y", "5")]
+        );
+
+        // a transaction started after a commit does not conflict with it
+        let txn1 = storage.new_txn();
+        txn1.get(b"x").unwrap();
+        txn1.put(b"x", b"6").unwrap();
+        txn1.commit().unwrap();
+        assert_eq!(storage.get(b"x").unwrap().unwrap(), "6");
+    });
+}
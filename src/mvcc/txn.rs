@@ -0,0 +1,330 @@
+use std::collections::HashSet;
+use std::ops::{Bound, RangeBounds};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use parking_lot::Mutex;
+
+use crate::iterators::{ensure_valid, step, step_if, Poison, Poisonable, StorageIterator};
+use crate::key::{KeySlice, TS_DEFAULT, TS_MAX, TS_MIN};
+use crate::lsm_iterator::LsmIterator;
+use crate::lsm_storage::LsmStorageInner;
+use crate::mem_table::{lower_key_bound, map_bound, upper_key_bound, MemTable, MemTableIterator};
+use crate::mvcc::Snapshot;
+use crate::write_batch::WriteBatch;
+
+/// A serializable optimistic transaction. Reads see a snapshot taken when the transaction starts
+/// together with its own writes, which are buffered until `commit`. The commit fails if a
+/// transaction committed since the start wrote a key this one read, or a key within a range this
+/// one scanned. Writes outside transactions are not checked for conflicts.
+pub struct Transaction {
+    inner: Arc<LsmStorageInner>,
+    snapshot: Snapshot,
+    /// Buffered writes at `TS_DEFAULT`, a deleted key is buffered as a tombstone.
+    local_storage: MemTable,
+    /// Keys read from the snapshot.
+    read_set: Mutex<HashSet<Bytes>>,
+    /// Ranges scanned, a key written into one of them would change the scan.
+    scan_ranges: Mutex<Vec<(Bound<Bytes>, Bound<Bytes>)>>,
+    committed: AtomicBool,
+}
+
+impl Transaction {
+    pub(crate) fn new(inner: Arc<LsmStorageInner>) -> Self {
+        Self {
+            snapshot: Snapshot::new(Arc::clone(&inner)),
+            inner,
+            local_storage: MemTable::create(0),
+            read_set: Mutex::new(HashSet::new()),
+            scan_ranges: Mutex::new(Vec::new()),
+            committed: AtomicBool::new(false),
+        }
+    }
+
+    /// Get the read timestamp of the transaction.
+    pub fn read_ts(&self) -> u64 {
+        self.snapshot.read_ts()
+    }
+
+    /// Get a value by key, the writes of the transaction included.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        self.check_active()?;
+        if let Some(value) = self.local_storage.get(KeySlice::from_slice(key, TS_MAX)) {
+            return Ok(value);
+        }
+        self.read_set.lock().insert(Bytes::copy_from_slice(key));
+        self.snapshot.get(key)
+    }
+
+    /// Buffer a put of a key-value pair.
+    pub fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.check_active()?;
+        self.local_storage
+            .put(KeySlice::from_slice(key, TS_DEFAULT), value)
+    }
+
+    /// Buffer a delete of a key.
+    pub fn delete(&self, key: &[u8]) -> Result<()> {
+        self.check_active()?;
+        self.local_storage
+            .delete(KeySlice::from_slice(key, TS_DEFAULT))
+    }
+
+    /// Create an iterator over a range of keys, the writes of the transaction included.
+    pub fn scan(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<TxnIterator<'_>> {
+        self.check_active()?;
+        let local = self
+            .local_storage
+            .scan(lower_key_bound(lower), upper_key_bound(upper));
+        let storage = self.snapshot.scan(lower, upper)?;
+        self.scan_ranges
+            .lock()
+            .push((map_bound(lower), map_bound(upper)));
+        TxnIterator::create(self, local, storage)
+    }
+
+    /// Validate the transaction against the transactions committed since it started, then apply
+    /// its buffered writes as a write batch. A transaction without writes always commits.
+    /// The transaction cannot be used afterwards, even if the commit fails.
+    pub fn commit(&self) -> Result<()> {
+        if self.committed.swap(true, Ordering::SeqCst) {
+            bail!("transaction is already committed");
+        }
+
+        let mut batch = WriteBatch::new();
+        let mut iter = self.local_storage.scan(Bound::Unbounded, Bound::Unbounded);
+        while iter.is_valid() {
+            if iter.is_deleted() {
+                batch.delete(iter.key().key_ref());
+            } else {
+                batch.put(iter.key().key_ref(), iter.value());
+            }
+            iter.next()?;
+        }
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        let mut committed_txns = self.inner.committed_txns.lock();
+        let read_set = self.read_set.lock();
+        let scan_ranges = self.scan_ranges.lock();
+        for keys in committed_txns
+            .range(self.read_ts() + 1..)
+            .map(|(_, keys)| keys)
+        {
+            if let Some(key) = read_set.iter().find(|key| keys.contains(*key)) {
+                bail!(
+                    "transaction conflicts with a committed transaction on key {:?}",
+                    key
+                );
+            }
+            if let Some(key) = keys
+                .iter()
+                .find(|key| scan_ranges.iter().any(|range| range.contains(*key)))
+            {
+                bail!(
+                    "transaction conflicts with a committed write in a scanned range on key {:?}",
+                    key
+                );
+            }
+        }
+
+        let commit_ts = self.inner.write_batch(&batch)?;
+        let keys = batch.records().iter().map(|record| record.key().clone());
+        committed_txns.insert(commit_ts, keys.collect());
+        // Transactions starting from now read at or above the watermark.
+        let watermark = self.inner.gc_watermark();
+        committed_txns.retain(|ts, _| *ts > watermark);
+        Ok(())
+    }
+
+    fn check_active(&self) -> Result<()> {
+        if self.committed.load(Ordering::SeqCst) {
+            bail!("transaction is already committed");
+        }
+        Ok(())
+    }
+}
+
+/// An iterator over the writes of a transaction merged with its snapshot. The writes take
+/// precedence over the snapshot and their tombstones are skipped. The keys read from the snapshot
+/// are added to the read set of the transaction.
+pub struct TxnIterator<'a> {
+    txn: &'a Transaction,
+    local: MemTableIterator,
+    storage: LsmIterator,
+    /// The current entry is a write of the transaction.
+    use_local: bool,
+    reverse: bool,
+    poison: Poison,
+}
+
+impl<'a> TxnIterator<'a> {
+    fn create(txn: &'a Transaction, local: MemTableIterator, storage: LsmIterator) -> Result<Self> {
+        let mut iter = Self {
+            txn,
+            local,
+            storage,
+            use_local: false,
+            reverse: false,
+            poison: Poison::default(),
+        };
+        iter.move_to_visible()?;
+        Ok(iter)
+    }
+
+    fn choose(&mut self) {
+        self.use_local = self.local.is_valid()
+            && (!self.storage.is_valid() || {
+                let order = self.local.key().key_ref().cmp(self.storage.key().key_ref());
+                if self.reverse {
+                    order.is_ge()
+                } else {
+                    order.is_le()
+                }
+            });
+    }
+
+    fn next_inner(&mut self) -> Result<()> {
+        if !self.use_local {
+            return step(&mut self.storage, self.reverse);
+        }
+        // The write hides the version of the key in the snapshot.
+        let key = self.local.key().key_ref().to_vec();
+        step_if(&mut self.storage, self.reverse, |x| x.key_ref() == key)?;
+        step(&mut self.local, self.reverse)
+    }
+
+    /// Change the direction of the iteration, both iterators move past the current key.
+    fn switch_direction(&mut self) -> Result<()> {
+        let key = self.key().key_ref().to_vec();
+        self.reverse = !self.reverse;
+        if self.reverse {
+            self.local
+                .seek_for_prev(KeySlice::from_slice(&key, TS_MIN))?;
+            self.storage
+                .seek_for_prev(KeySlice::from_slice(&key, TS_MIN))?;
+        } else {
+            self.local.seek(KeySlice::from_slice(&key, TS_MAX))?;
+            self.storage.seek(KeySlice::from_slice(&key, TS_MAX))?;
+        }
+        step_if(&mut self.local, self.reverse, |x| x.key_ref() == key)?;
+        step_if(&mut self.storage, self.reverse, |x| x.key_ref() == key)
+    }
+
+    /// Skip the tombstones of the transaction and record the current key if it is read from the
+    /// snapshot.
+    fn move_to_visible(&mut self) -> Result<()> {
+        loop {
+            self.choose();
+            if !self.use_local || !self.local.is_deleted() {
+                break;
+            }
+            self.next_inner()?;
+        }
+        if !self.use_local && self.storage.is_valid() {
+            let key = Bytes::copy_from_slice(self.storage.key().key_ref());
+            self.txn.read_set.lock().insert(key);
+        }
+        Ok(())
+    }
+}
+
+impl Poisonable for TxnIterator<'_> {
+    fn poison(&mut self) -> &mut Poison {
+        &mut self.poison
+    }
+}
+
+impl StorageIterator for TxnIterator<'_> {
+    fn value(&self) -> &[u8] {
+        if self.use_local {
+            self.local.value()
+        } else {
+            self.storage.value()
+        }
+    }
+
+    fn key(&self) -> KeySlice<'_> {
+        if self.use_local {
+            self.local.key()
+        } else {
+            self.storage.key()
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        !self.poison.is_poisoned() && (self.use_local || self.storage.is_valid())
+    }
+
+    fn is_deleted(&self) -> bool {
+        false
+    }
+
+    fn next(&mut self) -> Result<()> {
+        ensure_valid(self)?;
+        Poison::guard(self, |iter| {
+            if iter.reverse {
+                iter.switch_direction()?;
+            } else {
+                iter.next_inner()?;
+            }
+            iter.move_to_visible()
+        })
+    }
+
+    /// Only the user key of `key` is used, the writes of the transaction are stored at
+    /// `TS_DEFAULT`.
+    fn seek(&mut self, key: KeySlice) -> Result<()> {
+        Poison::guard(self, |iter| {
+            iter.local
+                .seek(KeySlice::from_slice(key.key_ref(), TS_MAX))?;
+            iter.storage.seek(key)?;
+            iter.reverse = false;
+            iter.move_to_visible()
+        })
+    }
+
+    fn seek_to_first(&mut self) -> Result<()> {
+        Poison::guard(self, |iter| {
+            iter.local.seek_to_first()?;
+            iter.storage.seek_to_first()?;
+            iter.reverse = false;
+            iter.move_to_visible()
+        })
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        ensure_valid(self)?;
+        Poison::guard(self, |iter| {
+            if iter.reverse {
+                iter.next_inner()?;
+            } else {
+                iter.switch_direction()?;
+            }
+            iter.move_to_visible()
+        })
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        Poison::guard(self, |iter| {
+            iter.local.seek_to_last()?;
+            iter.storage.seek_to_last()?;
+            iter.reverse = true;
+            iter.move_to_visible()
+        })
+    }
+
+    /// Only the user key of `key` is used.
+    fn seek_for_prev(&mut self, key: KeySlice) -> Result<()> {
+        Poison::guard(self, |iter| {
+            iter.local
+                .seek_for_prev(KeySlice::from_slice(key.key_ref(), TS_MIN))?;
+            iter.storage.seek_for_prev(key)?;
+            iter.reverse = true;
+            iter.move_to_visible()
+        })
+    }
+}
@@ -0,0 +1,42 @@
+use std::collections::BTreeMap;
+
+/// Tracks the read timestamps of the live snapshots. The watermark is the lowest of them: the
+/// versions of a key older than its newest version at or below the watermark are not visible to
+/// any snapshot.
+#[derive(Debug, Default)]
+pub struct Watermark {
+    /// Number of snapshots by read timestamp.
+    readers: BTreeMap<u64, usize>,
+}
+
+impl Watermark {
+    /// Create a watermark without snapshots.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a snapshot at `ts`.
+    pub fn add_reader(&mut self, ts: u64) {
+        *self.readers.entry(ts).or_default() += 1;
+    }
+
+    /// Remove a snapshot at `ts`.
+    pub fn remove_reader(&mut self, ts: u64) {
+        if let Some(count) = self.readers.get_mut(&ts) {
+            *count -= 1;
+            if *count == 0 {
+                self.readers.remove(&ts);
+            }
+        }
+    }
+
+    /// Get the lowest read timestamp, `None` if there is no snapshot.
+    pub fn watermark(&self) -> Option<u64> {
+        self.readers.keys().next().copied()
+    }
+
+    /// Get the number of live snapshots.
+    pub fn num_retained_snapshots(&self) -> usize {
+        self.readers.values().sum()
+    }
+}
@@ -0,0 +1,133 @@
+use std::fmt::Debug;
+
+use anyhow::{bail, Result};
+use bytes::{Buf, BufMut, Bytes};
+
+use crate::block::{get_varint, put_varint};
+use crate::key::{KeyBytes, TS_MAX, TS_MIN};
+
+/// A range tombstone deletes the versions of the keys in `[start, end)` older than its
+/// timestamp.
+#[derive(Clone, PartialEq, Eq)]
+pub struct RangeTombstone {
+    start: Bytes,
+    end: Bytes,
+    ts: u64,
+}
+
+impl RangeTombstone {
+    /// Create a tombstone of the keys in `[start, end)` at `ts`.
+    pub fn new(start: Bytes, end: Bytes, ts: u64) -> Self {
+        Self { start, end, ts }
+    }
+
+    /// Get the first deleted key.
+    pub fn start(&self) -> &Bytes {
+        &self.start
+    }
+
+    /// Get the end of the range, which is not deleted.
+    pub fn end(&self) -> &Bytes {
+        &self.end
+    }
+
+    /// Get the timestamp.
+    pub fn ts(&self) -> u64 {
+        self.ts
+    }
+
+    /// Check if `key` is in the range.
+    pub fn contains(&self, key: &[u8]) -> bool {
+        &self.start[..] <= key && key < &self.end[..]
+    }
+
+    /// Check if the tombstone deletes the version of `key` at `ts`.
+    pub fn covers(&self, key: &[u8], ts: u64) -> bool {
+        ts < self.ts && self.contains(key)
+    }
+
+    /// Check if the range overlaps the keys from `lower` to `upper`, both included.
+    pub fn overlaps(&self, lower: &[u8], upper: &[u8]) -> bool {
+        &self.start[..] <= upper && lower < &self.end[..]
+    }
+
+    /// Clip the range to `[lower, upper)`, `None` standing for no bound. Returns `None` if nothing
+    /// is left.
+    pub(crate) fn clip(&self, lower: Option<&Bytes>, upper: Option<&Bytes>) -> Option<Self> {
+        let start = match lower {
+            Some(lower) if *lower > self.start => lower.clone(),
+            _ => self.start.clone(),
+        };
+        let end = match upper {
+            Some(upper) if *upper < self.end => upper.clone(),
+            _ => self.end.clone(),
+        };
+        (start < end).then_some(Self::new(start, end, self.ts))
+    }
+
+    /// The smallest internal key in the range.
+    pub(crate) fn first_key(&self) -> KeyBytes {
+        KeyBytes::from_bytes(self.start.clone(), TS_MAX)
+    }
+
+    /// An internal key at least as large as every internal key in the range and smaller than
+    /// every version of `end`.
+    pub(crate) fn last_key(&self) -> KeyBytes {
+        // If `end` is a key followed by a zero byte, that key is the last one in the range.
+        match self.end.split_last() {
+            Some((0, _)) => KeyBytes::from_bytes(self.end.slice(..self.end.len() - 1), TS_MIN),
+            _ => KeyBytes::from_bytes(self.end.clone(), TS_MAX),
+        }
+    }
+
+    /// Encode the tombstone as `varint start length | start | varint end length | end | ts(u64)`.
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        for key in [&self.start, &self.end] {
+            put_varint(buf, key.len() as u64);
+            buf.put_slice(key);
+        }
+        buf.put_u64(self.ts);
+    }
+
+    /// Decode a tombstone from the front of a buffer.
+    pub fn decode(buf: &mut impl Buf) -> Result<Self> {
+        let mut keys = Vec::with_capacity(2);
+        for _ in 0..2 {
+            let len = match get_varint(buf) {
+                Some(len) => len as usize,
+                None => bail!("key length of range tombstone is invalid"),
+            };
+            if buf.remaining() < len {
+                bail!("range tombstone is truncated");
+            }
+            keys.push(buf.copy_to_bytes(len));
+        }
+        if buf.remaining() < 8 {
+            bail!("range tombstone is truncated");
+        }
+        let end = keys.pop().unwrap();
+        let start = keys.pop().unwrap();
+        Ok(Self::new(start, end, buf.get_u64()))
+    }
+}
+
+impl Debug for RangeTombstone {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{:?}, {:?})@{}", self.start, self.end, self.ts)
+    }
+}
+
+/// Get the newest timestamp at or below `read_ts` of the tombstones containing `key`, `TS_MIN` if
+/// there is none. The versions of `key` older than it are deleted.
+pub fn max_covering_ts<'a>(
+    tombstones: impl IntoIterator<Item = &'a RangeTombstone>,
+    key: &[u8],
+    read_ts: u64,
+) -> u64 {
+    tombstones
+        .into_iter()
+        .filter(|tombstone| tombstone.ts <= read_ts && tombstone.contains(key))
+        .map(|tombstone| tombstone.ts)
+        .max()
+        .unwrap_or(TS_MIN)
+}
@@ -1,20 +1,35 @@
-use anyhow::{Ok, Result};
-use bytes::{Buf, BufMut, Bytes};
+use anyhow::{bail, Ok, Result};
+use bytes::{Buf, BufMut};
 use std::{
     fmt::Debug,
     fs::{File, OpenOptions},
     io::Write,
+    ops::Bound,
     os::unix::prelude::FileExt,
     path::Path,
     sync::Arc,
 };
 
-use crate::{block::Block, lsm_storage::BlockCache};
+use crate::{
+    block::{get_varint, put_varint, varint_len, Block},
+    key::{KeyBytes, KeySlice},
+    lsm_storage::BlockCache,
+    range_tombstone::RangeTombstone,
+};
 
-///
+use self::bloom::{key_hash, Bloom};
+use self::compression::{decompress, CompressionType};
+
+/// Bloom filter
+pub mod bloom;
+
+/// Block compression
+pub mod compression;
+
+/// SSTable builder
 pub mod builder;
 
-///
+/// SSTable iterator
 pub mod iterator;
 
 /// blcok meta
@@ -23,38 +38,65 @@ pub struct BlockMeta {
     /// Offset of this data block.
     pub offset: usize,
     /// The first key of the data block.
-    pub first_key: Bytes,
+    pub first_key: KeyBytes,
+    /// The last key of the data block.
+    pub last_key: KeyBytes,
 }
 
 impl BlockMeta {
-    /// Encode block meta to a buffer.
+    /// Encode block meta to a buffer. Each meta is encoded as `offset(u64) | first key | last key`,
+    /// and each key as `varint length | user key | ts(u64)`.
     pub fn encode_block_meta(block_meta: &[BlockMeta], buf: &mut Vec<u8>) {
-        let mut buf_len = block_meta.len() * 6;
+        let mut buf_len = 0;
         for meta in block_meta {
-            buf_len += meta.first_key.len();
+            buf_len += 8;
+            for key in [&meta.first_key, &meta.last_key] {
+                buf_len += varint_len(key.key_len() as u64) + key.raw_len();
+            }
         }
 
         buf.reserve(buf_len);
 
         for meta in block_meta {
-            buf.put_u32(meta.offset as u32);
-            buf.put_u16(meta.first_key.len() as u16);
-            buf.put_slice(&meta.first_key);
+            buf.put_u64(meta.offset as u64);
+            for key in [&meta.first_key, &meta.last_key] {
+                put_varint(buf, key.key_len() as u64);
+                buf.put_slice(key.key_ref());
+                buf.put_u64(key.ts());
+            }
         }
     }
 
     /// Decode block meta from a buffer.
-    pub fn decode_block_meta(mut buf: impl Buf) -> Vec<BlockMeta> {
+    pub fn decode_block_meta(mut buf: impl Buf) -> Result<Vec<BlockMeta>> {
+        fn get_key(buf: &mut impl Buf) -> Result<KeyBytes> {
+            let len = match get_varint(buf) {
+                Some(len) => len as usize,
+                None => bail!("key length of block meta is invalid"),
+            };
+            if buf.remaining() < 8 || buf.remaining() - 8 < len {
+                bail!("key of block meta is truncated");
+            }
+            let key = buf.copy_to_bytes(len);
+            Ok(KeyBytes::from_bytes(key, buf.get_u64()))
+        }
+
         let mut block_meta = Vec::new();
         while buf.has_remaining() {
-            let offset = buf.get_u32() as usize;
-
-            let first_key_len = buf.get_u16() as usize;
-            let first_key = buf.copy_to_bytes(first_key_len);
+            if buf.remaining() < 26 {
+                bail!("block meta is truncated");
+            }
+            let offset = buf.get_u64() as usize;
+            let first_key = get_key(&mut buf)?;
+            let last_key = get_key(&mut buf)?;
 
-            block_meta.push(BlockMeta { offset, first_key });
+            block_meta.push(BlockMeta {
+                offset,
+                first_key,
+                last_key,
+            });
         }
-        block_meta
+        Ok(block_meta)
     }
 }
 
@@ -68,16 +110,18 @@ impl FileObject {
         assert_ne!(data.len(), 0);
         let mut file = OpenOptions::new()
             .create(true)
+            .truncate(true)
             .read(true)
             .write(true)
             .open(path)?;
         file.write_all(&data)?;
+        file.sync_all()?;
         Ok(FileObject(file, data.len() as u64))
     }
 
-    /// open a file
+    /// Open an existing file for reading.
     pub fn open(path: &Path) -> Result<Self> {
-        let file = File::create(path)?;
+        let file = File::open(path)?;
         let size = file.metadata()?.len();
         Ok(FileObject(file, size))
     }
@@ -85,12 +129,126 @@ impl FileObject {
     /// read a file
     pub fn read(&self, offset: u64, len: u64) -> Result<Vec<u8>> {
         let mut buf = vec![0; len as usize];
-        self.0.read_exact_at(&mut buf, offset).unwrap();
+        self.0.read_exact_at(&mut buf, offset)?;
         Ok(buf)
     }
 }
 
+/// Magic number at the end of every SST file.
+pub const SST_MAGIC: u32 = 0x4c53_4d54;
+
+/// Version of the SST format.
+pub const SST_FORMAT_VERSION: u32 = 3;
+
+/// Size of the footer: `meta offset(u64) | range tombstone offset(u64) | bloom offset(u64) |
+/// max ts(u64) | version(u32) | magic(u32) | checksum(u32)`.
+const FOOTER_SIZE: u64 = 44;
+
+/// Size of the checksum appended to each block and section.
+pub(crate) const CHECKSUM_SIZE: usize = 4;
+
+/// The content of an SST does not match its checksums or format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CorruptionError {
+    /// The checksum of a section does not match its content.
+    ChecksumMismatch {
+        /// Id of the SST.
+        sst_id: usize,
+        /// The corrupted section, e.g. `block 3` or `footer`.
+        section: String,
+    },
+    /// The file does not end with `SST_MAGIC`.
+    InvalidMagic {
+        /// Id of the SST.
+        sst_id: usize,
+        /// The magic number found in the file.
+        magic: u32,
+    },
+    /// The format version is not supported.
+    UnsupportedVersion {
+        /// Id of the SST.
+        sst_id: usize,
+        /// The version found in the file.
+        version: u32,
+    },
+    /// The layout of the file is invalid, e.g. an offset points outside the file.
+    InvalidLayout {
+        /// Id of the SST.
+        sst_id: usize,
+        /// What is wrong with the layout.
+        reason: String,
+    },
+}
+
+impl std::fmt::Display for CorruptionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CorruptionError::ChecksumMismatch { sst_id, section } => {
+                write!(f, "SST {}: checksum mismatch in {}", sst_id, section)
+            }
+            CorruptionError::InvalidMagic { sst_id, magic } => {
+                write!(f, "SST {}: invalid magic number {:#x}", sst_id, magic)
+            }
+            CorruptionError::UnsupportedVersion { sst_id, version } => {
+                write!(f, "SST {}: unsupported format version {}", sst_id, version)
+            }
+            CorruptionError::InvalidLayout { sst_id, reason } => {
+                write!(f, "SST {}: {}", sst_id, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CorruptionError {}
+
+/// Split the checksum off the end of `data` and verify it.
+fn verify_checksum<'a>(sst_id: usize, section: &str, data: &'a [u8]) -> Result<&'a [u8]> {
+    if data.len() < CHECKSUM_SIZE {
+        return Err(CorruptionError::InvalidLayout {
+            sst_id,
+            reason: format!("{} is shorter than its checksum", section),
+        }
+        .into());
+    }
+    let (data, checksum) = data.split_at(data.len() - CHECKSUM_SIZE);
+    if crc32fast::hash(data) != (&checksum[..]).get_u32() {
+        return Err(CorruptionError::ChecksumMismatch {
+            sst_id,
+            section: section.to_string(),
+        }
+        .into());
+    }
+    Ok(data)
+}
+
+/// Get the range of internal keys covered by the blocks and the range tombstones of an SST.
+fn key_range(
+    block_metas: &[BlockMeta],
+    range_tombstones: &[RangeTombstone],
+) -> (KeyBytes, KeyBytes) {
+    let first_keys = block_metas
+        .first()
+        .map(|meta| meta.first_key.clone())
+        .into_iter()
+        .chain(range_tombstones.iter().map(|x| x.first_key()));
+    let last_keys = block_metas
+        .last()
+        .map(|meta| meta.last_key.clone())
+        .into_iter()
+        .chain(range_tombstones.iter().map(|x| x.last_key()));
+    (
+        first_keys.min().unwrap_or_default(),
+        last_keys.max().unwrap_or_default(),
+    )
+}
+
 /// sstable
+///
+/// The file is laid out as `blocks | block metas | range tombstones | bloom filter | footer`.
+/// Each block is stored as `(compressed) block | compression type(u8)`. Each block, the block
+/// metas, the range tombstones and the bloom filter are followed by their CRC32 checksum, and the
+/// footer is checked by its own checksum. The bloom filter section is empty if the table has no
+/// filter. A table holding only range tombstones has no block.
 #[derive(Debug)]
 pub struct SSTable {
     sst_id: usize,
@@ -98,30 +256,124 @@ pub struct SSTable {
     block_metas: Vec<BlockMeta>,
     block_meta_offset: usize,
     block_cache: Option<Arc<BlockCache>>,
+    bloom: Option<Bloom>,
+    range_tombstones: Vec<RangeTombstone>,
+    first_key: KeyBytes,
+    last_key: KeyBytes,
+    max_ts: u64,
 }
 
 impl SSTable {
-    /// Open SSTable from a file.
+    /// Open SSTable from a file. The footer, the block metas, the range tombstones and the bloom
+    /// filter are verified by their checksums and against the file length, a corrupted file is
+    /// reported as a `CorruptionError`.
     pub fn open(id: usize, block_cache: Option<Arc<BlockCache>>, file: FileObject) -> Result<Self> {
+        let invalid_layout = |reason: String| CorruptionError::InvalidLayout { sst_id: id, reason };
+
         let file_len = file.1;
+        if file_len < FOOTER_SIZE {
+            bail!(invalid_layout(format!(
+                "file is too short: {} bytes",
+                file_len
+            )));
+        }
+
+        let footer = file.read(file_len - FOOTER_SIZE, FOOTER_SIZE)?;
+        let magic = (&footer[36..40]).get_u32();
+        if magic != SST_MAGIC {
+            bail!(CorruptionError::InvalidMagic { sst_id: id, magic });
+        }
+        let footer = verify_checksum(id, "footer", &footer)?;
+        let block_meta_offset = (&footer[0..8]).get_u64();
+        let range_tombstone_offset = (&footer[8..16]).get_u64();
+        let bloom_offset = (&footer[16..24]).get_u64();
+        let max_ts = (&footer[24..32]).get_u64();
+        let version = (&footer[32..36]).get_u32();
+        if version != SST_FORMAT_VERSION {
+            bail!(CorruptionError::UnsupportedVersion {
+                sst_id: id,
+                version
+            });
+        }
 
-        let block_meta_offset = file.read(0, file_len)?;
-        let block_meta_offset = (&block_meta_offset[0..]).get_u64();
+        let footer_offset = file_len - FOOTER_SIZE;
+        if bloom_offset > footer_offset
+            || range_tombstone_offset > bloom_offset
+            || block_meta_offset > range_tombstone_offset
+        {
+            bail!(invalid_layout(format!(
+                "invalid meta offset {}, range tombstone offset {} and bloom offset {}, file length \
+                 is {}",
+                block_meta_offset, range_tombstone_offset, bloom_offset, file_len
+            )));
+        }
+
+        let bloom_len = footer_offset - bloom_offset;
+        let bloom = if bloom_len > 0 {
+            let bloom_data = file.read(bloom_offset, bloom_len)?;
+            let bloom_data = verify_checksum(id, "bloom filter", &bloom_data)?;
+            let bloom = Bloom::decode(bloom_data)
+                .map_err(|e| invalid_layout(format!("corrupted bloom filter: {}", e)))?;
+            Some(bloom)
+        } else {
+            None
+        };
+
+        let range_tombstone_len = bloom_offset - range_tombstone_offset;
+        let range_tombstone_data = file.read(range_tombstone_offset, range_tombstone_len)?;
+        let mut range_tombstone_data =
+            verify_checksum(id, "range tombstones", &range_tombstone_data)?;
+        let mut range_tombstones = Vec::new();
+        while range_tombstone_data.has_remaining() {
+            let tombstone = RangeTombstone::decode(&mut range_tombstone_data)
+                .map_err(|e| invalid_layout(format!("corrupted range tombstones: {}", e)))?;
+            range_tombstones.push(tombstone);
+        }
 
-        let block_meta_len = file_len - 8 - block_meta_offset;
+        let block_meta_len = range_tombstone_offset - block_meta_offset;
 
         let metas_data = file.read(block_meta_offset, block_meta_len)?;
-        let block_metas = BlockMeta::decode_block_meta(&metas_data[0..]);
+        let metas_data = verify_checksum(id, "block metas", &metas_data)?;
+        let block_metas = BlockMeta::decode_block_meta(metas_data)
+            .map_err(|e| invalid_layout(format!("corrupted block metas: {}", e)))?;
 
+        if block_metas.is_empty() && range_tombstones.is_empty() {
+            bail!(invalid_layout("no data block".to_string()));
+        }
+        let mut prev_offset = None;
+        for meta in &block_metas {
+            if meta.offset as u64 >= block_meta_offset || prev_offset >= Some(meta.offset) {
+                bail!(invalid_layout(format!(
+                    "invalid block offset {}, meta offset is {}",
+                    meta.offset, block_meta_offset
+                )));
+            }
+            prev_offset = Some(meta.offset);
+        }
+
+        let (first_key, last_key) = key_range(&block_metas, &range_tombstones);
         Ok(Self {
             sst_id: id,
             file,
             block_metas,
             block_meta_offset: block_meta_offset as usize,
             block_cache,
+            bloom,
+            range_tombstones,
+            first_key,
+            last_key,
+            max_ts,
         })
     }
 
+    /// Check if the SSTable may contain a version of the user key `key`. Always true if the
+    /// table has no bloom filter.
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        self.bloom
+            .as_ref()
+            .is_none_or(|bloom| bloom.may_contain(key_hash(key)))
+    }
+
     /// Read a block from the disk.
     pub fn read_block(&self, block_idx: usize) -> Result<Arc<Block>> {
         let offset = self.block_metas[block_idx].offset as u64;
@@ -131,34 +383,104 @@ impl SSTable {
             .map_or(self.block_meta_offset, |x| x.offset) as u64;
 
         let data = self.file.read(offset, offset_end - offset)?;
-        Ok(Arc::new(Block::decode(&data)))
+        let section = format!("block {}", block_idx);
+        let data = verify_checksum(self.sst_id, &section, &data)?;
+        let invalid_layout = |reason: String| CorruptionError::InvalidLayout {
+            sst_id: self.sst_id,
+            reason: format!("{}: {}", section, reason),
+        };
+
+        let (data, compression) = match data.split_last() {
+            Some((compression, data)) => (data, *compression),
+            None => bail!(invalid_layout("missing compression type".to_string())),
+        };
+        let compression =
+            CompressionType::from_u8(compression).map_err(|e| invalid_layout(e.to_string()))?;
+        let block = match compression {
+            CompressionType::None => Block::decode(data),
+            _ => {
+                let data =
+                    decompress(compression, data).map_err(|e| invalid_layout(e.to_string()))?;
+                Block::decode(&data)
+            }
+        };
+        let block = block.map_err(|e| invalid_layout(e.to_string()))?;
+        Ok(Arc::new(block))
     }
 
-    /// Read a block from disk, with block cache.
-    pub fn read_block_cached(&mut self, block_idx: usize) -> Result<Arc<Block>> {
-        if self.block_cache.is_none() {
-            return Err(anyhow::anyhow!("block cache is not set"));
-        }
+    /// Read a block from disk, with block cache. The cache holds decompressed blocks. Falls back to
+    /// `read_block` if the cache is not set. Failed reads are not cached.
+    pub fn read_block_cached(&self, block_idx: usize) -> Result<Arc<Block>> {
+        let cache = match &self.block_cache {
+            Some(cache) => cache,
+            None => return self.read_block(block_idx),
+        };
 
-        let cache = self.block_cache.as_mut().unwrap();
-        let block = cache.get(&(self.sst_id, block_idx)).unwrap_or_else(|| {
-            let block = self.read_block(block_idx).unwrap();
-            self.block_cache
-                .as_ref()
-                .unwrap()
-                .insert((self.sst_id, block_idx), Arc::clone(&block));
-            block
-        });
+        if let Some(block) = cache.get(&(self.sst_id, block_idx)) {
+            return Ok(block);
+        }
+        let block = self.read_block(block_idx)?;
+        cache.insert((self.sst_id, block_idx), Arc::clone(&block));
         Ok(block)
     }
 
+    /// Get the id of the SSTable.
+    pub fn sst_id(&self) -> usize {
+        self.sst_id
+    }
+
+    /// Get the first key of the SSTable, the range tombstones included.
+    pub fn first_key(&self) -> &KeyBytes {
+        &self.first_key
+    }
+
+    /// Get the last key of the SSTable, the range tombstones included.
+    pub fn last_key(&self) -> &KeyBytes {
+        &self.last_key
+    }
+
+    /// Check if the keys of the SSTable may fall within the range of user keys.
+    pub fn range_overlap(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> bool {
+        let after_lower = match lower {
+            Bound::Included(key) => self.last_key.key_ref() >= key,
+            Bound::Excluded(key) => self.last_key.key_ref() > key,
+            Bound::Unbounded => true,
+        };
+        let before_upper = match upper {
+            Bound::Included(key) => self.first_key.key_ref() <= key,
+            Bound::Excluded(key) => self.first_key.key_ref() < key,
+            Bound::Unbounded => true,
+        };
+        after_lower && before_upper
+    }
+
+    /// Get the range tombstones of the SSTable.
+    pub fn range_tombstones(&self) -> &[RangeTombstone] {
+        &self.range_tombstones
+    }
+
+    /// Get the largest timestamp of the keys and the range tombstones in the SSTable.
+    pub fn max_ts(&self) -> u64 {
+        self.max_ts
+    }
+
+    /// Get the size of the SSTable file in bytes.
+    pub fn table_size(&self) -> u64 {
+        self.file.1
+    }
+
+    /// Get the number of blocks in the SSTable.
+    pub fn num_of_blocks(&self) -> usize {
+        self.block_metas.len()
+    }
+
     /// Find the block that may contain `key`.
-    pub fn find_block_idx(&self, key: &[u8]) -> usize {
+    pub fn find_block_idx(&self, key: KeySlice) -> usize {
         let mut l = 0;
-        let mut r = self.block_metas.len() - 1;
+        let mut r = self.block_metas.len().saturating_sub(1);
         while l < r {
             let m = (l + r + 1) >> 1;
-            if key >= self.block_metas[m].first_key {
+            if key >= self.block_metas[m].first_key.as_key_slice() {
                 l = m;
             } else {
                 r = m - 1;
@@ -168,6 +490,5 @@ impl SSTable {
     }
 }
 
-///
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,99 @@
+use anyhow::{bail, Result};
+use bytes::{BufMut, Bytes};
+
+/// A bloom filter over the keys of an SSTable, probed with double hashing as in LevelDB.
+///
+/// Encoded as `bits | k(u8)`.
+#[derive(Debug)]
+pub struct Bloom {
+    filter: Bytes,
+    k: u8,
+}
+
+impl Bloom {
+    /// Build a filter from the hashes of the keys, using about `bits_per_key` bits per key.
+    pub fn build_from_key_hashes(hashes: &[u32], bits_per_key: usize) -> Self {
+        // k = ln(2) * bits_per_key minimizes the false positive rate.
+        let k = ((bits_per_key as f64 * 0.69) as u8).clamp(1, 30);
+        let nbits = (hashes.len() * bits_per_key).max(64);
+        let nbytes = nbits.div_ceil(8);
+        let nbits = nbytes * 8;
+
+        let mut filter = vec![0u8; nbytes];
+        for h in hashes {
+            let mut h = *h;
+            let delta = h.rotate_left(15);
+            for _ in 0..k {
+                let bit_pos = h as usize % nbits;
+                filter[bit_pos / 8] |= 1 << (bit_pos % 8);
+                h = h.wrapping_add(delta);
+            }
+        }
+
+        Self {
+            filter: filter.into(),
+            k,
+        }
+    }
+
+    /// Check if a key with hash `h` may be in the filter.
+    pub fn may_contain(&self, h: u32) -> bool {
+        if self.k > 30 {
+            // Reserved for potentially new encodings, treat as a match.
+            return true;
+        }
+
+        let nbits = self.filter.len() * 8;
+        let delta = h.rotate_left(15);
+        let mut h = h;
+        for _ in 0..self.k {
+            let bit_pos = h as usize % nbits;
+            if self.filter[bit_pos / 8] & (1 << (bit_pos % 8)) == 0 {
+                return false;
+            }
+            h = h.wrapping_add(delta);
+        }
+        true
+    }
+
+    /// Encode the filter to a buffer.
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_slice(&self.filter);
+        buf.put_u8(self.k);
+    }
+
+    /// Decode the filter from a buffer.
+    pub fn decode(buf: &[u8]) -> Result<Self> {
+        if buf.len() < 2 {
+            bail!("bloom filter is too short: {} bytes", buf.len());
+        }
+        Ok(Self {
+            filter: Bytes::copy_from_slice(&buf[..buf.len() - 1]),
+            k: buf[buf.len() - 1],
+        })
+    }
+}
+
+/// Hash a key for the bloom filter (the 32-bit hash used by LevelDB).
+pub fn key_hash(key: &[u8]) -> u32 {
+    const SEED: u32 = 0xbc9f1d34;
+    const M: u32 = 0xc6a4a793;
+
+    let mut h = SEED ^ (key.len() as u32).wrapping_mul(M);
+    let mut chunks = key.chunks_exact(4);
+    for chunk in &mut chunks {
+        let w = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        h = h.wrapping_add(w).wrapping_mul(M);
+        h ^= h >> 16;
+    }
+
+    let rest = chunks.remainder();
+    if !rest.is_empty() {
+        for (i, b) in rest.iter().enumerate() {
+            h = h.wrapping_add((*b as u32) << (8 * i));
+        }
+        h = h.wrapping_mul(M);
+        h ^= h >> 24;
+    }
+    h
+}
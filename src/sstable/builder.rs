@@ -1,18 +1,35 @@
 use std::{path::Path, sync::Arc};
 
-use crate::{block::builder::BlockBuilder, lsm_storage::BlockCache};
+use crate::{
+    block::builder::BlockBuilder,
+    key::{KeySlice, KeyVec},
+    lsm_storage::BlockCache,
+    range_tombstone::RangeTombstone,
+};
 use anyhow::{Ok, Result};
 use bytes::BufMut;
 
-use super::{BlockMeta, FileObject, SSTable};
+use super::bloom::{key_hash, Bloom};
+use super::compression::{compress, CompressionType};
+use super::{key_range, BlockMeta, FileObject, SSTable, SST_FORMAT_VERSION, SST_MAGIC};
 
-///
+/// Default number of bloom filter bits per key, about 1% false positive rate.
+pub const DEFAULT_BLOOM_BITS_PER_KEY: usize = 10;
+
+/// Builds an SSTable from key-value pairs.
 #[derive(Debug)]
 pub struct SSTableBuilder {
     pub(super) meta: Vec<BlockMeta>,
     max_block_size: usize,
     curr_block: BlockBuilder,
+    first_key: KeyVec,
+    last_key: KeyVec,
     data: Vec<u8>,
+    key_hashes: Vec<u32>,
+    range_tombstones: Vec<RangeTombstone>,
+    max_ts: u64,
+    bloom_bits_per_key: usize,
+    compression: CompressionType,
 }
 
 impl SSTableBuilder {
@@ -21,11 +38,31 @@ impl SSTableBuilder {
         Self {
             meta: vec![],
             max_block_size: block_size,
-            curr_block: BlockBuilder::new(0),
+            curr_block: BlockBuilder::new(block_size),
+            first_key: KeyVec::new(),
+            last_key: KeyVec::new(),
             data: vec![],
+            key_hashes: vec![],
+            range_tombstones: vec![],
+            max_ts: 0,
+            bloom_bits_per_key: DEFAULT_BLOOM_BITS_PER_KEY,
+            compression: CompressionType::None,
         }
     }
 
+    /// Set the number of bloom filter bits per key. No filter is built if it is 0.
+    pub fn bloom_bits_per_key(mut self, bits_per_key: usize) -> Self {
+        self.bloom_bits_per_key = bits_per_key;
+        self
+    }
+
+    /// Set the compression algorithm of the blocks. A block is stored uncompressed if the
+    /// algorithm does not save enough space.
+    pub fn compression(mut self, compression: CompressionType) -> Self {
+        self.compression = compression;
+        self
+    }
+
     /// Builds the SSTable and writes it to the given path.
     pub fn build(
         mut self,
@@ -34,52 +71,138 @@ impl SSTableBuilder {
         path: impl AsRef<Path>,
     ) -> Result<SSTable> {
         // write the block
-        let block = self.curr_block.build().encode();
-        self.data.extend(block);
+        if !self.curr_block.is_empty() {
+            self.finish_block();
+        }
 
         let block_meta_offset = self.data.len() as u64;
 
         // wirte meta
-        BlockMeta::encode_block_meta(&self.meta, &mut self.data);
+        let mut meta = Vec::new();
+        BlockMeta::encode_block_meta(&self.meta, &mut meta);
+        put_with_checksum(&mut self.data, &meta);
 
-        // write meta offset
-        self.data.put_u64(block_meta_offset);
+        // write range tombstones
+        let range_tombstone_offset = self.data.len() as u64;
+        let mut buf = Vec::new();
+        for tombstone in &self.range_tombstones {
+            tombstone.encode(&mut buf);
+        }
+        put_with_checksum(&mut self.data, &buf);
+
+        // write bloom filter
+        let bloom_offset = self.data.len() as u64;
+        let bloom = if self.bloom_bits_per_key > 0 {
+            let bloom = Bloom::build_from_key_hashes(&self.key_hashes, self.bloom_bits_per_key);
+            let mut buf = Vec::new();
+            bloom.encode(&mut buf);
+            put_with_checksum(&mut self.data, &buf);
+            Some(bloom)
+        } else {
+            None
+        };
+
+        // write footer
+        let mut footer = Vec::new();
+        footer.put_u64(block_meta_offset);
+        footer.put_u64(range_tombstone_offset);
+        footer.put_u64(bloom_offset);
+        footer.put_u64(self.max_ts);
+        footer.put_u32(SST_FORMAT_VERSION);
+        footer.put_u32(SST_MAGIC);
+        put_with_checksum(&mut self.data, &footer);
 
         let file = FileObject::create(path.as_ref(), self.data)?;
 
+        let (first_key, last_key) = key_range(&self.meta, &self.range_tombstones);
         Ok(SSTable {
             sst_id: id,
             file,
             block_cache,
             block_meta_offset: block_meta_offset as usize,
             block_metas: self.meta,
+            bloom,
+            range_tombstones: self.range_tombstones,
+            first_key,
+            last_key,
+            max_ts: self.max_ts,
         })
     }
 
-    /// Adds a key-value pair to SSTable
-    pub fn add(&mut self, key: &[u8], value: &[u8]) {
+    /// Adds a key-value pair to SSTable. Keys must be added in ascending order.
+    pub fn add(&mut self, key: KeySlice, value: &[u8]) {
+        self.add_key(key);
         if !self.curr_block.add(key, value) {
-            let block =
-                std::mem::replace(&mut self.curr_block, BlockBuilder::new(self.max_block_size));
-            let block = block.build().encode();
-
-            if block.len() > 2 {
-                self.data.extend(block);
-            }
-
-            self.meta.push(BlockMeta {
-                offset: self.data.len(),
-                first_key: key.to_vec().into(),
-            });
-
-            if !self.curr_block.add(key, value) {
-                panic!("key + val >= max_block_size");
-            }
+            // an empty block accepts any entry
+            self.finish_block();
+            self.first_key = key.to_key_vec();
+            assert!(self.curr_block.add(key, value));
+        }
+        self.last_key.set_from_slice(key);
+    }
+
+    /// Adds a tombstone of `key` to SSTable
+    pub fn add_tombstone(&mut self, key: KeySlice) {
+        self.add_key(key);
+        if !self.curr_block.add_tombstone(key) {
+            self.finish_block();
+            self.first_key = key.to_key_vec();
+            assert!(self.curr_block.add_tombstone(key));
+        }
+        self.last_key.set_from_slice(key);
+    }
+
+    /// Adds a range tombstone to SSTable. Range tombstones may be added in any order.
+    pub fn add_range_tombstone(&mut self, tombstone: RangeTombstone) {
+        self.max_ts = self.max_ts.max(tombstone.ts());
+        self.range_tombstones.push(tombstone);
+    }
+
+    /// Check if nothing has been added.
+    pub fn is_empty(&self) -> bool {
+        self.meta.is_empty() && self.curr_block.is_empty() && self.range_tombstones.is_empty()
+    }
+
+    /// Record the key in the bloom filter and the block meta before adding it to the block.
+    fn add_key(&mut self, key: KeySlice) {
+        // the versions of a user key are adjacent and share a hash
+        let hash = key_hash(key.key_ref());
+        if self.key_hashes.last() != Some(&hash) {
+            self.key_hashes.push(hash);
+        }
+        self.max_ts = self.max_ts.max(key.ts());
+        if self.curr_block.is_empty() {
+            self.first_key = key.to_key_vec();
         }
     }
 
+    /// Writes the current block and starts a new one.
+    fn finish_block(&mut self) {
+        let block = std::mem::replace(&mut self.curr_block, BlockBuilder::new(self.max_block_size));
+        let block = block.build().encode();
+
+        let (mut block, compression) = match compress(self.compression, &block) {
+            Some(compressed) => (compressed, self.compression),
+            None => (block.to_vec(), CompressionType::None),
+        };
+        block.push(compression.to_u8());
+
+        self.meta.push(BlockMeta {
+            offset: self.data.len(),
+            first_key: std::mem::take(&mut self.first_key).into_key_bytes(),
+            last_key: self.last_key.to_key_bytes(),
+        });
+        put_with_checksum(&mut self.data, &block);
+    }
+
     /// Get the estimated size of the SSTable.
     pub fn estimated_size(&self) -> usize {
         self.data.len()
     }
 }
+
+/// Append `data` followed by its CRC32 checksum to `buf`.
+fn put_with_checksum(buf: &mut Vec<u8>, data: &[u8]) {
+    buf.put_slice(data);
+    buf.put_u32(crc32fast::hash(data));
+}
@@ -0,0 +1,62 @@
+use anyhow::{bail, Result};
+
+/// Compression algorithm of the blocks of an SSTable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionType {
+    /// Blocks are stored as they are.
+    None,
+    /// LZ4 block format.
+    Lz4,
+    /// Snappy raw format.
+    Snappy,
+    /// Zstandard at the default level.
+    Zstd,
+}
+
+impl CompressionType {
+    /// Tag of the compression type stored after each block.
+    pub(crate) fn to_u8(self) -> u8 {
+        match self {
+            CompressionType::None => 0,
+            CompressionType::Lz4 => 1,
+            CompressionType::Snappy => 2,
+            CompressionType::Zstd => 3,
+        }
+    }
+
+    /// Get the compression type from its tag.
+    pub(crate) fn from_u8(tag: u8) -> Result<Self> {
+        Ok(match tag {
+            0 => CompressionType::None,
+            1 => CompressionType::Lz4,
+            2 => CompressionType::Snappy,
+            3 => CompressionType::Zstd,
+            x => bail!("unknown compression type {}", x),
+        })
+    }
+}
+
+/// Compress `data`. Returns None if the algorithm fails or saves less than 1/8 of the size, in
+/// which case the block is stored uncompressed.
+pub(crate) fn compress(compression: CompressionType, data: &[u8]) -> Option<Vec<u8>> {
+    let compressed = match compression {
+        CompressionType::None => return None,
+        CompressionType::Lz4 => lz4_flex::block::compress_prepend_size(data),
+        CompressionType::Snappy => snap::raw::Encoder::new().compress_vec(data).ok()?,
+        CompressionType::Zstd => zstd::bulk::compress(data, 0).ok()?,
+    };
+    if compressed.len() > data.len() - data.len() / 8 {
+        return None;
+    }
+    Some(compressed)
+}
+
+/// Decompress `data` compressed by `compression`.
+pub(crate) fn decompress(compression: CompressionType, data: &[u8]) -> Result<Vec<u8>> {
+    Ok(match compression {
+        CompressionType::None => data.to_vec(),
+        CompressionType::Lz4 => lz4_flex::block::decompress_size_prepended(data)?,
+        CompressionType::Snappy => snap::raw::Decoder::new().decompress_vec(data)?,
+        CompressionType::Zstd => zstd::stream::decode_all(data)?,
+    })
+}
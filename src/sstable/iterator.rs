@@ -1,63 +1,92 @@
 use std::sync::Arc;
 
 use crate::block::iterator::BlockIterator;
-use crate::iterators::StorageIterator;
+use crate::block::Block;
+use crate::iterators::{ensure_valid, Poison, Poisonable, StorageIterator};
+use crate::key::KeySlice;
 
 use super::SSTable;
 use anyhow::{Ok, Result};
 
-///
+/// Read a block, or an empty block if the table holds only range tombstones.
+fn read_block(table: &SSTable, block_idx: usize) -> Result<Arc<Block>> {
+    if table.num_of_blocks() == 0 {
+        return Ok(Arc::new(Block::decode(&[0; 4])?));
+    }
+    table.read_block_cached(block_idx)
+}
+
+/// An iterator over the contents of an SSTable.
 #[derive(Debug)]
 pub struct SSTableIterator {
     table: Arc<SSTable>,
     block_iterator: BlockIterator,
     block_idx: usize,
+    poison: Poison,
 }
 
 impl SSTableIterator {
     /// Create a new iterator and seek to the first key-value pair.
     pub fn create_and_seek_to_first(table: Arc<SSTable>) -> Result<Self> {
-        let read_block = table.read_block(0)?;
+        let read_block = read_block(&table, 0)?;
         let block_iterator = BlockIterator::create_and_seek_to_first(read_block);
 
         Ok(SSTableIterator {
             table,
             block_iterator,
             block_idx: 0,
+            poison: Poison::default(),
         })
     }
 
-    /// Seek to the first key-value pair.
-    pub fn seek_to_first(&mut self) -> Result<()> {
-        let read_block = self.table.read_block(0)?;
-        self.block_iterator = BlockIterator::create_and_seek_to_first(read_block);
-        self.block_idx = 0;
-        Ok(())
-    }
-
     /// Create a new iterator and seek to the first key-value pair which >= `key`.
-    pub fn create_and_seek_to_key(table: Arc<SSTable>, key: &[u8]) -> Result<Self> {
+    pub fn create_and_seek_to_key(table: Arc<SSTable>, key: KeySlice) -> Result<Self> {
         let block_idx = table.find_block_idx(key);
-        let read_block = table.read_block(block_idx)?;
+        let read_block = read_block(&table, block_idx)?;
         let block_iterator = BlockIterator::create_and_seek_to_key(read_block, key);
-        Result::Ok(SSTableIterator {
+        let mut iter = SSTableIterator {
             table,
             block_iterator,
             block_idx,
-        })
+            poison: Poison::default(),
+        };
+        iter.move_to_next_block()?;
+        Result::Ok(iter)
     }
 
-    /// Seek to the first key-value pair which >= `key`.
-    pub fn seek_to_key(&mut self, key: &[u8]) -> Result<()> {
-        self.block_idx = self.table.find_block_idx(key);
-        let read_block = self.table.read_block(self.block_idx)?;
-        self.block_iterator = BlockIterator::create_and_seek_to_key(read_block, key);
-        Result::Ok(())
+    /// Move to the last entry of the previous block if the iterator went before the first entry of
+    /// the current block.
+    fn move_to_prev_block(&mut self) -> Result<()> {
+        if !self.block_iterator.is_valid() && self.block_idx > 0 {
+            self.block_idx -= 1;
+            let block = read_block(&self.table, self.block_idx)?;
+            self.block_iterator = BlockIterator::create_and_seek_to_last(block);
+        }
+        Ok(())
+    }
+
+    /// Move to the first entry of the next block if the current block is exhausted.
+    fn move_to_next_block(&mut self) -> Result<()> {
+        if !self.block_iterator.is_valid() {
+            if self.block_idx + 1 >= self.table.num_of_blocks() {
+                return Ok(());
+            }
+            self.block_idx += 1;
+            let block = read_block(&self.table, self.block_idx)?;
+            self.block_iterator = BlockIterator::create_and_seek_to_first(block);
+        }
+        Ok(())
+    }
+}
+
+impl Poisonable for SSTableIterator {
+    fn poison(&mut self) -> &mut Poison {
+        &mut self.poison
     }
 }
 
 impl StorageIterator for SSTableIterator {
-    fn key(&self) -> &[u8] {
+    fn key(&self) -> KeySlice<'_> {
         self.block_iterator.key()
     }
 
@@ -66,19 +95,63 @@ impl StorageIterator for SSTableIterator {
     }
 
     fn is_valid(&self) -> bool {
-        self.block_iterator.is_valid()
+        !self.poison.is_poisoned() && self.block_iterator.is_valid()
+    }
+
+    fn is_deleted(&self) -> bool {
+        self.block_iterator.is_deleted()
     }
 
     fn next(&mut self) -> Result<()> {
-        self.block_iterator.next();
-        if !self.block_iterator.is_valid() {
-            self.block_idx += 1;
-            if self.block_idx >= self.table.block_metas.len() {
-                return Ok(());
-            }
-            let block = self.table.read_block(self.block_idx)?;
-            self.block_iterator = BlockIterator::create_and_seek_to_first(block);
-        }
-        Ok(())
+        ensure_valid(self)?;
+        Poison::guard(self, |iter| {
+            iter.block_iterator.next();
+            iter.move_to_next_block()
+        })
+    }
+
+    fn seek(&mut self, key: KeySlice) -> Result<()> {
+        Poison::guard(self, |iter| {
+            iter.block_idx = iter.table.find_block_idx(key);
+            let read_block = read_block(&iter.table, iter.block_idx)?;
+            iter.block_iterator = BlockIterator::create_and_seek_to_key(read_block, key);
+            iter.move_to_next_block()
+        })
+    }
+
+    fn seek_to_first(&mut self) -> Result<()> {
+        Poison::guard(self, |iter| {
+            let read_block = read_block(&iter.table, 0)?;
+            iter.block_iterator = BlockIterator::create_and_seek_to_first(read_block);
+            iter.block_idx = 0;
+            Ok(())
+        })
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        ensure_valid(self)?;
+        Poison::guard(self, |iter| {
+            iter.block_iterator.prev();
+            iter.move_to_prev_block()
+        })
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        Poison::guard(self, |iter| {
+            iter.block_idx = iter.table.num_of_blocks().saturating_sub(1);
+            let read_block = read_block(&iter.table, iter.block_idx)?;
+            iter.block_iterator = BlockIterator::create_and_seek_to_last(read_block);
+            Ok(())
+        })
+    }
+
+    fn seek_for_prev(&mut self, key: KeySlice) -> Result<()> {
+        Poison::guard(self, |iter| {
+            iter.block_idx = iter.table.find_block_idx(key);
+            let read_block = read_block(&iter.table, iter.block_idx)?;
+            iter.block_iterator = BlockIterator::create_and_seek_to_first(read_block);
+            iter.block_iterator.seek_for_prev(key);
+            iter.move_to_prev_block()
+        })
     }
 }
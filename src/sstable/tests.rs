@@ -1,11 +1,21 @@
-use std::{fs, path::Path, sync::Arc};
+use std::{fs, ops::Bound, path::Path, sync::Arc};
 
 use bytes::Bytes;
 
-use crate::{block::Block, sstable::builder::SSTableBuilder};
+use crate::block::{put_varint, Block};
+use crate::{lsm_storage::BlockCache, sstable::builder::SSTableBuilder};
 
-use super::{iterator::SSTableIterator, SSTable};
+use super::{
+    compression::CompressionType, iterator::SSTableIterator, BlockMeta, CorruptionError,
+    FileObject, SSTable, SST_FORMAT_VERSION, SST_MAGIC,
+};
 use crate::iterators::StorageIterator;
+use crate::key::{KeySlice, TS_DEFAULT, TS_MAX, TS_MIN};
+use crate::range_tombstone::RangeTombstone;
+
+fn ks(key: &[u8]) -> KeySlice<'_> {
+    KeySlice::from_slice(key, TS_DEFAULT)
+}
 
 fn sst_build_test<T, K>(id: usize, map: T, test: K)
 where
@@ -59,13 +69,13 @@ fn assert_kv(i: usize, key: &[u8], value: &[u8]) {
 #[test]
 fn test_sst_build_single_key() {
     let map = |builder: &mut SSTableBuilder| {
-        builder.add(b"233", b"23333");
+        builder.add(ks(b"233"), b"23333");
     };
 
     let test = |sst: Arc<SSTable>| {
         let it = SSTableIterator::create_and_seek_to_first(sst).unwrap();
 
-        assert_eq!(it.key(), b"233");
+        assert_eq!(it.key().key_ref(), b"233");
         assert_eq!(it.value(), b"23333");
     };
 
@@ -75,17 +85,17 @@ fn test_sst_build_single_key() {
 #[test]
 fn test_sst_build_two_key() {
     let map = |builder: &mut SSTableBuilder| {
-        builder.add(b"233", b"23333");
-        builder.add(b"233", b"23333");
+        builder.add(ks(b"233"), b"23333");
+        builder.add(ks(b"233"), b"23333");
     };
 
     let test = |sst: Arc<SSTable>| {
         let mut it = SSTableIterator::create_and_seek_to_first(sst).unwrap();
 
-        assert_eq!(it.key(), b"233");
+        assert_eq!(it.key().key_ref(), b"233");
         assert_eq!(it.value(), b"23333");
         it.next().unwrap();
-        assert_eq!(it.key(), b"233");
+        assert_eq!(it.key().key_ref(), b"233");
         assert_eq!(it.value(), b"23333");
     };
 
@@ -98,7 +108,7 @@ fn test_sst_build_multiple_keys_print() {
         for i in 0..100 {
             let key = key_of(i);
             let value = value_of(i);
-            builder.add(&key, &value);
+            builder.add(ks(&key), &value);
         }
     };
 
@@ -118,7 +128,7 @@ fn test_sst_build_multiple_keys() {
         for i in 0..100 {
             let key = key_of(i);
             let value = value_of(i);
-            builder.add(&key, &value);
+            builder.add(ks(&key), &value);
         }
     };
 
@@ -127,7 +137,7 @@ fn test_sst_build_multiple_keys() {
         for i in 0..100 {
             assert!(iter.is_valid(), "idx:{i}");
 
-            let key = iter.key();
+            let key = iter.key().key_ref();
             let value = iter.value();
 
             assert_eq!(
@@ -159,7 +169,7 @@ fn test_sst_build_multiple_keys_plus() {
         for i in 0..1090 {
             let key = key_of(i);
             let value = value_of(i);
-            builder.add(&key, &value);
+            builder.add(ks(&key), &value);
         }
     };
 
@@ -168,7 +178,7 @@ fn test_sst_build_multiple_keys_plus() {
         for i in 0..1000 {
             assert!(iter.is_valid(), "idx:{i}");
 
-            let key = iter.key();
+            let key = iter.key().key_ref();
             let value = iter.value();
 
             assert_eq!(
@@ -200,18 +210,19 @@ fn test_sst_iterator() {
         for i in 0..1100 {
             let key = key_of(i);
             let value = value_of(i);
-            builder.add(&key, &value);
+            builder.add(ks(&key), &value);
         }
     };
 
     let test = |sst: Arc<SSTable>| {
         for start in 0..1100 {
             let mut iter =
-                SSTableIterator::create_and_seek_to_key(Arc::clone(&sst), &key_of(start)).unwrap();
+                SSTableIterator::create_and_seek_to_key(Arc::clone(&sst), ks(&key_of(start)))
+                    .unwrap();
             for i in start..1000 {
                 assert!(iter.is_valid(), "idx:{i}");
 
-                let key = iter.key();
+                let key = iter.key().key_ref();
                 let value = iter.value();
                 assert_kv(i, key, value);
 
@@ -222,3 +233,531 @@ fn test_sst_iterator() {
 
     sst_build_test(6, map, test);
 }
+
+#[test]
+fn test_sst_reverse() {
+    let map = |builder: &mut SSTableBuilder| {
+        for i in (0..1000).step_by(2) {
+            builder.add(ks(&key_of(i)), &value_of(i));
+        }
+    };
+
+    let test = |sst: Arc<SSTable>| {
+        assert!(sst.num_of_blocks() > 1);
+        let mut iter = SSTableIterator::create_and_seek_to_first(sst).unwrap();
+        iter.seek_to_last().unwrap();
+        for i in (0..1000).step_by(2).rev() {
+            assert!(iter.is_valid(), "idx:{i}");
+            assert_kv(i, iter.key().key_ref(), iter.value());
+            iter.prev().unwrap();
+        }
+        assert!(!iter.is_valid());
+
+        for key in 0..1000 {
+            iter.seek_for_prev(ks(&key_of(key))).unwrap();
+            let expected = key - key % 2;
+            assert_kv(expected, iter.key().key_ref(), iter.value());
+            iter.prev().unwrap();
+            assert_eq!(iter.is_valid(), expected > 0, "idx:{key}");
+        }
+        iter.seek_for_prev(ks(b"key")).unwrap();
+        assert!(!iter.is_valid());
+    };
+
+    sst_build_test(8, map, test);
+}
+
+#[test]
+fn test_sst_large_entries() {
+    // a 3 MiB value and a 70 KiB key, each larger than the block and the u16 range
+    let large_value = vec![b'v'; 3 << 20];
+    let large_key = [key_of(2), vec![b'k'; 70 << 10]].concat();
+    let entries = (0..10)
+        .map(|i| match i {
+            1 => (key_of(i), large_value.clone()),
+            2 => (large_key.clone(), value_of(i)),
+            _ => (key_of(i), value_of(i)),
+        })
+        .collect::<Vec<_>>();
+
+    let map = |builder: &mut SSTableBuilder| {
+        for (key, value) in &entries {
+            builder.add(ks(key), value);
+        }
+    };
+
+    let test = |sst: Arc<SSTable>| {
+        assert_eq!(sst.block_metas[1].first_key.key_ref(), key_of(1));
+        assert_eq!(sst.block_metas[2].first_key.key_ref(), large_key);
+        assert_eq!(sst.block_metas[3].first_key.key_ref(), key_of(3));
+
+        let reopened = SSTable::open(
+            0,
+            None,
+            FileObject::open(Path::new("./tmp/test-10")).unwrap(),
+        );
+        for sst in [sst, Arc::new(reopened.unwrap())] {
+            let mut iter = SSTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+            for (key, value) in &entries {
+                assert!(iter.is_valid());
+                assert_eq!(iter.key().key_ref(), &key[..]);
+                assert_eq!(iter.value(), &value[..]);
+                iter.next().unwrap();
+            }
+            assert!(!iter.is_valid());
+
+            let iter = SSTableIterator::create_and_seek_to_key(sst, ks(&key_of(2))).unwrap();
+            assert_eq!(iter.value(), value_of(2));
+        }
+    };
+
+    sst_build_test(10, map, test);
+}
+
+#[test]
+fn test_sst_compression() {
+    let path = Path::new("./tmp/test-compression");
+    let build = |compression, values: &[Vec<u8>]| {
+        let mut builder = SSTableBuilder::new(4096).compression(compression);
+        for (i, value) in values.iter().enumerate() {
+            builder.add(ks(&key_of(i)), value);
+        }
+        builder.build(1, None, path).unwrap();
+        fs::metadata(path).unwrap().len()
+    };
+
+    let compressible = (0..1000).map(value_of).collect::<Vec<_>>();
+    let incompressible = (0..100)
+        .map(|_| (0..1000).map(|_| rand::random::<u8>()).collect())
+        .collect::<Vec<_>>();
+    let raw_size = build(CompressionType::None, &compressible);
+    let raw_incompressible_size = build(CompressionType::None, &incompressible);
+
+    for compression in [
+        CompressionType::Lz4,
+        CompressionType::Snappy,
+        CompressionType::Zstd,
+    ] {
+        // blocks that do not shrink are stored uncompressed
+        assert_eq!(build(compression, &incompressible), raw_incompressible_size);
+
+        let size = build(compression, &compressible);
+        assert!(
+            size < raw_size * 3 / 4,
+            "{compression:?}: {size} {raw_size}"
+        );
+
+        let cache = Arc::new(BlockCache::new(1024));
+        let sst = Arc::new(
+            SSTable::open(1, Some(cache.clone()), FileObject::open(path).unwrap()).unwrap(),
+        );
+        for _ in 0..2 {
+            let mut iter = SSTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+            for (i, value) in compressible.iter().enumerate() {
+                assert_eq!(iter.key().key_ref(), key_of(i));
+                assert_eq!(iter.value(), &value[..]);
+                iter.next().unwrap();
+            }
+            assert!(!iter.is_valid());
+        }
+        assert!(cache.contains_key(&(1, 0)));
+
+        let iter = SSTableIterator::create_and_seek_to_key(sst, ks(&key_of(500))).unwrap();
+        assert_eq!(iter.value(), value_of(500));
+    }
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_sst_reopen() {
+    let path = Path::new("./tmp/test-reopen");
+    let mut builder = SSTableBuilder::new(300);
+    for i in 0..1000 {
+        builder.add(ks(&key_of(i)), &value_of(i));
+    }
+    let sst = builder.build(7, None, path).unwrap();
+    let num_of_blocks = sst.num_of_blocks();
+    drop(sst);
+
+    for _ in 0..2 {
+        let sst = SSTable::open(7, None, FileObject::open(path).unwrap()).unwrap();
+        assert_eq!(sst.sst_id(), 7);
+        assert_eq!(sst.num_of_blocks(), num_of_blocks);
+
+        let mut iter = SSTableIterator::create_and_seek_to_first(Arc::new(sst)).unwrap();
+        for i in 0..1000 {
+            assert!(iter.is_valid(), "idx:{i}");
+            assert_kv(i, iter.key().key_ref(), iter.value());
+            iter.next().unwrap();
+        }
+        assert!(!iter.is_valid());
+    }
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_sst_range_tombstones() {
+    let tombstone = |start: &[u8], end: &[u8], ts: u64| {
+        RangeTombstone::new(
+            Bytes::copy_from_slice(start),
+            Bytes::copy_from_slice(end),
+            ts,
+        )
+    };
+    let path = Path::new("./tmp/test-range-tombstones");
+
+    // the key range covers the tombstones
+    let mut builder = SSTableBuilder::new(300);
+    for i in 10..20 {
+        builder.add(ks(&key_of(i)), &value_of(i));
+    }
+    builder.add_range_tombstone(tombstone(&key_of(5), &key_of(15), 3));
+    builder.add_range_tombstone(tombstone(&key_of(18), &[&key_of(30)[..], &[0]].concat(), 7));
+    builder.build(1, None, path).unwrap();
+    let sst = SSTable::open(1, None, FileObject::open(path).unwrap()).unwrap();
+    assert_eq!(sst.first_key().key_ref(), key_of(5));
+    assert_eq!(sst.first_key().ts(), TS_MAX);
+    assert_eq!(sst.last_key().key_ref(), key_of(30));
+    assert_eq!(sst.last_key().ts(), TS_MIN);
+    assert_eq!(sst.max_ts(), 7);
+    assert_eq!(sst.range_tombstones().len(), 2);
+    assert_eq!(sst.range_tombstones()[1].ts(), 7);
+
+    // a table may hold only range tombstones
+    let mut builder = SSTableBuilder::new(300);
+    assert!(builder.is_empty());
+    builder.add_range_tombstone(tombstone(&key_of(5), &key_of(15), 3));
+    assert!(!builder.is_empty());
+    builder.build(2, None, path).unwrap();
+    let sst = Arc::new(SSTable::open(2, None, FileObject::open(path).unwrap()).unwrap());
+    assert_eq!(sst.num_of_blocks(), 0);
+    assert_eq!(sst.first_key().key_ref(), key_of(5));
+    assert_eq!(sst.last_key().key_ref(), key_of(15));
+    assert_eq!(sst.last_key().ts(), TS_MAX);
+    assert_eq!(
+        sst.range_tombstones(),
+        &[tombstone(&key_of(5), &key_of(15), 3)]
+    );
+    let iter = SSTableIterator::create_and_seek_to_first(Arc::clone(&sst)).unwrap();
+    assert!(!iter.is_valid());
+    let iter = SSTableIterator::create_and_seek_to_key(sst, ks(&key_of(10))).unwrap();
+    assert!(!iter.is_valid());
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_sst_open_invalid_file() {
+    let path = Path::new("./tmp/test-invalid");
+    let open = |data: &[u8]| {
+        fs::write(path, data).unwrap();
+        SSTable::open(1, None, FileObject::open(path).unwrap())
+    };
+
+    // shorter than the footer
+    assert!(open(b"abc").is_err());
+
+    // offsets beyond the file
+    assert!(open(&footer(100, 100, 100, SST_FORMAT_VERSION, SST_MAGIC)).is_err());
+
+    // meta offset after range tombstone offset
+    assert!(open(&footer(1, 0, 0, SST_FORMAT_VERSION, SST_MAGIC)).is_err());
+
+    // range tombstone offset after bloom offset
+    assert!(open(&footer(0, 1, 0, SST_FORMAT_VERSION, SST_MAGIC)).is_err());
+
+    // garbage
+    let garbage = (0..1000).map(|x| (x * 7 + 3) as u8).collect::<Vec<_>>();
+    assert!(open(&garbage).is_err());
+
+    // truncated block meta
+    let data = build_sst_data(path);
+    let len = data.len();
+    let range_tombstone_offset = u64::from_be_bytes(data[len - 36..len - 28].try_into().unwrap());
+    let bloom_offset = u64::from_be_bytes(data[len - 28..len - 20].try_into().unwrap());
+    let mut truncated = data[..len - 44].to_vec();
+    truncated.extend(footer(
+        range_tombstone_offset - 3,
+        range_tombstone_offset,
+        bloom_offset,
+        SST_FORMAT_VERSION,
+        SST_MAGIC,
+    ));
+    assert!(open(&truncated).is_err());
+
+    // no data block and no range tombstone
+    let empty = crc32fast::hash(&[]).to_be_bytes();
+    let mut data = [&empty[..], &empty].concat();
+    data.extend(footer(0, 4, 8, SST_FORMAT_VERSION, SST_MAGIC));
+    let err = open(&data).unwrap_err();
+    assert!(matches!(
+        err.downcast::<CorruptionError>().unwrap(),
+        CorruptionError::InvalidLayout { .. }
+    ));
+
+    assert!(FileObject::open(Path::new("./tmp/missing")).is_err());
+
+    fs::remove_file(path).unwrap();
+}
+
+/// Encode a footer with a valid checksum.
+fn footer(
+    meta_offset: u64,
+    range_tombstone_offset: u64,
+    bloom_offset: u64,
+    version: u32,
+    magic: u32,
+) -> Vec<u8> {
+    let mut footer = [
+        &meta_offset.to_be_bytes()[..],
+        &range_tombstone_offset.to_be_bytes(),
+        &bloom_offset.to_be_bytes(),
+        &TS_DEFAULT.to_be_bytes(),
+        &version.to_be_bytes(),
+        &magic.to_be_bytes(),
+    ]
+    .concat();
+    let checksum = crc32fast::hash(&footer);
+    footer.extend(checksum.to_be_bytes());
+    footer
+}
+
+fn build_sst_data(path: &Path) -> Vec<u8> {
+    let mut builder = SSTableBuilder::new(300);
+    for i in 0..100 {
+        builder.add(ks(&key_of(i)), &value_of(i));
+    }
+    builder.build(1, None, path).unwrap();
+    fs::read(path).unwrap()
+}
+
+#[test]
+fn test_sst_corruption() {
+    let path = Path::new("./tmp/test-corruption");
+    let data = build_sst_data(path);
+    let len = data.len();
+    let open_corrupted = |pos: usize| {
+        let mut data = data.clone();
+        data[pos] ^= 0xff;
+        fs::write(path, data).unwrap();
+        SSTable::open(1, None, FileObject::open(path).unwrap())
+    };
+    let corruption = |err: anyhow::Error| err.downcast::<CorruptionError>().unwrap();
+
+    // data block
+    let sst = open_corrupted(10).unwrap();
+    let err = corruption(sst.read_block(0).unwrap_err());
+    assert_eq!(
+        err,
+        CorruptionError::ChecksumMismatch {
+            sst_id: 1,
+            section: "block 0".to_string()
+        }
+    );
+    assert!(sst.read_block(1).is_ok());
+    let err = SSTableIterator::create_and_seek_to_first(Arc::new(sst)).unwrap_err();
+    assert!(err.downcast_ref::<CorruptionError>().is_some());
+
+    // block metas
+    let meta_offset = u64::from_be_bytes(data[len - 44..len - 36].try_into().unwrap()) as usize;
+    let range_tombstone_offset =
+        u64::from_be_bytes(data[len - 36..len - 28].try_into().unwrap()) as usize;
+    let err = corruption(open_corrupted(meta_offset + 1).unwrap_err());
+    assert_eq!(
+        err,
+        CorruptionError::ChecksumMismatch {
+            sst_id: 1,
+            section: "block metas".to_string()
+        }
+    );
+
+    // range tombstones
+    let err = corruption(open_corrupted(range_tombstone_offset).unwrap_err());
+    assert_eq!(
+        err,
+        CorruptionError::ChecksumMismatch {
+            sst_id: 1,
+            section: "range tombstones".to_string()
+        }
+    );
+
+    // bloom filter
+    let bloom_offset = u64::from_be_bytes(data[len - 28..len - 20].try_into().unwrap()) as usize;
+    let err = corruption(open_corrupted(bloom_offset).unwrap_err());
+    assert_eq!(
+        err,
+        CorruptionError::ChecksumMismatch {
+            sst_id: 1,
+            section: "bloom filter".to_string()
+        }
+    );
+
+    // footer
+    let err = corruption(open_corrupted(len - 33).unwrap_err());
+    assert_eq!(
+        err,
+        CorruptionError::ChecksumMismatch {
+            sst_id: 1,
+            section: "footer".to_string()
+        }
+    );
+
+    // magic number
+    let err = corruption(open_corrupted(len - 5).unwrap_err());
+    assert_eq!(
+        err,
+        CorruptionError::InvalidMagic {
+            sst_id: 1,
+            magic: SST_MAGIC ^ 0xff
+        }
+    );
+
+    // version
+    let mut data = data[..len - 44].to_vec();
+    data.extend(footer(
+        meta_offset as u64,
+        range_tombstone_offset as u64,
+        bloom_offset as u64,
+        SST_FORMAT_VERSION + 1,
+        SST_MAGIC,
+    ));
+    fs::write(path, data).unwrap();
+    let err = SSTable::open(1, None, FileObject::open(path).unwrap()).unwrap_err();
+    assert_eq!(
+        corruption(err),
+        CorruptionError::UnsupportedVersion {
+            sst_id: 1,
+            version: SST_FORMAT_VERSION + 1
+        }
+    );
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_sst_bloom_filter() {
+    let path = Path::new("./tmp/test-bloom");
+    for bits_per_key in [0, 1, 10, 20] {
+        let mut builder = SSTableBuilder::new(300).bloom_bits_per_key(bits_per_key);
+        for i in (0..2000).step_by(2) {
+            builder.add(ks(&key_of(i)), &value_of(i));
+        }
+        builder.add_tombstone(ks(&key_of(2001)));
+        let sst = builder.build(1, None, path).unwrap();
+        let reopened = SSTable::open(1, None, FileObject::open(path).unwrap()).unwrap();
+
+        for sst in [&sst, &reopened] {
+            for i in (0..2000).step_by(2) {
+                assert!(sst.may_contain(&key_of(i)), "{i}");
+            }
+            assert!(sst.may_contain(&key_of(2001)));
+
+            let false_positives = (1..2000)
+                .step_by(2)
+                .filter(|i| sst.may_contain(&key_of(*i)))
+                .count();
+            match bits_per_key {
+                0 => assert_eq!(false_positives, 1000),
+                10 => assert!(false_positives < 50, "{false_positives}"),
+                20 => assert!(false_positives < 5, "{false_positives}"),
+                _ => assert!(false_positives < 1000, "{false_positives}"),
+            }
+        }
+    }
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_sst_read_error() {
+    let path = Path::new("./tmp/test-read-error");
+    let data = build_sst_data(path);
+    for block_cache in [None, Some(Arc::new(BlockCache::new(1024)))] {
+        fs::write(path, &data).unwrap();
+        let sst = SSTable::open(1, block_cache, FileObject::open(path).unwrap()).unwrap();
+        let mut iter = SSTableIterator::create_and_seek_to_first(Arc::new(sst)).unwrap();
+        // the blocks after the first one can no longer be read
+        fs::OpenOptions::new()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_len(10)
+            .unwrap();
+        let mut result = Ok(());
+        for i in 0..100 {
+            assert!(iter.is_valid(), "idx:{i}");
+            assert_kv(i, iter.key().key_ref(), iter.value());
+            result = iter.next();
+            if result.is_err() {
+                break;
+            }
+        }
+        assert!(result.is_err());
+        assert!(!iter.is_valid());
+        assert!(iter.seek_to_first().is_err());
+    }
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_sst_range_overlap() {
+    let map = |builder: &mut SSTableBuilder| {
+        for i in 10..20 {
+            builder.add(ks(&key_of(i)), &value_of(i));
+        }
+    };
+    let test = |sst: Arc<SSTable>| {
+        let overlap = |lower: Bound<usize>, upper: Bound<usize>| {
+            let lower = lower.map(key_of);
+            let upper = upper.map(key_of);
+            sst.range_overlap(
+                lower.as_ref().map(|x| &x[..]),
+                upper.as_ref().map(|x| &x[..]),
+            )
+        };
+        assert!(overlap(Bound::Unbounded, Bound::Unbounded));
+        assert!(overlap(Bound::Included(5), Bound::Included(10)));
+        assert!(!overlap(Bound::Included(5), Bound::Excluded(10)));
+        assert!(overlap(Bound::Included(19), Bound::Unbounded));
+        assert!(!overlap(Bound::Excluded(19), Bound::Unbounded));
+        assert!(overlap(Bound::Included(12), Bound::Included(15)));
+        assert!(!overlap(Bound::Included(20), Bound::Included(30)));
+        assert!(!overlap(Bound::Unbounded, Bound::Included(5)));
+    };
+
+    sst_build_test(9, map, test);
+}
+
+#[test]
+fn test_sst_invalid_block() {
+    let path = Path::new("./tmp/test-invalid-block");
+    let data = build_sst_data(path);
+    let sst = SSTable::open(1, None, FileObject::open(path).unwrap()).unwrap();
+    let block_len = sst.block_metas[1].offset;
+
+    // a block with a valid checksum but too many restart points
+    let mut block = vec![0xff; block_len - 5];
+    block.push(CompressionType::None as u8);
+    block.extend(crc32fast::hash(&block).to_be_bytes());
+    let mut data = data;
+    data[..block_len].copy_from_slice(&block);
+    fs::write(path, data).unwrap();
+
+    let sst = SSTable::open(1, None, FileObject::open(path).unwrap()).unwrap();
+    let err = sst.read_block(0).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<CorruptionError>(),
+        Some(CorruptionError::InvalidLayout { sst_id: 1, .. })
+    ));
+    assert!(sst.read_block(1).is_ok());
+
+    // a block meta whose key length overflows
+    let mut meta = vec![0; 8];
+    put_varint(&mut meta, u64::MAX);
+    meta.extend([0; 24]);
+    assert!(BlockMeta::decode_block_meta(&meta[..]).is_err());
+
+    fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,320 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Result};
+use bytes::{Buf, BufMut, Bytes};
+use crossbeam_skiplist::SkipMap;
+use parking_lot::Mutex;
+
+use crate::key::{KeyBytes, KeySlice};
+use crate::range_tombstone::RangeTombstone;
+use crate::write_batch::{WriteBatch, WriteBatchRecord};
+
+/// Record type of a put.
+const RECORD_PUT: u8 = 0;
+
+/// Record type of a delete.
+const RECORD_DELETE: u8 = 1;
+
+/// Record type of a write batch.
+const RECORD_BATCH: u8 = 2;
+
+/// Record type of a range tombstone.
+const RECORD_DELETE_RANGE: u8 = 3;
+
+/// Size of the record header: `len(u32) | crc32(u32)`.
+const HEADER_SIZE: usize = 8;
+
+/// When the WAL is synced to the disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalSyncMode {
+    /// Call fsync after every write.
+    EveryWrite,
+    /// Concurrent writers share a single fsync. A write returns once it is durable.
+    GroupCommit,
+    /// Never call fsync. Writes are handed to the OS but may be lost on power failure.
+    NoSync,
+}
+
+/// A write-ahead log of a mem-table.
+///
+/// Each record is encoded as `len(u32) | crc32(u32) | payload`, where the checksum covers the
+/// payload. The payload of a put or a delete is
+/// `type(u8) | key_len(u32) | key | ts(u64) | value_len(u32) | value`, a range tombstone is
+/// stored the same way with its start as the key and its end as the value. The payload of a write
+/// batch is `type(u8) | ts(u64) | num_writes(u32) | writes`, each write being
+/// `type(u8) | key_len(u32) | key | value_len(u32) | value` at `ts` plus its index.
+#[derive(Debug)]
+pub struct Wal {
+    file: File,
+    sync_mode: WalSyncMode,
+    /// Serializes appends, holds the number of records written.
+    written: Mutex<u64>,
+    /// Serializes fsyncs, holds the number of records known to be durable.
+    synced: Mutex<u64>,
+}
+
+impl Wal {
+    /// Create a new WAL file.
+    pub fn create(path: impl AsRef<Path>, sync_mode: WalSyncMode) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create_new(true)
+            .append(true)
+            .open(path)?;
+        Ok(Self::new(file, sync_mode))
+    }
+
+    /// Replay a WAL file into `map` and `range_tombstones` and open it for appending, creating the
+    /// file if it does not exist. A crash may leave a tail that is short, zero-filled or holding a
+    /// partly written record: an invalid record that no valid record follows is such a torn tail
+    /// and is truncated. An invalid record followed by valid ones is reported as an error.
+    pub fn recover(
+        path: impl AsRef<Path>,
+        sync_mode: WalSyncMode,
+        map: &SkipMap<KeyBytes, Option<Bytes>>,
+        range_tombstones: &mut Vec<RangeTombstone>,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)?;
+
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+
+        let mut buf = &data[..];
+        while buf.has_remaining() {
+            let result = match Self::next_record(buf) {
+                Some((payload, rest)) => {
+                    Self::decode_record(payload, map, range_tombstones).map(|_| rest)
+                }
+                None => Err(anyhow!("checksum mismatch")),
+            };
+            match result {
+                Ok(rest) => buf = rest,
+                Err(e) if Self::has_valid_record(buf) => {
+                    return Err(e.context(format!("corrupted record in WAL {}", path.display())));
+                }
+                Err(_) => break,
+            }
+        }
+        file.set_len((data.len() - buf.remaining()) as u64)?;
+
+        Ok(Self::new(file, sync_mode))
+    }
+
+    /// Split the payload of the first record off `buf`, None if the record is truncated or its
+    /// checksum does not match.
+    fn next_record(buf: &[u8]) -> Option<(&[u8], &[u8])> {
+        if buf.len() < HEADER_SIZE {
+            return None;
+        }
+        let len = (&buf[..4]).get_u32() as usize;
+        let checksum = (&buf[4..8]).get_u32();
+        let payload = buf[HEADER_SIZE..].get(..len)?;
+        (crc32fast::hash(payload) == checksum).then(|| (payload, &buf[HEADER_SIZE + len..]))
+    }
+
+    /// Check if a valid record follows the invalid record at the start of `buf`. The records are
+    /// found through their lengths, so a corrupted length hides the records after it.
+    fn has_valid_record(buf: &[u8]) -> bool {
+        let mut buf = buf;
+        while buf.len() >= HEADER_SIZE {
+            let len = (&buf[..4]).get_u32() as usize;
+            buf = match buf.get(HEADER_SIZE + len..) {
+                Some(rest) => rest,
+                None => return false,
+            };
+            if let Some((payload, _)) = Self::next_record(buf) {
+                let valid = Self::decode_record(payload, &SkipMap::new(), &mut Vec::new());
+                if valid.is_ok() {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    fn new(file: File, sync_mode: WalSyncMode) -> Self {
+        Self {
+            file,
+            sync_mode,
+            written: Mutex::new(0),
+            synced: Mutex::new(0),
+        }
+    }
+
+    /// Append a put record to the WAL.
+    pub fn put(&self, key: KeySlice, value: &[u8]) -> Result<()> {
+        self.append(&Self::encode_write(RECORD_PUT, key, value))
+    }
+
+    /// Append a delete record to the WAL.
+    pub fn delete(&self, key: KeySlice) -> Result<()> {
+        self.append(&Self::encode_write(RECORD_DELETE, key, &[]))
+    }
+
+    /// Append a range tombstone record to the WAL.
+    pub fn delete_range(&self, tombstone: &RangeTombstone) -> Result<()> {
+        let start = KeySlice::from_slice(tombstone.start(), tombstone.ts());
+        self.append(&Self::encode_write(
+            RECORD_DELETE_RANGE,
+            start,
+            tombstone.end(),
+        ))
+    }
+
+    /// Append a write batch as a single record, its writes starting at `ts`.
+    pub fn write_batch(&self, batch: &WriteBatch, ts: u64) -> Result<()> {
+        let mut payload = Vec::new();
+        payload.put_u8(RECORD_BATCH);
+        payload.put_u64(ts);
+        payload.put_u32(batch.len() as u32);
+        for record in batch.records() {
+            let (record_type, value) = match record {
+                WriteBatchRecord::Put(_, value) => (RECORD_PUT, &value[..]),
+                WriteBatchRecord::Delete(_) => (RECORD_DELETE, &[][..]),
+            };
+            payload.put_u8(record_type);
+            payload.put_u32(record.key().len() as u32);
+            payload.put_slice(record.key());
+            payload.put_u32(value.len() as u32);
+            payload.put_slice(value);
+        }
+        self.append(&payload)
+    }
+
+    /// Make every record written so far durable.
+    pub fn sync(&self) -> Result<()> {
+        let written = *self.written.lock();
+        self.sync_to(written)
+    }
+
+    fn encode_write(record_type: u8, key: KeySlice, value: &[u8]) -> Vec<u8> {
+        let mut payload = Vec::with_capacity(9 + key.raw_len() + value.len());
+        payload.put_u8(record_type);
+        payload.put_u32(key.key_len() as u32);
+        payload.put_slice(key.key_ref());
+        payload.put_u64(key.ts());
+        payload.put_u32(value.len() as u32);
+        payload.put_slice(value);
+        payload
+    }
+
+    fn append(&self, payload: &[u8]) -> Result<()> {
+        let mut record = Vec::with_capacity(HEADER_SIZE + payload.len());
+        record.put_u32(payload.len() as u32);
+        record.put_u32(crc32fast::hash(payload));
+        record.put_slice(payload);
+
+        let seq = {
+            let mut written = self.written.lock();
+            (&self.file).write_all(&record)?;
+            *written += 1;
+            *written
+        };
+
+        match self.sync_mode {
+            WalSyncMode::EveryWrite => {
+                self.file.sync_data()?;
+                Ok(())
+            }
+            WalSyncMode::GroupCommit => self.sync_to(seq),
+            WalSyncMode::NoSync => Ok(()),
+        }
+    }
+
+    /// Sync the file unless the first `seq` records are already durable. The fsync covers every
+    /// record written before it starts, so writers waiting on `synced` usually find their record
+    /// has been synced by another writer.
+    fn sync_to(&self, seq: u64) -> Result<()> {
+        let mut synced = self.synced.lock();
+        if *synced >= seq {
+            return Ok(());
+        }
+        let written = *self.written.lock();
+        self.file.sync_data()?;
+        *synced = written;
+        Ok(())
+    }
+
+    fn decode_record(
+        mut payload: &[u8],
+        map: &SkipMap<KeyBytes, Option<Bytes>>,
+        range_tombstones: &mut Vec<RangeTombstone>,
+    ) -> Result<()> {
+        if !payload.has_remaining() {
+            bail!("WAL record is too short");
+        }
+        let record_type = payload.get_u8();
+        if record_type != RECORD_BATCH {
+            if payload.remaining() < 4 {
+                bail!("WAL record is too short");
+            }
+            let key_len = payload.get_u32() as usize;
+            if payload.remaining() < key_len + 12 {
+                bail!("WAL record is too short");
+            }
+            let key = payload.copy_to_bytes(key_len);
+            let key = KeyBytes::from_bytes(key, payload.get_u64());
+            let value_len = payload.get_u32() as usize;
+            if payload.remaining() != value_len {
+                bail!("WAL record has a wrong value length");
+            }
+            let value = payload.copy_to_bytes(value_len);
+            if record_type == RECORD_DELETE_RANGE {
+                let tombstone = RangeTombstone::new(key.key().clone(), value, key.ts());
+                range_tombstones.push(tombstone);
+            } else {
+                map.insert(key, Self::decode_value(record_type, value)?);
+            }
+            return Ok(());
+        }
+
+        if payload.remaining() < 12 {
+            bail!("WAL record is too short");
+        }
+        let ts = payload.get_u64();
+        let num_writes = payload.get_u32() as u64;
+        // The batch is decoded entirely before it is applied.
+        let mut writes = Vec::new();
+        for i in 0..num_writes {
+            if payload.remaining() < 5 {
+                bail!("WAL record is too short");
+            }
+            let record_type = payload.get_u8();
+            let key_len = payload.get_u32() as usize;
+            if payload.remaining() < key_len + 4 {
+                bail!("WAL record is too short");
+            }
+            let key = KeyBytes::from_bytes(payload.copy_to_bytes(key_len), ts + i);
+            let value_len = payload.get_u32() as usize;
+            if payload.remaining() < value_len {
+                bail!("WAL record is too short");
+            }
+            let value = Self::decode_value(record_type, payload.copy_to_bytes(value_len))?;
+            writes.push((key, value));
+        }
+        if payload.has_remaining() {
+            bail!("WAL record has trailing bytes");
+        }
+        for (key, value) in writes {
+            map.insert(key, value);
+        }
+        Ok(())
+    }
+
+    fn decode_value(record_type: u8, value: Bytes) -> Result<Option<Bytes>> {
+        match record_type {
+            RECORD_PUT => Ok(Some(value)),
+            RECORD_DELETE => Ok(None),
+            _ => bail!("unknown WAL record type {}", record_type),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,291 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    ops::Bound,
+    path::Path,
+};
+
+use bytes::Bytes;
+use crossbeam_skiplist::SkipMap;
+
+use super::{Wal, WalSyncMode};
+use crate::{
+    iterators::StorageIterator,
+    key::{KeyBytes, KeySlice, TS_DEFAULT},
+    mem_table::MemTable,
+    range_tombstone::RangeTombstone,
+    write_batch::WriteBatch,
+};
+
+fn key_of(val: usize) -> Vec<u8> {
+    format!("key_{:05}", val).into_bytes()
+}
+
+fn value_of(val: usize) -> Vec<u8> {
+    format!("val_{:010}", val).into_bytes()
+}
+
+fn ks(key: &[u8]) -> KeySlice<'_> {
+    KeySlice::from_slice(key, TS_DEFAULT)
+}
+
+fn kb(key: Vec<u8>) -> KeyBytes {
+    KeyBytes::from_bytes(key.into(), TS_DEFAULT)
+}
+
+fn wal_test<T>(name: &str, test: T)
+where
+    T: Fn(&Path),
+{
+    fs::create_dir_all("./tmp").unwrap();
+    let path = format!("./tmp/{name}.wal");
+    let path = Path::new(&path);
+    _ = fs::remove_file(path);
+
+    test(path);
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_wal_recover() {
+    for sync_mode in [
+        WalSyncMode::EveryWrite,
+        WalSyncMode::GroupCommit,
+        WalSyncMode::NoSync,
+    ] {
+        wal_test("wal-recover", |path| {
+            {
+                let wal = Wal::create(path, sync_mode).unwrap();
+                for i in 0..100 {
+                    wal.put(ks(&key_of(i)), &value_of(i)).unwrap();
+                }
+                for i in (0..100).step_by(2) {
+                    wal.delete(ks(&key_of(i))).unwrap();
+                }
+                wal.put(ks(&key_of(0)), b"").unwrap();
+                wal.sync().unwrap();
+            }
+
+            let map = SkipMap::new();
+            let wal = Wal::recover(path, sync_mode, &map, &mut Vec::new()).unwrap();
+            assert_eq!(map.len(), 100);
+            assert_eq!(
+                map.get(&kb(key_of(0))).unwrap().value(),
+                &Some(Bytes::new())
+            );
+            for i in 1..100 {
+                let value = map.get(&kb(key_of(i))).unwrap().value().clone();
+                if i % 2 == 0 {
+                    assert!(value.is_none(), "{i}");
+                } else {
+                    assert_eq!(value.unwrap(), value_of(i));
+                }
+            }
+
+            wal.put(ks(&key_of(100)), &value_of(100)).unwrap();
+            drop(wal);
+
+            let map = SkipMap::new();
+            Wal::recover(path, sync_mode, &map, &mut Vec::new()).unwrap();
+            assert_eq!(map.len(), 101);
+        });
+    }
+}
+
+#[test]
+fn test_wal_torn_tail() {
+    wal_test("wal-torn-tail", |path| {
+        {
+            let wal = Wal::create(path, WalSyncMode::NoSync).unwrap();
+            for i in 0..10 {
+                wal.put(ks(&key_of(i)), &value_of(i)).unwrap();
+            }
+        }
+
+        let len = fs::metadata(path).unwrap().len();
+        let file = OpenOptions::new().write(true).open(path).unwrap();
+        file.set_len(len - 3).unwrap();
+
+        let map = SkipMap::new();
+        Wal::recover(path, WalSyncMode::NoSync, &map, &mut Vec::new()).unwrap();
+        assert_eq!(map.len(), 9);
+        assert!(map.get(&kb(key_of(9))).is_none());
+    });
+}
+
+#[test]
+fn test_wal_torn_record() {
+    wal_test("wal-torn-record", |path| {
+        let write = |path: &Path| {
+            let wal = Wal::create(path, WalSyncMode::NoSync).unwrap();
+            for i in 0..10 {
+                wal.put(ks(&key_of(i)), &value_of(i)).unwrap();
+            }
+        };
+        let recover = |path: &Path| {
+            let map = SkipMap::new();
+            Wal::recover(path, WalSyncMode::NoSync, &map, &mut Vec::new()).unwrap();
+            assert_eq!(map.len(), 9);
+            assert!(map.get(&kb(key_of(9))).is_none());
+            fs::metadata(path).unwrap().len()
+        };
+        write(path);
+        let data = fs::read(path).unwrap();
+        let len = data.len();
+
+        // a zero-filled tail
+        let mut file = OpenOptions::new().append(true).open(path).unwrap();
+        file.write_all(&[0; 16]).unwrap();
+        let map = SkipMap::new();
+        Wal::recover(path, WalSyncMode::NoSync, &map, &mut Vec::new()).unwrap();
+        assert_eq!(map.len(), 10);
+        assert_eq!(fs::metadata(path).unwrap().len(), len as u64);
+
+        // the last record zero-filled after its header
+        let mut torn = data.clone();
+        torn[len - 10..].fill(0);
+        fs::write(path, &torn).unwrap();
+        let torn_len = recover(path);
+
+        // the last record partly written
+        let mut torn = data.clone();
+        torn[len - 1] ^= 0xff;
+        fs::write(path, &torn).unwrap();
+        assert_eq!(recover(path), torn_len);
+    });
+}
+
+#[test]
+fn test_wal_corrupted() {
+    wal_test("wal-corrupted", |path| {
+        {
+            let wal = Wal::create(path, WalSyncMode::NoSync).unwrap();
+            for i in 0..10 {
+                wal.put(ks(&key_of(i)), &value_of(i)).unwrap();
+            }
+        }
+
+        let mut data = fs::read(path).unwrap();
+        data[20] ^= 0xff;
+        let mut file = OpenOptions::new().write(true).open(path).unwrap();
+        file.write_all(&data).unwrap();
+
+        let map = SkipMap::new();
+        assert!(Wal::recover(path, WalSyncMode::NoSync, &map, &mut Vec::new()).is_err());
+
+        // a record that passes its checksum but cannot be decoded, followed by valid ones
+        data[20] ^= 0xff;
+        data.splice(..0, [0; 8]);
+        fs::write(path, &data).unwrap();
+        let map = SkipMap::new();
+        assert!(Wal::recover(path, WalSyncMode::NoSync, &map, &mut Vec::new()).is_err());
+    });
+}
+
+#[test]
+fn test_wal_write_batch() {
+    wal_test("wal-write-batch", |path| {
+        let mut batch = WriteBatch::new();
+        for i in 0..10 {
+            batch.put(&key_of(i), &value_of(i));
+        }
+        batch.delete(&key_of(3));
+        batch.put(&key_of(3), b"");
+        {
+            let wal = Wal::create(path, WalSyncMode::NoSync).unwrap();
+            wal.put(ks(&key_of(100)), &value_of(100)).unwrap();
+            wal.write_batch(&batch, 10).unwrap();
+            wal.write_batch(&batch, 22).unwrap();
+        }
+
+        let map = SkipMap::new();
+        Wal::recover(path, WalSyncMode::NoSync, &map, &mut Vec::new()).unwrap();
+        assert_eq!(map.len(), 1 + 2 * 12);
+        for (i, ts) in (0..10).zip(10..) {
+            let key = KeyBytes::from_bytes(key_of(i).into(), ts);
+            assert_eq!(
+                map.get(&key).unwrap().value().as_ref().unwrap(),
+                &value_of(i)
+            );
+        }
+        let key = |ts| KeyBytes::from_bytes(key_of(3).into(), ts);
+        assert!(map.get(&key(20)).unwrap().value().is_none());
+        assert_eq!(map.get(&key(21)).unwrap().value(), &Some(Bytes::new()));
+
+        // a torn batch is dropped entirely
+        let len = fs::metadata(path).unwrap().len();
+        let file = OpenOptions::new().write(true).open(path).unwrap();
+        file.set_len(len - 3).unwrap();
+
+        let map = SkipMap::new();
+        Wal::recover(path, WalSyncMode::NoSync, &map, &mut Vec::new()).unwrap();
+        assert_eq!(map.len(), 1 + 12);
+        assert!(map.iter().all(|entry| entry.key().ts() < 22));
+    });
+}
+
+#[test]
+fn test_wal_range_tombstone() {
+    wal_test("wal-range-tombstone", |path| {
+        let tombstone = |start: usize, end: usize, ts: u64| {
+            RangeTombstone::new(key_of(start).into(), key_of(end).into(), ts)
+        };
+        {
+            let wal = Wal::create(path, WalSyncMode::NoSync).unwrap();
+            wal.put(ks(&key_of(1)), &value_of(1)).unwrap();
+            wal.delete_range(&tombstone(0, 10, 2)).unwrap();
+            wal.put(ks(&key_of(5)), &value_of(5)).unwrap();
+            wal.delete_range(&tombstone(3, 4, 4)).unwrap();
+        }
+
+        let map = SkipMap::new();
+        let mut range_tombstones = Vec::new();
+        Wal::recover(path, WalSyncMode::NoSync, &map, &mut range_tombstones).unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!(
+            range_tombstones,
+            vec![tombstone(0, 10, 2), tombstone(3, 4, 4)]
+        );
+
+        // a torn range tombstone is dropped
+        let len = fs::metadata(path).unwrap().len();
+        let file = OpenOptions::new().write(true).open(path).unwrap();
+        file.set_len(len - 3).unwrap();
+
+        let map = SkipMap::new();
+        let mut range_tombstones = Vec::new();
+        Wal::recover(path, WalSyncMode::NoSync, &map, &mut range_tombstones).unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!(range_tombstones, vec![tombstone(0, 10, 2)]);
+    });
+}
+
+#[test]
+fn test_memtable_recover_from_wal() {
+    wal_test("wal-memtable", |path| {
+        {
+            let memtable = MemTable::create_with_wal(1, path, WalSyncMode::NoSync).unwrap();
+            for i in 0..100 {
+                memtable.put(ks(&key_of(i)), &value_of(i)).unwrap();
+            }
+            memtable.delete(ks(&key_of(50))).unwrap();
+        }
+
+        let memtable = MemTable::recover_from_wal(1, path, WalSyncMode::NoSync).unwrap();
+        assert_eq!(memtable.id(), 1);
+        assert!(memtable.get(ks(&key_of(50))).unwrap().is_none());
+
+        let mut iter = memtable.scan(Bound::Unbounded, Bound::Unbounded);
+        for i in 0..100 {
+            assert!(iter.is_valid(), "{i}");
+            assert_eq!(iter.key().key_ref(), key_of(i));
+            assert_eq!(iter.is_deleted(), i == 50);
+            if i != 50 {
+                assert_eq!(iter.value(), value_of(i));
+            }
+            iter.next().unwrap();
+        }
+        assert!(!iter.is_valid());
+    });
+}
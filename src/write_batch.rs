@@ -0,0 +1,68 @@
+use bytes::Bytes;
+
+/// A write of a batch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WriteBatchRecord {
+    /// Put a key-value pair.
+    Put(Bytes, Bytes),
+    /// Delete a key.
+    Delete(Bytes),
+}
+
+impl WriteBatchRecord {
+    /// Get the key of the write.
+    pub fn key(&self) -> &Bytes {
+        match self {
+            WriteBatchRecord::Put(key, _) | WriteBatchRecord::Delete(key) => key,
+        }
+    }
+}
+
+/// Puts and deletes applied atomically: they are logged as a single WAL record and become visible
+/// together. The writes get consecutive timestamps in order, so a later write of a key overrides
+/// an earlier one.
+#[derive(Debug, Clone, Default)]
+pub struct WriteBatch {
+    records: Vec<WriteBatchRecord>,
+}
+
+impl WriteBatch {
+    /// Create an empty batch.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a put of a key-value pair.
+    pub fn put(&mut self, key: &[u8], value: &[u8]) {
+        self.records.push(WriteBatchRecord::Put(
+            Bytes::copy_from_slice(key),
+            Bytes::copy_from_slice(value),
+        ));
+    }
+
+    /// Add a delete of a key.
+    pub fn delete(&mut self, key: &[u8]) {
+        self.records
+            .push(WriteBatchRecord::Delete(Bytes::copy_from_slice(key)));
+    }
+
+    /// Get the writes in order.
+    pub fn records(&self) -> &[WriteBatchRecord] {
+        &self.records
+    }
+
+    /// Get the number of writes.
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Check if the batch has no write.
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Remove all writes.
+    pub fn clear(&mut self) {
+        self.records.clear();
+    }
+}